@@ -0,0 +1,202 @@
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::{doc, oid::ObjectId, DateTime}, Database};
+
+use crate::audit::{self, RequestMeta};
+use crate::credentials;
+use crate::exports;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome, Post, User};
+use crate::sessions;
+use crate::settings::AccountSettings;
+
+/// What happens to a user's posts when their account is purged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostPolicy {
+    Delete,
+    /// Keep the posts without an author
+    Anonymize,
+    /// Move the posts to another user of the same organization
+    Reassign(ObjectId),
+}
+
+/// Settings for deactivated accounts.
+#[derive(Debug, Clone)]
+pub struct AccountConfig {
+    /// How long a deactivated account can still be restored
+    pub grace_period: Duration,
+    pub post_policy: PostPolicy,
+    /// How often the purge job looks for accounts past their grace period
+    pub purge_interval: std::time::Duration,
+}
+
+impl AccountConfig {
+    /// Build from the `accounts` section; `Settings::load` has already checked the policy.
+    pub fn new(settings: &AccountSettings) -> Self {
+        let post_policy = match settings.post_deletion_policy.as_str() {
+            "delete" => PostPolicy::Delete,
+            "reassign" => PostPolicy::Reassign(
+                settings.post_reassign_to.as_deref()
+                    .and_then(|target| ObjectId::parse_str(target).ok())
+                    .expect("POST_REASSIGN_TO must be a user id for the reassign policy"),
+            ),
+            _ => PostPolicy::Anonymize,
+        };
+
+        AccountConfig {
+            grace_period: Duration::days(settings.grace_period_days),
+            post_policy,
+            purge_interval: std::time::Duration::from_secs(settings.purge_interval_secs),
+        }
+    }
+}
+
+/// Block logins for `user_id` and schedule the account for purging; returns the purge time.
+pub async fn deactivate(db: &Database, config: &AccountConfig, user_id: ObjectId) -> AppResult<DateTime> {
+    let purge_after = DateTime::from_millis((Utc::now() + config.grace_period).timestamp_millis());
+    let result = db.collection::<User>("users").update_one(
+        doc! { "_id": user_id, "deactivated_at": null },
+        doc! { "$set": { "deactivated_at": DateTime::now(), "purge_after": purge_after } },
+        None,
+    ).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("User not found".to_string()));
+    }
+
+    sessions::revoke_all(db, user_id).await?;
+    Ok(purge_after)
+}
+
+/// Deactivate a member on behalf of the admin `removed_by`. The account is purged once
+/// the grace period ends like a deleted one, but only `unlock` can bring it back.
+pub async fn remove(db: &Database, config: &AccountConfig, user_id: ObjectId, removed_by: ObjectId) -> AppResult<DateTime> {
+    let purge_after = DateTime::from_millis((Utc::now() + config.grace_period).timestamp_millis());
+    let result = db.collection::<User>("users").update_one(
+        doc! { "_id": user_id },
+        doc! { "$set": { "deactivated_at": DateTime::now(), "purge_after": purge_after, "removed_by": removed_by } },
+        None,
+    ).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("User not found".to_string()));
+    }
+
+    sessions::revoke_all(db, user_id).await?;
+    Ok(purge_after)
+}
+
+/// Block logins for `user_id` until `unlock`, without scheduling a purge.
+pub async fn lock(db: &Database, user_id: ObjectId) -> AppResult<()> {
+    let result = db.collection::<User>("users").update_one(
+        doc! { "_id": user_id, "deactivated_at": null },
+        doc! { "$set": { "deactivated_at": DateTime::now(), "purge_after": null } },
+        None,
+    ).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("No active account".to_string()));
+    }
+
+    sessions::revoke_all(db, user_id).await?;
+    Ok(())
+}
+
+/// Lift a `lock`, or cancel a deactivation or removal that has not been purged yet.
+pub async fn unlock(db: &Database, user_id: ObjectId) -> AppResult<()> {
+    let result = db.collection::<User>("users").update_one(
+        doc! { "_id": user_id, "deactivated_at": { "$ne": null } },
+        doc! { "$set": { "deactivated_at": null, "purge_after": null, "removed_by": null } },
+        None,
+    ).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("Account is not locked".to_string()));
+    }
+    Ok(())
+}
+
+/// Reactivate an account that is still within its grace period, unless an admin removed it.
+pub async fn restore(db: &Database, user_id: ObjectId) -> AppResult<()> {
+    let result = db.collection::<User>("users").update_one(
+        doc! {
+            "_id": user_id,
+            "deactivated_at": { "$ne": null },
+            "purge_after": { "$gt": DateTime::now() },
+            "removed_by": null,
+        },
+        doc! { "$set": { "deactivated_at": null, "purge_after": null } },
+        None,
+    ).await?;
+    if result.matched_count == 0 {
+        return Err(AppError::NotFound("No restorable account".to_string()));
+    }
+    Ok(())
+}
+
+/// Delete an account now, applying the post policy and dropping its sessions.
+pub async fn purge(db: &Database, config: &AccountConfig, user: &User) -> AppResult<()> {
+    let Some(user_id) = user.id else {
+        return Ok(());
+    };
+    let posts = db.collection::<Post>("posts");
+    let authored = doc! { "author_id": user_id };
+
+    match &config.post_policy {
+        PostPolicy::Delete => {
+            posts.delete_many(authored, None).await?;
+        }
+        PostPolicy::Reassign(target) if reassign_target_valid(db, user, *target).await? => {
+            posts.update_many(authored, doc! { "$set": { "author_id": target } }, None).await?;
+        }
+        PostPolicy::Reassign(_) | PostPolicy::Anonymize => {
+            posts.update_many(authored, doc! { "$set": { "author_id": null } }, None).await?;
+        }
+    }
+
+    db.collection::<User>("users").delete_one(doc! { "_id": user_id }, None).await?;
+    exports::remove(db, doc! { "user_id": user_id }).await?;
+    credentials::remove_all(db, user_id).await?;
+    sessions::revoke_all(db, user_id).await?;
+    Ok(())
+}
+
+/// Posts may only move to an active user of the same organization.
+async fn reassign_target_valid(db: &Database, user: &User, target: ObjectId) -> AppResult<bool> {
+    let valid = db.collection::<User>("users")
+        .find_one(doc! { "_id": target, "org_id": user.org_id, "deactivated_at": null }, None).await?
+        .is_some();
+    if !valid {
+        log::warn!("POST_REASSIGN_TO {} is not an active user of the same organization, anonymizing posts instead", target);
+    }
+    Ok(valid)
+}
+
+/// Purge every account whose grace period has ended; returns how many were removed.
+pub async fn purge_expired(db: &Database, config: &AccountConfig) -> AppResult<usize> {
+    let due: Vec<User> = db.collection::<User>("users")
+        .find(doc! { "deactivated_at": { "$ne": null }, "purge_after": { "$lte": DateTime::now() } }, None).await?
+        .try_collect().await?;
+
+    for user in &due {
+        purge(db, config, user).await?;
+        audit::record(db, &RequestMeta::default(), AuditEvent {
+            org_id: user.org_id,
+            target: user.id.map(|id| id.to_hex()),
+            actor_email: Some(user.email.clone()),
+            ..audit::event("user.purge", AuditOutcome::Success)
+        }).await;
+    }
+    Ok(due.len())
+}
+
+/// Run `purge_expired` every `purge_interval` in the background.
+pub fn spawn_purge_job(db: Database, config: AccountConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.purge_interval);
+        loop {
+            interval.tick().await;
+            match purge_expired(&db, &config).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("purged {} deactivated accounts", count),
+                Err(e) => log::error!("account purge failed: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,337 @@
+use std::net::IpAddr;
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use async_graphql::InputObject;
+use chrono::DateTime as ChronoDateTime;
+use futures::{future, stream::TryStreamExt};
+use mongodb::{bson::{doc, oid::ObjectId, DateTime, Document}, options::FindOptions, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::authorize;
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome};
+use crate::settings::ServerSettings;
+use crate::tenant::Tenant;
+
+/// Client details attached to every audit event.
+#[derive(Debug, Clone, Default)]
+pub struct RequestMeta {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestMeta {
+    pub fn from_request(req: &HttpRequest, server: &ServerSettings) -> Self {
+        let proxies: Vec<IpRange> = server.trusted_proxies.iter()
+            .filter_map(|proxy| IpRange::parse(proxy).ok())
+            .collect();
+        RequestMeta {
+            ip: req.peer_addr().map(|peer| client_ip(peer.ip(), req, &proxies).to_string()),
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+        }
+    }
+}
+
+/// The client behind `peer`: when the peer is a trusted proxy, the nearest
+/// `X-Forwarded-For` entry that is not itself a trusted proxy.
+fn client_ip(peer: IpAddr, req: &HttpRequest, proxies: &[IpRange]) -> IpAddr {
+    let trusted = |ip: &IpAddr| proxies.iter().any(|range| range.contains(*ip));
+    if !trusted(&peer) {
+        return peer;
+    }
+    let forwarded = req.headers().get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|entry| entry.trim().parse::<IpAddr>())
+        .collect::<Result<Vec<_>, _>>();
+    // A garbled header cannot be trusted any further than the proxy itself
+    let Ok(forwarded) = forwarded else {
+        return peer;
+    };
+    forwarded.into_iter().rev().find(|ip| !trusted(ip)).unwrap_or(peer)
+}
+
+/// An address, or a CIDR range such as `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("{} is not an address or CIDR range", value);
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u8>().map_err(|_| invalid())?)),
+            None => (value.trim(), None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| invalid())?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        if prefix > bits {
+            return Err(invalid());
+        }
+        Ok(IpRange { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Start an event for `action`; callers fill in actor, target and detail.
+pub fn event(action: &str, outcome: AuditOutcome) -> AuditEvent {
+    AuditEvent {
+        id: None,
+        org_id: None,
+        actor_id: None,
+        actor_email: None,
+        impersonator_id: None,
+        action: action.to_string(),
+        target: None,
+        ip: None,
+        user_agent: None,
+        outcome,
+        detail: None,
+        created_at: DateTime::now(),
+    }
+}
+
+/// Append an event to the `audit_events` collection.
+///
+/// Failures are logged rather than returned so auditing never breaks the audited operation.
+pub async fn record(db: &Database, meta: &RequestMeta, mut event: AuditEvent) {
+    event.ip = meta.ip.clone();
+    event.user_agent = meta.user_agent.clone();
+
+    if let Err(e) = db.collection::<AuditEvent>("audit_events").insert_one(&event, None).await {
+        log::error!("failed to record audit event {}: {}", event.action, e);
+    }
+}
+
+/// Filters shared by the `auditEvents` query and the export endpoint.
+#[derive(Debug, Default, Deserialize, InputObject)]
+pub struct AuditFilter {
+    pub action: Option<String>,
+    pub actor_id: Option<String>,
+    pub impersonator_id: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<String>,
+    /// RFC 3339 lower bound (inclusive) on the event time
+    pub from: Option<String>,
+    /// RFC 3339 upper bound (exclusive) on the event time
+    pub to: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn to_document(&self) -> Result<Document, String> {
+        let mut filter = doc! {};
+        if let Some(action) = &self.action {
+            filter.insert("action", action);
+        }
+        if let Some(actor_id) = &self.actor_id {
+            let oid = ObjectId::parse_str(actor_id).map_err(|_| "Invalid actor ID".to_string())?;
+            filter.insert("actor_id", oid);
+        }
+        if let Some(impersonator_id) = &self.impersonator_id {
+            let oid = ObjectId::parse_str(impersonator_id).map_err(|_| "Invalid impersonator ID".to_string())?;
+            filter.insert("impersonator_id", oid);
+        }
+        if let Some(target) = &self.target {
+            filter.insert("target", target);
+        }
+        if let Some(outcome) = &self.outcome {
+            filter.insert("outcome", outcome.to_lowercase());
+        }
+
+        let mut range = doc! {};
+        if let Some(from) = &self.from {
+            range.insert("$gte", parse_time(from)?);
+        }
+        if let Some(to) = &self.to {
+            range.insert("$lt", parse_time(to)?);
+        }
+        if !range.is_empty() {
+            filter.insert("created_at", range);
+        }
+        Ok(filter)
+    }
+}
+
+fn parse_time(value: &str) -> Result<DateTime, String> {
+    ChronoDateTime::parse_from_rfc3339(value)
+        .map(|dt| DateTime::from_millis(dt.timestamp_millis()))
+        .map_err(|_| format!("Invalid timestamp: {}", value))
+}
+
+/// Flat representation used for CSV and NDJSON exports.
+#[derive(Serialize)]
+struct ExportRow {
+    id: String,
+    created_at: String,
+    action: String,
+    outcome: String,
+    actor_id: String,
+    actor_email: String,
+    impersonator_id: String,
+    target: String,
+    ip: String,
+    user_agent: String,
+    detail: String,
+}
+
+impl From<AuditEvent> for ExportRow {
+    fn from(event: AuditEvent) -> Self {
+        ExportRow {
+            id: event.id.map(|id| id.to_hex()).unwrap_or_default(),
+            created_at: event.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            action: event.action,
+            outcome: match event.outcome {
+                AuditOutcome::Success => "success".to_string(),
+                AuditOutcome::Failure => "failure".to_string(),
+            },
+            actor_id: event.actor_id.map(|id| id.to_hex()).unwrap_or_default(),
+            actor_email: event.actor_email.unwrap_or_default(),
+            impersonator_id: event.impersonator_id.map(|id| id.to_hex()).unwrap_or_default(),
+            target: event.target.unwrap_or_default(),
+            ip: event.ip.unwrap_or_default(),
+            user_agent: event.user_agent.unwrap_or_default(),
+            detail: event.detail.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    pub format: Option<String>,
+    #[serde(flatten)]
+    pub filter: AuditFilter,
+}
+
+/// Export audit events as CSV (`?format=csv`, default) or NDJSON (`?format=ndjson`). Admin only.
+pub async fn export_events(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    tenant: Tenant,
+    req: HttpRequest,
+    params: web::Query<ExportParams>,
+) -> AppResult<HttpResponse> {
+    authorize(&req, &jwt, &db, "admin").await?;
+
+    let format = params.format.as_deref().unwrap_or("csv");
+    if !matches!(format, "csv" | "ndjson") {
+        return Err(AppError::Validation("Unsupported format, use csv or ndjson".to_string()));
+    }
+
+    // Rows are written as the cursor yields them, so large exports are never held in memory
+    let filter = tenant.scope(params.filter.to_document().map_err(AppError::Validation)?);
+    let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+    let rows = db.collection::<AuditEvent>("audit_events")
+        .find(filter, options).await?
+        .map_err(|e| AppError::internal("audit export", e))
+        .map_ok(ExportRow::from);
+
+    if format == "csv" {
+        let mut first = true;
+        let body = rows.and_then(move |row| {
+            // The header goes out with the first row
+            let mut writer = csv::WriterBuilder::new().has_headers(std::mem::take(&mut first)).from_writer(vec![]);
+            let chunk = writer.serialize(row)
+                .map_err(|e| AppError::internal("audit export", e))
+                .and_then(|_| writer.into_inner().map_err(|e| AppError::internal("audit export", e)))
+                .map(web::Bytes::from);
+            future::ready(chunk)
+        });
+        Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"audit_events.csv\""))
+            .streaming(body))
+    } else {
+        let body = rows.and_then(|row| {
+            let chunk = serde_json::to_vec(&row)
+                .map(|mut line| {
+                    line.push(b'\n');
+                    web::Bytes::from(line)
+                })
+                .map_err(|e| AppError::internal("audit export", e));
+            future::ready(chunk)
+        });
+        Ok(HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"audit_events.ndjson\""))
+            .streaming(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn ranges(values: &[&str]) -> Vec<IpRange> {
+        values.iter().map(|value| IpRange::parse(value).unwrap()).collect()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges_match_by_prefix() {
+        let range = IpRange::parse("10.0.0.0/8").unwrap();
+        assert!(range.contains(ip("10.200.1.2")));
+        assert!(range.contains(ip("::ffff:10.0.0.1")));
+        assert!(!range.contains(ip("11.0.0.1")));
+        assert!(IpRange::parse("192.0.2.7").unwrap().contains(ip("192.0.2.7")));
+        assert!(!IpRange::parse("192.0.2.7").unwrap().contains(ip("192.0.2.8")));
+        assert!(IpRange::parse("2001:db8::/32").unwrap().contains(ip("2001:db8:1::1")));
+        assert!(IpRange::parse("0.0.0.0/0").unwrap().contains(ip("203.0.113.9")));
+        assert!(IpRange::parse("10.0.0.0/33").is_err());
+        assert!(IpRange::parse("proxy.internal").is_err());
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.9:5000".parse().unwrap())
+            .insert_header(("x-forwarded-for", "198.51.100.1"))
+            .to_http_request();
+        assert_eq!(client_ip(ip("203.0.113.9"), &req, &ranges(&["10.0.0.0/8"])), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn trusted_proxies_are_skipped_from_the_right() {
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "1.2.3.4, 198.51.100.1, 10.0.0.2"))
+            .to_http_request();
+        assert_eq!(client_ip(ip("10.0.0.1"), &req, &ranges(&["10.0.0.0/8"])), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn garbled_forwarded_for_falls_back_to_the_peer() {
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "198.51.100.1, unknown"))
+            .to_http_request();
+        assert_eq!(client_ip(ip("10.0.0.1"), &req, &ranges(&["10.0.0.0/8"])), ip("10.0.0.1"));
+    }
+}
@@ -1,77 +1,665 @@
 use actix_web::{
-    web, HttpResponse, Responder,
+    web, HttpRequest, HttpResponse,
 };
 use mongodb::{bson::doc, Database};
-use bcrypt::{hash, verify};
-use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
-use std::env;
-use chrono::{Utc, Duration};
+use bcrypt::hash;
+use serde::{Deserialize, Serialize};
 
-use crate::models::models::{AuthUser, Claims};
+use crate::accounts;
+use crate::audit::{self, RequestMeta};
+use crate::challenge::{self, ChallengeConfig};
+use crate::credentials;
+use crate::db::is_duplicate_key;
+use crate::email::normalize_email;
+use crate::email_domains::EmailScreening;
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome, AuthUser, Claims, Credential, LoginRequest, OtpCode, User};
+use crate::otp::{self, PURPOSE_LOGIN, PURPOSE_TWO_FACTOR};
+use crate::passwords::{self, PasswordPolicy};
+use crate::registration;
+use crate::schema::bearer_token;
+use crate::sessions;
+use crate::settings::Settings;
+use crate::sms::{normalize_phone, SmsConfig};
+use crate::tenant::Tenant;
+use crate::tokens::{generate_token, hash_token};
+use crate::validation::Valid;
+use crate::webhooks;
 
+#[derive(Debug, Deserialize)]
+pub struct SmsLoginRequest {
+    pub phone_number: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmsLoginVerify {
+    pub phone_number: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorVerify {
+    pub challenge: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Result of checking a password.
+pub enum LoginOutcome {
+    Authenticated(Box<User>),
+    /// The password was right but an SMS code was sent and must be verified
+    TwoFactorRequired { challenge: String },
+    /// Fully authenticated, but this password has expired and must be replaced first
+    PasswordChangeRequired(Box<Credential>),
+}
+
+/// Result of exchanging a refresh token.
+pub enum RefreshOutcome {
+    Refreshed(Box<User>, AuthTokens),
+    /// The password expired during the session, which has been ended; it must be replaced first
+    PasswordChangeRequired(Box<Credential>),
+}
+
+/// Tokens handed out after a successful login or refresh.
+#[derive(Debug, Serialize)]
+pub struct AuthTokens {
+    #[serde(rename = "token")]
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: i64,
+}
 
-/// Register a new user
-pub async fn register_user(db: web::Data<Database>, user: web::Json<AuthUser>) -> impl Responder {
-    let collection = db.collection::<AuthUser>("users");
 
-    // Check if the user already exists
-    if collection.find_one(doc! {"email": &user.email}, None).await.unwrap().is_some() {
-        return HttpResponse::Conflict().body("User already exists!");
+/// Create an account under the tenant's registration policy; shared by the
+/// REST endpoint and the `register` mutation.
+pub async fn self_register(
+    db: &Database,
+    tenant: &Tenant,
+    screening: &EmailScreening,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    input: &AuthUser,
+) -> AppResult<User> {
+    let email = normalize_email(&input.email).map_err(AppError::Validation)?;
+    // Screen the address before an invite code is used up
+    let admitted = match screening.check(db, tenant, "email", &email).await {
+        Ok(()) => registration::admit(db, tenant, &email, input.invite_code.as_deref()).await,
+        Err(e) => Err(e),
+    };
+    let admission = match admitted {
+        Ok(admission) => admission,
+        Err(e) => {
+            audit::record(db, meta, AuditEvent {
+                org_id: tenant.org_id(),
+                actor_email: Some(email),
+                detail: Some(e.to_string()),
+                ..audit::event("user.register", AuditOutcome::Failure)
+            }).await;
+            return Err(e);
+        }
+    };
+
+    let result = register(db, tenant, passwords, meta, input, Vec::new(), admission.pending_approval).await;
+    if result.is_err() {
+        registration::release(db, &admission).await;
     }
+    result
+}
+
+/// Create an account with `roles`, bypassing the registration policy; used
+/// directly by `acceptInvitation`.
+pub async fn register(
+    db: &Database,
+    tenant: &Tenant,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    input: &AuthUser,
+    roles: Vec<String>,
+    pending_approval: bool,
+) -> AppResult<User> {
+    let collection = db.collection::<User>("users");
+
+    let email = normalize_email(&input.email).map_err(AppError::Validation)?;
+
+    // Store phone numbers in E.164 form
+    let phone_number = input.phone_number.as_deref().map(normalize_phone).transpose()
+        .map_err(AppError::Validation)?;
 
     // Hash the password
-    let hashed_password = match hash(&user.password, 10) {
-        Ok(pwd) => pwd,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to hash password"),
-    };
+    let hashed_password = hash(&input.password, passwords.bcrypt_cost)?;
 
     // Create the new user
-    let new_user = AuthUser {
-        email: user.email.clone(),
-        password: hashed_password,
-        full_name: user.full_name.clone(),
-        phone_number: user.phone_number.clone(),
+    let mut new_user = User {
+        id: None,
+        org_id: tenant.org_id(),
+        email: email.clone(),
+        full_name: input.full_name.clone(),
+        phone_number,
+        roles,
+        phone_verified: false,
+        sms_two_factor: false,
+        sms_login: false,
+        deactivated_at: None,
+        purge_after: None,
+        removed_by: None,
+        pending_approval,
+        tokens_valid_after: None,
     };
 
-    // Insert the user into the database
-    match collection.insert_one(new_user, None).await {
-        Ok(_) => HttpResponse::Ok().body("User registered successfully!"),
-        Err(_) => HttpResponse::InternalServerError().body("Failed to register user"),
+    // Insert the user into the database; the unique email index rejects duplicates
+    match collection.insert_one(&new_user, None).await {
+        Ok(result) => {
+            new_user.id = result.inserted_id.as_object_id();
+            if let Err(e) = credentials::set_password(db, &new_user, None, hashed_password, Vec::new()).await {
+                // Without a password the account could never log in, and its email stays taken
+                discard_user(db, &new_user).await;
+                return Err(e);
+            }
+            audit::record(db, meta, AuditEvent {
+                org_id: tenant.org_id(),
+                actor_id: new_user.id,
+                actor_email: Some(email),
+                detail: pending_approval.then(|| "pending approval".to_string()),
+                ..audit::event("user.register", AuditOutcome::Success)
+            }).await;
+            webhooks::emit(db, tenant.org_id(), "user.registered", webhooks::user_data(&new_user)).await;
+            Ok(new_user)
+        }
+        Err(e) if is_duplicate_key(&e) => {
+            audit::record(db, meta, AuditEvent {
+                org_id: tenant.org_id(),
+                actor_email: Some(email),
+                detail: Some("email already registered".to_string()),
+                ..audit::event("user.register", AuditOutcome::Failure)
+            }).await;
+            Err(AppError::Conflict("User already exists!".to_string()))
+        }
+        Err(e) => Err(e.into()),
     }
 }
 
-/// Log in a user and issue a JWT token
-pub async fn login_user(db: web::Data<Database>, user: web::Json<AuthUser>) -> impl Responder {
-    let collection = db.collection::<AuthUser>("users");
+/// Delete an account just inserted whose setup could not be finished.
+pub(crate) async fn discard_user(db: &Database, user: &User) {
+    let Some(user_id) = user.id else {
+        return;
+    };
+    if let Err(e) = db.collection::<User>("users").delete_one(doc! { "_id": user_id }, None).await {
+        log::error!("failed to remove incomplete account {}: {}", user_id, e);
+    }
+}
+
+/// Check an email and password; shared by the REST endpoint and the `login` mutation.
+pub async fn password_login(
+    db: &Database,
+    tenant: &Tenant,
+    sms: &SmsConfig,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    email: &str,
+    password: &str,
+) -> AppResult<LoginOutcome> {
+    tenant.require_login_method("password")?;
+    let (existing_user, credential) = check_password(db, tenant, passwords, meta, email, password).await?;
+
+    if existing_user.deactivated_at.is_some() {
+        audit::record(db, meta, AuditEvent {
+            org_id: tenant.org_id(),
+            actor_id: existing_user.id,
+            actor_email: Some(existing_user.email.clone()),
+            detail: Some("account deactivated".to_string()),
+            ..audit::event("user.login", AuditOutcome::Failure)
+        }).await;
+        return Err(deactivated());
+    }
+    if existing_user.pending_approval {
+        audit::record(db, meta, AuditEvent {
+            org_id: tenant.org_id(),
+            actor_id: existing_user.id,
+            actor_email: Some(existing_user.email.clone()),
+            detail: Some("approval pending".to_string()),
+            ..audit::event("user.login", AuditOutcome::Failure)
+        }).await;
+        return Err(approval_pending());
+    }
+
+    // With SMS two-factor enabled the password alone is not enough
+    if existing_user.sms_two_factor {
+        let (Some(user_id), Some(phone)) = (existing_user.id, existing_user.phone_number.as_deref()) else {
+            return Err(AppError::internal("login", "two-factor enabled without a phone number"));
+        };
+        let challenge = generate_token();
+        otp::send_code(db, sms, user_id, phone, PURPOSE_TWO_FACTOR, Some(hash_token(&challenge))).await?;
+        return Ok(LoginOutcome::TwoFactorRequired { challenge });
+    }
+
+    audit::record(db, meta, AuditEvent {
+        org_id: tenant.org_id(),
+        actor_id: existing_user.id,
+        actor_email: Some(existing_user.email.clone()),
+        ..audit::event("user.login", AuditOutcome::Success)
+    }).await;
+
+    Ok(outcome(passwords, existing_user, Some(credential)))
+}
+
+/// `Authenticated`, unless the user's password has expired.
+fn outcome(passwords: &PasswordPolicy, user: User, password: Option<Credential>) -> LoginOutcome {
+    match password {
+        Some(password) if passwords.is_expired(&user, &password) => LoginOutcome::PasswordChangeRequired(Box::new(password)),
+        _ => LoginOutcome::Authenticated(Box::new(user)),
+    }
+}
+
+/// Reactivate a deactivated account during its grace period.
+///
+/// The user signs in normally afterwards, so two-factor still applies. Callers
+/// apply `ChallengeConfig::require_for_login` first, as for a login. Active
+/// accounts fail like a wrong password, so this cannot be used to test passwords.
+pub async fn restore_account(
+    db: &Database,
+    tenant: &Tenant,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    email: &str,
+    password: &str,
+) -> AppResult<()> {
+    let (user, _) = check_password(db, tenant, passwords, meta, email, password).await?;
+    let user_id = user.id.ok_or_else(invalid_credentials)?;
+    if user.deactivated_at.is_none() {
+        return Err(invalid_credentials());
+    }
+
+    accounts::restore(db, user_id).await?;
+    audit::record(db, meta, AuditEvent {
+        org_id: tenant.org_id(),
+        actor_id: Some(user_id),
+        actor_email: Some(user.email),
+        ..audit::event("user.restore", AuditOutcome::Success)
+    }).await;
+    Ok(())
+}
+
+/// Find the tenant's user with `email` and verify their password, auditing failures.
+async fn check_password(
+    db: &Database,
+    tenant: &Tenant,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    email: &str,
+    password: &str,
+) -> AppResult<(User, Credential)> {
+    let collection = db.collection::<User>("users");
+    // Unparseable addresses cannot match an account and fail like unknown ones
+    let email = normalize_email(email).unwrap_or_else(|_| email.trim().to_string());
+    let failed_login = |actor_id, detail: &str| AuditEvent {
+        org_id: tenant.org_id(),
+        actor_id,
+        actor_email: Some(email.clone()),
+        detail: Some(detail.to_string()),
+        ..audit::event("user.login", AuditOutcome::Failure)
+    };
 
     // Find the user by email
-    let existing_user = match collection.find_one(doc! {"email": &user.email}, None).await {
-        Ok(Some(user)) => user,
-        Ok(None) => return HttpResponse::Unauthorized().body("Invalid credentials"),
-        Err(_) => return HttpResponse::InternalServerError().body("Database error"),
+    let Some(existing_user) = collection.find_one(tenant.scope(doc! {"email": &email}), None).await? else {
+        audit::record(db, meta, failed_login(None, "unknown email")).await;
+        return Err(invalid_credentials());
+    };
+
+    // Verify the password; accounts without one cannot log in this way
+    let credential = match existing_user.id {
+        Some(user_id) => credentials::verify_password(db, passwords.bcrypt_cost, user_id, password).await?,
+        None => None,
+    };
+    let Some(credential) = credential else {
+        audit::record(db, meta, failed_login(existing_user.id, "wrong password")).await;
+        return Err(invalid_credentials());
+    };
+
+    Ok((existing_user, credential))
+}
+
+/// Finish a password login with the SMS code sent for `challenge`.
+pub async fn two_factor_login(
+    db: &Database,
+    tenant: &Tenant,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    challenge: &str,
+    code: &str,
+) -> AppResult<LoginOutcome> {
+    let filter = doc! { "challenge_hash": hash_token(challenge), "purpose": PURPOSE_TWO_FACTOR };
+    let code = otp::verify_code(db, filter, code).await?;
+    let user = complete_code_login(db, tenant, meta, code, "pwd+sms").await?;
+    let password = match user.id {
+        Some(user_id) => credentials::password(db, user_id).await?,
+        None => None,
     };
+    Ok(outcome(passwords, user, password))
+}
+
+/// Log in with a code texted to a verified phone number.
+pub async fn sms_login(
+    db: &Database,
+    tenant: &Tenant,
+    passwords: &PasswordPolicy,
+    meta: &RequestMeta,
+    phone_number: &str,
+    code: &str,
+) -> AppResult<LoginOutcome> {
+    tenant.require_login_method("sms")?;
+    let phone = normalize_phone(phone_number).map_err(AppError::Validation)?;
 
-    // Verify the password
-    if let Err(_) = verify(&user.password, &existing_user.password) {
-        return HttpResponse::Unauthorized().body("Invalid credentials");
+    let filter = doc! { "phone": &phone, "purpose": PURPOSE_LOGIN };
+    let mut code = otp::verify_code(db, filter, code).await?;
+    // The user may have turned SMS login off after the code was sent
+    if let Some(otp) = &code {
+        let opted_in = db.collection::<User>("users")
+            .count_documents(doc! { "_id": otp.user_id, "sms_login": true }, None).await? > 0;
+        if !opted_in {
+            code = None;
+        }
     }
+    let user = complete_code_login(db, tenant, meta, code, "sms").await?;
+    // The code stands in for the password, which still has to be current
+    let password = match user.id {
+        Some(user_id) => credentials::password(db, user_id).await?,
+        None => None,
+    };
+    Ok(outcome(passwords, user, password))
+}
 
-    // Generate a JWT token
-    let expiration = (Utc::now() + Duration::hours(24)).timestamp() as usize;
-    let claims = Claims {
-        sub: user.email.clone(),
-        exp: expiration,
+async fn complete_code_login(db: &Database, tenant: &Tenant, meta: &RequestMeta, code: Option<OtpCode>, method: &str) -> AppResult<User> {
+    let Some(code) = code else {
+        audit::record(db, meta, AuditEvent {
+            org_id: tenant.org_id(),
+            detail: Some("invalid sms code".to_string()),
+            ..audit::event("user.login", AuditOutcome::Failure)
+        }).await;
+        return Err(AppError::Unauthenticated("Invalid or expired code".to_string()));
     };
 
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let token = match encode(
-        &Header::new(Algorithm::HS256),
-        &claims,
-        &EncodingKey::from_secret(jwt_secret.as_ref()),
-    ) {
-        Ok(token) => token,
-        Err(_) => return HttpResponse::InternalServerError().body("Failed to generate token"),
+    let user = db.collection::<User>("users")
+        .find_one(tenant.scope(doc! { "_id": code.user_id }), None).await?
+        .ok_or_else(invalid_credentials)?;
+    if user.deactivated_at.is_some() {
+        return Err(deactivated());
+    }
+    if user.pending_approval {
+        return Err(approval_pending());
+    }
+
+    audit::record(db, meta, AuditEvent {
+        org_id: tenant.org_id(),
+        actor_id: user.id,
+        actor_email: Some(user.email.clone()),
+        detail: Some(method.to_string()),
+        ..audit::event("user.login", AuditOutcome::Success)
+    }).await;
+
+    Ok(user)
+}
+
+/// Start a session for `user` and issue its access and refresh tokens.
+pub async fn issue_tokens(
+    db: &Database,
+    jwt: &JwtConfig,
+    meta: &RequestMeta,
+    user: &User,
+    amr: &[&str],
+) -> AppResult<AuthTokens> {
+    let user_id = user.id
+        .ok_or_else(|| AppError::internal("login", "user document without _id"))?;
+    let (_, refresh_token) = sessions::create(db, user_id, amr, meta, jwt.refresh_token_ttl).await?;
+
+    let claims = jwt.claims_for(user, user_id.to_hex(), amr);
+    Ok(AuthTokens {
+        access_token: jwt.encode(&claims)?,
+        refresh_token,
+        expires_at: claims.exp as i64,
+    })
+}
+
+/// Rotate a refresh token and issue a new access token for its session.
+///
+/// Sessions do not outlive the user's password: once it expires the session is
+/// ended and the password has to be changed, as at login.
+pub async fn refresh_tokens(
+    db: &Database,
+    jwt: &JwtConfig,
+    passwords: &PasswordPolicy,
+    refresh_token: &str,
+) -> AppResult<RefreshOutcome> {
+    let (session, refresh_token) = sessions::rotate(db, refresh_token).await?;
+
+    let user = db.collection::<User>("users")
+        .find_one(doc! { "_id": session.user_id, "deactivated_at": null, "pending_approval": { "$ne": true } }, None).await?
+        .ok_or_else(|| AppError::Unauthenticated("Invalid or expired refresh token".to_string()))?;
+
+    if let Some(password) = credentials::password(db, session.user_id).await? {
+        if passwords.is_expired(&user, &password) {
+            sessions::revoke(db, &refresh_token).await?;
+            return Ok(RefreshOutcome::PasswordChangeRequired(Box::new(password)));
+        }
+    }
+
+    // Refreshed tokens keep the session's original login time as auth_time,
+    // so step-up checks still require a recent sign-in.
+    let amr: Vec<&str> = session.amr.iter().map(String::as_str).collect();
+    let mut claims = jwt.claims_for(&user, session.user_id.to_hex(), &amr);
+    claims.auth_time = session.created_at.timestamp_millis() as usize / 1000;
+
+    let tokens = AuthTokens {
+        access_token: jwt.encode(&claims)?,
+        refresh_token,
+        expires_at: claims.exp as i64,
     };
+    Ok(RefreshOutcome::Refreshed(Box::new(user), tokens))
+}
+
+/// Register a new user
+#[allow(clippy::too_many_arguments)]
+pub async fn register_user(
+    db: web::Data<Database>,
+    challenges: web::Data<ChallengeConfig>,
+    screening: web::Data<EmailScreening>,
+    passwords: web::Data<PasswordPolicy>,
+    tenant: Tenant,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    user: Valid<AuthUser>,
+) -> AppResult<HttpResponse> {
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    challenges.require(&meta, challenge::response_from(&req).as_deref()).await?;
+    let user = self_register(&db, &tenant, &screening, &passwords, &meta, &user).await?;
+    if user.pending_approval {
+        return Ok(HttpResponse::Accepted().body("Registration received; an administrator has to approve it before you can log in."));
+    }
+    Ok(HttpResponse::Ok().body("User registered successfully!"))
+}
+
+/// Log in a user and issue a JWT token
+#[allow(clippy::too_many_arguments)]
+pub async fn login_user(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    sms: web::Data<SmsConfig>,
+    challenges: web::Data<ChallengeConfig>,
+    passwords: web::Data<PasswordPolicy>,
+    tenant: Tenant,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    user: web::Json<LoginRequest>,
+) -> AppResult<HttpResponse> {
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    let response = challenge::response_from(&req);
+    challenges.require_for_login(&db, &tenant, &meta, &user.email, response.as_deref()).await?;
+
+    let outcome = password_login(&db, &tenant, &sms, &passwords, &meta, &user.email, &user.password).await?;
+    login_response(&db, &jwt, &meta, outcome, &["pwd"]).await
+}
+
+/// REST body for each login outcome.
+async fn login_response(
+    db: &Database,
+    jwt: &JwtConfig,
+    meta: &RequestMeta,
+    outcome: LoginOutcome,
+    amr: &[&str],
+) -> AppResult<HttpResponse> {
+    match outcome {
+        LoginOutcome::Authenticated(user) => {
+            let tokens = issue_tokens(db, jwt, meta, &user, amr).await?;
+            Ok(HttpResponse::Ok().json(tokens))
+        }
+        LoginOutcome::TwoFactorRequired { challenge } => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "two_factor_required": true,
+            "challenge": challenge,
+        }))),
+        LoginOutcome::PasswordChangeRequired(password) => password_change_response(jwt, &password),
+    }
+}
+
+/// Answer telling the client to replace an expired password using the change token.
+fn password_change_response(jwt: &JwtConfig, password: &Credential) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "password_change_required": true,
+        "change_token": passwords::change_token(jwt, password)?,
+    })))
+}
+
+/// Send a login code to a verified phone number whose user turned SMS login on.
+///
+/// The response, rate limit included, does not reveal whether the number belongs to an account.
+pub async fn request_sms_login(
+    db: web::Data<Database>,
+    sms: web::Data<SmsConfig>,
+    tenant: Tenant,
+    body: web::Json<SmsLoginRequest>,
+) -> AppResult<HttpResponse> {
+    tenant.require_login_method("sms")?;
+    let phone = normalize_phone(&body.phone_number).map_err(AppError::Validation)?;
+    otp::throttle(&db, &sms, &phone).await?;
 
-    HttpResponse::Ok().json(serde_json::json!({ "token": token }))
-}
\ No newline at end of file
+    let user = db.collection::<User>("users")
+        .find_one(tenant.scope(doc! {
+            "phone_number": &phone,
+            "phone_verified": true,
+            "sms_login": true,
+            "deactivated_at": null,
+            "pending_approval": { "$ne": true },
+        }), None).await?;
+
+    if let Some(user_id) = user.and_then(|user| user.id) {
+        otp::deliver_code(&db, &sms, user_id, &phone, PURPOSE_LOGIN, None).await?;
+    }
+
+    Ok(HttpResponse::Ok().body("If the number is registered, a code has been sent"))
+}
+
+/// Exchange an SMS login code for a token.
+pub async fn verify_sms_login(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    passwords: web::Data<PasswordPolicy>,
+    tenant: Tenant,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    body: web::Json<SmsLoginVerify>,
+) -> AppResult<HttpResponse> {
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    let outcome = sms_login(&db, &tenant, &passwords, &meta, &body.phone_number, &body.code).await?;
+    login_response(&db, &jwt, &meta, outcome, &["sms"]).await
+}
+
+/// Second step of a password login for users with SMS two-factor enabled.
+pub async fn verify_two_factor(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    passwords: web::Data<PasswordPolicy>,
+    tenant: Tenant,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    body: web::Json<TwoFactorVerify>,
+) -> AppResult<HttpResponse> {
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    let outcome = two_factor_login(&db, &tenant, &passwords, &meta, &body.challenge, &body.code).await?;
+    login_response(&db, &jwt, &meta, outcome, &["pwd", "sms"]).await
+}
+
+/// Exchange a refresh token for a new access/refresh token pair.
+///
+/// Answers `password_change_required` like `/login` once the password has expired.
+pub async fn refresh_token(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    passwords: web::Data<PasswordPolicy>,
+    body: web::Json<RefreshRequest>,
+) -> AppResult<HttpResponse> {
+    match refresh_tokens(&db, &jwt, &passwords, &body.refresh_token).await? {
+        RefreshOutcome::Refreshed(_, tokens) => Ok(HttpResponse::Ok().json(tokens)),
+        RefreshOutcome::PasswordChangeRequired(password) => password_change_response(&jwt, &password),
+    }
+}
+
+/// Reactivate a deactivated account with its email and password.
+///
+/// Like `/login`, repeated failures require a solved challenge in `X-Challenge-Response`.
+pub async fn restore(
+    db: web::Data<Database>,
+    challenges: web::Data<ChallengeConfig>,
+    passwords: web::Data<PasswordPolicy>,
+    tenant: Tenant,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    body: web::Json<LoginRequest>,
+) -> AppResult<HttpResponse> {
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    let response = challenge::response_from(&req);
+    challenges.require_for_login(&db, &tenant, &meta, &body.email, response.as_deref()).await?;
+
+    restore_account(&db, &tenant, &passwords, &meta, &body.email, &body.password).await?;
+    Ok(HttpResponse::Ok().body("Account restored, you can sign in again"))
+}
+
+/// End the session behind a refresh token.
+pub async fn logout(db: web::Data<Database>, body: web::Json<RefreshRequest>) -> AppResult<HttpResponse> {
+    sessions::revoke(&db, &body.refresh_token).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn invalid_credentials() -> AppError {
+    AppError::Unauthenticated("Invalid credentials".to_string())
+}
+
+fn deactivated() -> AppError {
+    AppError::Forbidden("Account is deactivated; restore it to sign in".to_string())
+}
+
+fn approval_pending() -> AppError {
+    AppError::Forbidden("Registration is awaiting approval by an administrator".to_string())
+}
+
+/// Verify the bearer token of a REST request, refusing tokens revoked since they were issued.
+pub async fn authenticate(req: &HttpRequest, jwt: &JwtConfig, db: &Database) -> AppResult<Claims> {
+    let token = bearer_token(req).ok_or_else(|| AppError::Unauthenticated("Not authenticated".to_string()))?;
+    let claims = jwt.verify(token)
+        .map_err(|_| AppError::Unauthenticated("Invalid or expired token".to_string()))?;
+    if !sessions::is_current(db, &claims).await? {
+        return Err(AppError::Unauthenticated("Invalid or expired token".to_string()));
+    }
+    Ok(claims)
+}
+
+/// Verify the bearer token and require `role`.
+pub async fn authorize(req: &HttpRequest, jwt: &JwtConfig, db: &Database, role: &str) -> AppResult<Claims> {
+    let claims = authenticate(req, jwt, db).await?;
+    if !claims.roles.iter().any(|r| r == role) {
+        return Err(AppError::Forbidden(format!("{} role required", role)));
+    }
+    Ok(claims)
+}
@@ -0,0 +1,273 @@
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use futures::stream::TryStreamExt;
+use mongodb::{bson::{doc, oid::ObjectId}, options::FindOptions, Database};
+
+use rust_auth::audit::{self, RequestMeta};
+use rust_auth::error::{AppError, AppResult};
+use rust_auth::jwt::{JwtConfig, ProfileClaims};
+use rust_auth::models::models::{AuditEvent, AuditOutcome, AuthUser, Organization, User};
+use rust_auth::passwords::{self, PasswordPolicy};
+use rust_auth::settings::Settings;
+use rust_auth::tenant::Tenant;
+use rust_auth::validation::{Rule, Validate};
+use rust_auth::email::normalize_email;
+use rust_auth::{accounts, auth, db, schema_sdl, sessions};
+
+/// Operate the auth service directly against its database.
+#[derive(Parser)]
+#[command(name = "rust_auth-admin")]
+struct Cli {
+    /// Organization slug; the default tenant when omitted
+    #[arg(long, global = true)]
+    org: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a user with the admin role
+    CreateAdmin {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        full_name: Option<String>,
+        /// Read from stdin when omitted
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Set a new password and sign the user out everywhere
+    ResetPassword {
+        #[arg(long)]
+        email: String,
+        /// Read from stdin when omitted
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Block logins and end all sessions, without scheduling a purge
+    Lock {
+        #[arg(long)]
+        email: String,
+    },
+    /// Let a locked or deactivated account sign in again
+    Unlock {
+        #[arg(long)]
+        email: String,
+    },
+    /// Replace the user's roles and end their sessions
+    SetRoles {
+        #[arg(long)]
+        email: String,
+        /// Comma-separated; empty to remove every role
+        #[arg(long, value_delimiter = ',', required = true)]
+        roles: Vec<String>,
+    },
+    /// List users, newest first
+    ListUsers {
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Sign the user out of every session
+    RevokeSessions {
+        #[arg(long)]
+        email: String,
+    },
+    /// Print a short-lived access token for the user, for debugging
+    MintToken {
+        #[arg(long)]
+        email: String,
+        #[arg(long, default_value_t = 300)]
+        ttl_secs: i64,
+    },
+    /// Print the GraphQL schema in SDL form
+    PrintSchema,
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    env_logger::init();
+    let cli = Cli::parse();
+
+    // The schema needs no database
+    if let Command::PrintSchema = cli.command {
+        print!("{}", schema_sdl());
+        return;
+    }
+
+    let settings = Settings::load().unwrap_or_else(|e| {
+        eprint!("{}", e);
+        std::process::exit(1);
+    });
+
+    let db = db::get_database(&settings.database).await;
+    // Commands rely on normalized emails and the unique indexes, as the server does
+    if let Err(e) = db::bootstrap(&db).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = run(&db, &settings, cli).await {
+        eprintln!("error: {}", e);
+        if let AppError::InvalidFields(errors) = &e {
+            for error in errors {
+                eprintln!("  {}: {}", error.field, error.message);
+            }
+        }
+        std::process::exit(1);
+    }
+}
+
+async fn run(db: &Database, settings: &Settings, cli: Cli) -> AppResult<()> {
+    let tenant = tenant(db, settings, cli.org.as_deref()).await?;
+    let meta = RequestMeta { ip: None, user_agent: Some("rust_auth-admin".to_string()) };
+
+    match cli.command {
+        Command::CreateAdmin { email, full_name, password } => {
+            let mut input = AuthUser {
+                email,
+                password: password_arg(password)?,
+                full_name,
+                phone_number: None,
+                invite_code: None,
+            };
+            let errors = input.validate();
+            if !errors.is_empty() {
+                return Err(AppError::InvalidFields(errors));
+            }
+            let user = auth::register(db, &tenant, &PasswordPolicy::new(&settings.passwords), &meta, &input, vec!["admin".to_string()], false).await?;
+            println!("Created admin {} ({})", user.email, hex_id(&user));
+        }
+        Command::ResetPassword { email, password } => {
+            let user = find_user(db, &tenant, &email).await?;
+            let password = password_arg(password)?;
+            passwords::change(db, &PasswordPolicy::new(&settings.passwords), &user, "password", &password).await?;
+            let revoked = revoke_sessions(db, &user).await?;
+            record(db, &meta, &user, "admin.reset_password").await;
+            println!("Password reset for {}; {} sessions revoked", user.email, revoked);
+        }
+        Command::Lock { email } => {
+            let user = find_user(db, &tenant, &email).await?;
+            accounts::lock(db, user_id(&user)?).await?;
+            record(db, &meta, &user, "admin.lock").await;
+            println!("Locked {}", user.email);
+        }
+        Command::Unlock { email } => {
+            let user = find_user(db, &tenant, &email).await?;
+            accounts::unlock(db, user_id(&user)?).await?;
+            record(db, &meta, &user, "admin.unlock").await;
+            println!("Unlocked {}", user.email);
+        }
+        Command::SetRoles { email, roles } => {
+            let user = find_user(db, &tenant, &email).await?;
+            let mut roles: Vec<String> = roles.iter().map(|role| role.trim().to_string()).filter(|role| !role.is_empty()).collect();
+            roles.sort();
+            roles.dedup();
+            db.collection::<User>("users")
+                .update_one(doc! { "_id": user_id(&user)? }, doc! { "$set": { "roles": &roles } }, None).await?;
+            // Tokens carry the roles; make the user sign in again to pick up the new ones
+            revoke_sessions(db, &user).await?;
+            record(db, &meta, &user, "admin.set_roles").await;
+            println!("Roles of {}: {}", user.email, if roles.is_empty() { "(none)".to_string() } else { roles.join(", ") });
+        }
+        Command::ListUsers { limit } => {
+            let options = FindOptions::builder().sort(doc! { "_id": -1 }).limit(limit).build();
+            let users: Vec<User> = db.collection::<User>("users")
+                .find(tenant.scope(doc! {}), options).await?
+                .try_collect().await?;
+            for user in users {
+                let status = if user.pending_approval {
+                    "pending"
+                } else if user.deactivated_at.is_some() && user.purge_after.is_none() {
+                    "locked"
+                } else if user.deactivated_at.is_some() {
+                    "deactivated"
+                } else {
+                    "active"
+                };
+                println!("{}\t{}\t{}\t{}", hex_id(&user), user.email, status, user.roles.join(","));
+            }
+        }
+        Command::RevokeSessions { email } => {
+            let user = find_user(db, &tenant, &email).await?;
+            let revoked = revoke_sessions(db, &user).await?;
+            record(db, &meta, &user, "admin.revoke_sessions").await;
+            println!("Revoked {} sessions of {}", revoked, user.email);
+        }
+        Command::MintToken { email, ttl_secs } => {
+            let user = find_user(db, &tenant, &email).await?;
+            if user.deactivated_at.is_some() {
+                return Err(AppError::Validation("Account is deactivated".to_string()));
+            }
+            let jwt = JwtConfig::new(&settings.jwt).with_claims_provider(Arc::new(ProfileClaims));
+            // Never longer-lived than a regular access token
+            let ttl = ttl_secs.clamp(1, jwt.access_token_ttl.num_seconds());
+            let mut claims = jwt.claims_for(&user, user_id(&user)?.to_hex(), &["admin_cli"]);
+            claims.exp = (Utc::now() + Duration::seconds(ttl)).timestamp() as usize;
+            let token = jwt.encode(&claims)?;
+            record(db, &meta, &user, "admin.mint_token").await;
+            println!("{}", token);
+        }
+        Command::PrintSchema => unreachable!("handled before connecting"),
+    }
+    Ok(())
+}
+
+async fn tenant(db: &Database, settings: &Settings, slug: Option<&str>) -> AppResult<Tenant> {
+    let Some(slug) = slug else {
+        return Ok(Tenant::new(None, settings));
+    };
+    let org = db.collection::<Organization>("organizations")
+        .find_one(doc! { "slug": slug }, None).await?
+        .ok_or_else(|| AppError::NotFound(format!("No organization {}", slug)))?;
+    Ok(Tenant::new(Some(org), settings))
+}
+
+async fn find_user(db: &Database, tenant: &Tenant, email: &str) -> AppResult<User> {
+    let email = normalize_email(email).map_err(AppError::Validation)?;
+    db.collection::<User>("users")
+        .find_one(tenant.scope(doc! { "email": &email }), None).await?
+        .ok_or_else(|| AppError::NotFound(format!("No user {}", email)))
+}
+
+fn user_id(user: &User) -> AppResult<ObjectId> {
+    user.id.ok_or_else(|| AppError::internal("admin", "user document without _id"))
+}
+
+fn hex_id(user: &User) -> String {
+    user.id.map(|id| id.to_hex()).unwrap_or_default()
+}
+
+async fn revoke_sessions(db: &Database, user: &User) -> AppResult<u64> {
+    sessions::revoke_all(db, user_id(user)?).await
+}
+
+/// Audit an operation on `user`; there is no actor, the CLI runs with database access.
+async fn record(db: &Database, meta: &RequestMeta, user: &User, action: &str) {
+    audit::record(db, meta, AuditEvent {
+        org_id: user.org_id,
+        target: user.id.map(|id| id.to_hex()),
+        detail: Some("admin cli".to_string()),
+        ..audit::event(action, AuditOutcome::Success)
+    }).await;
+}
+
+/// The `--password` value, or one line read from stdin so it stays out of the shell history.
+fn password_arg(password: Option<String>) -> AppResult<String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            eprint!("Password: ");
+            io::stderr().flush().ok();
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line).map_err(|e| AppError::internal("admin", e))?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    Rule::Password.check(&password).map_err(|message| AppError::Validation(format!("Password {}", message)))?;
+    Ok(password)
+}
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use async_graphql::SimpleObject;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use mongodb::{bson::{doc, DateTime}, Database};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::audit::RequestMeta;
+use crate::db::is_duplicate_key;
+use crate::email::normalize_email;
+use crate::error::{AppError, AppResult};
+use crate::models::models::AuditEvent;
+use crate::settings::ChallengeSettings;
+use crate::tenant::Tenant;
+
+/// REST clients send the solved challenge in this header.
+pub const CHALLENGE_HEADER: &str = "X-Challenge-Response";
+
+/// Failed logins are counted over this window.
+const LOGIN_FAILURE_WINDOW_MINUTES: i64 = 15;
+/// Lifetime of a proof-of-work puzzle.
+const POW_TTL_MINUTES: i64 = 5;
+
+/// What a client needs to present a challenge to the user or solve it itself.
+#[derive(Debug, Clone, Serialize, SimpleObject)]
+pub struct Challenge {
+    /// `none`, `http` or `pow`
+    pub provider: String,
+    /// Site key for the hCaptcha/Turnstile widget
+    pub site_key: Option<String>,
+    /// Proof-of-work puzzle; answer with `<puzzle>:<solution>` where the SHA-256
+    /// of that string starts with `difficulty` zero bits
+    pub puzzle: Option<String>,
+    pub difficulty: Option<u32>,
+}
+
+/// Checks that a request was made by a human (or at least paid for in CPU time).
+#[async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// Whether responses are checked at all.
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// A challenge to hand to the client.
+    async fn issue(&self) -> Result<Challenge, String>;
+
+    /// Check a client's response; `Ok(false)` for wrong or reused answers.
+    async fn verify(&self, response: &str, remote_ip: Option<&str>) -> Result<bool, String>;
+}
+
+/// Accepts everything; the default when no provider is configured.
+pub struct NoChallenge;
+
+#[async_trait]
+impl ChallengeVerifier for NoChallenge {
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    async fn issue(&self) -> Result<Challenge, String> {
+        Ok(Challenge { provider: "none".to_string(), site_key: None, puzzle: None, difficulty: None })
+    }
+
+    async fn verify(&self, _response: &str, _remote_ip: Option<&str>) -> Result<bool, String> {
+        Ok(true)
+    }
+}
+
+/// Verifier for hCaptcha, Turnstile and compatible `siteverify` endpoints.
+pub struct HttpChallengeVerifier {
+    client: reqwest::Client,
+    verify_url: String,
+    secret: String,
+    site_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+#[async_trait]
+impl ChallengeVerifier for HttpChallengeVerifier {
+    async fn issue(&self) -> Result<Challenge, String> {
+        Ok(Challenge { provider: "http".to_string(), site_key: self.site_key.clone(), puzzle: None, difficulty: None })
+    }
+
+    async fn verify(&self, response: &str, remote_ip: Option<&str>) -> Result<bool, String> {
+        let mut form = vec![("secret", self.secret.as_str()), ("response", response)];
+        if let Some(ip) = remote_ip {
+            form.push(("remoteip", ip));
+        }
+        let result: SiteVerifyResponse = self.client.post(&self.verify_url)
+            .form(&form)
+            .send().await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json().await
+            .map_err(|e| e.to_string())?;
+        Ok(result.success)
+    }
+}
+
+/// Built-in challenge: find a suffix whose hash has enough leading zero bits.
+///
+/// Puzzles are HMAC-signed so nothing is stored until one is solved; solved
+/// puzzles are remembered until they expire so each can be used only once.
+pub struct ProofOfWork {
+    db: Database,
+    key: String,
+    difficulty: u32,
+}
+
+impl ProofOfWork {
+    fn sign(&self, message: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+fn leading_zero_bits(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in digest {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            return bits + byte.leading_zeros();
+        }
+    }
+    bits
+}
+
+#[async_trait]
+impl ChallengeVerifier for ProofOfWork {
+    async fn issue(&self) -> Result<Challenge, String> {
+        let expires = (Utc::now() + Duration::minutes(POW_TTL_MINUTES)).timestamp();
+        let nonce: String = rand::thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect();
+        let body = format!("{}.{}.{}", expires, nonce, self.difficulty);
+        Ok(Challenge {
+            provider: "pow".to_string(),
+            site_key: None,
+            puzzle: Some(format!("{}.{}", body, self.sign(&body))),
+            difficulty: Some(self.difficulty),
+        })
+    }
+
+    async fn verify(&self, response: &str, _remote_ip: Option<&str>) -> Result<bool, String> {
+        let Some((puzzle, _solution)) = response.rsplit_once(':') else {
+            return Ok(false);
+        };
+        let Some((body, signature)) = puzzle.rsplit_once('.') else {
+            return Ok(false);
+        };
+        let mut parts = body.split('.');
+        let (Some(expires), Some(_nonce), Some(difficulty), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Ok(false);
+        };
+        let (Ok(expires), Ok(difficulty)) = (expires.parse::<i64>(), difficulty.parse::<u32>()) else {
+            return Ok(false);
+        };
+        if self.sign(body) != signature || expires < Utc::now().timestamp() || difficulty < self.difficulty {
+            return Ok(false);
+        }
+        if leading_zero_bits(&Sha256::digest(response.as_bytes())) < difficulty {
+            return Ok(false);
+        }
+
+        // Remember the puzzle so the same solution cannot be replayed
+        let used = self.db.collection::<mongodb::bson::Document>("used_challenges").insert_one(doc! {
+            "_id": signature,
+            "expires_at": DateTime::from_millis(expires * 1000),
+        }, None).await;
+        match used {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// The configured verifier and when it is applied.
+#[derive(Clone)]
+pub struct ChallengeConfig {
+    pub verifier: Arc<dyn ChallengeVerifier>,
+    /// Logins need a challenge once an address or IP has this many recent failures
+    pub login_after_failures: u64,
+}
+
+impl ChallengeConfig {
+    /// Build the verifier named by the `challenge` section: `none`, `http` or `pow`.
+    pub fn new(settings: &ChallengeSettings, db: &Database) -> Self {
+        let secret = || settings.secret.clone().expect("CHALLENGE_SECRET must be set for the challenge provider");
+        let verifier: Arc<dyn ChallengeVerifier> = match settings.provider.as_str() {
+            "http" => Arc::new(HttpChallengeVerifier {
+                client: reqwest::Client::builder()
+                    .timeout(std::time::Duration::from_secs(10))
+                    .build()
+                    .expect("Failed to build challenge HTTP client"),
+                verify_url: settings.verify_url.clone(),
+                secret: secret(),
+                site_key: settings.site_key.clone(),
+            }),
+            "pow" => Arc::new(ProofOfWork {
+                db: db.clone(),
+                key: secret(),
+                difficulty: settings.pow_difficulty,
+            }),
+            _ => Arc::new(NoChallenge),
+        };
+
+        ChallengeConfig { verifier, login_after_failures: settings.login_after_failures }
+    }
+
+    /// Fail with `CHALLENGE_REQUIRED` unless `response` solves a challenge.
+    pub async fn require(&self, meta: &RequestMeta, response: Option<&str>) -> AppResult<()> {
+        if !self.verifier.enabled() {
+            return Ok(());
+        }
+        let Some(response) = response.filter(|response| !response.is_empty()) else {
+            return Err(AppError::ChallengeRequired("Solve the challenge to continue".to_string()));
+        };
+        let passed = self.verifier.verify(response, meta.ip.as_deref()).await
+            .map_err(|e| AppError::internal("challenge verification", e))?;
+        if !passed {
+            return Err(AppError::ChallengeRequired("Challenge failed, please try again".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Like `require`, but only once `email` or the client IP has failed to log in repeatedly.
+    pub async fn require_for_login(
+        &self,
+        db: &Database,
+        tenant: &Tenant,
+        meta: &RequestMeta,
+        email: &str,
+        response: Option<&str>,
+    ) -> AppResult<()> {
+        if !self.verifier.enabled() {
+            return Ok(());
+        }
+        let email = normalize_email(email).unwrap_or_else(|_| email.trim().to_string());
+        let since = DateTime::from_millis((Utc::now() - Duration::minutes(LOGIN_FAILURE_WINDOW_MINUTES)).timestamp_millis());
+        let mut who = vec![doc! { "actor_email": &email }];
+        if let Some(ip) = &meta.ip {
+            who.push(doc! { "ip": ip });
+        }
+        let failures = db.collection::<AuditEvent>("audit_events").count_documents(tenant.scope(doc! {
+            "action": "user.login",
+            "outcome": "failure",
+            "created_at": { "$gte": since },
+            "$or": who,
+        }), None).await?;
+
+        if failures >= self.login_after_failures {
+            self.require(meta, response).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The solved challenge sent with a REST request, if any.
+pub fn response_from(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(CHALLENGE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// Hand out a challenge for the next register, login or password change.
+pub async fn issue(challenges: web::Data<ChallengeConfig>) -> AppResult<HttpResponse> {
+    let challenge = challenges.verifier.issue().await
+        .map_err(|e| AppError::internal("challenge", e))?;
+    Ok(HttpResponse::Ok().json(challenge))
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use actix_cors::Cors;
+use actix_web::{
+    dev::RequestHead,
+    http::{header, Method},
+};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::doc, Database};
+
+use crate::models::models::Organization;
+use crate::settings::Settings;
+use crate::tenant::{slug_from, TENANT_HEADER};
+
+/// How often origins configured by organizations are reloaded.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Methods that may be listed in `cors.allowed_methods`; preflight `OPTIONS` is always answered.
+pub const METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"];
+
+/// An allowed origin such as `https://app.example.com`, or `https://*.example.com`
+/// for every subdomain (but not `example.com` itself).
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginPattern {
+    scheme: String,
+    host: String,
+    port: Option<String>,
+    wildcard: bool,
+}
+
+impl OriginPattern {
+    /// Parse a pattern, which must be written as a browser would send the origin.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let (scheme, authority) = raw.split_once("://")
+            .ok_or_else(|| format!("origin {} must start with http:// or https://", raw))?;
+        if !matches!(scheme, "http" | "https") {
+            return Err(format!("origin {} must start with http:// or https://", raw));
+        }
+        let (wildcard, authority) = match authority.strip_prefix("*.") {
+            Some(rest) => (true, rest),
+            None => (false, authority),
+        };
+        let (host, port) = split_authority(authority);
+        if !host.starts_with('[') && !host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.') {
+            return Err(format!("origin {} may only use * as a whole leading label", raw));
+        }
+        if wildcard && host.split('.').count() < 2 {
+            return Err(format!("origin {} must not match every subdomain of a top-level domain", raw));
+        }
+
+        // The URL parser spells out the origin the way browsers send it
+        let canonical = url::Url::parse(&format!("{}://{}", scheme, authority)).ok()
+            .filter(|url| url.path() == "/" && url.query().is_none() && url.fragment().is_none() && url.username().is_empty())
+            .map(|url| url.origin().ascii_serialization());
+        if canonical.as_deref() != Some(&format!("{}://{}", scheme, authority)) {
+            return Err(format!("origin {} must be a scheme, lowercase host and optional port, without a path", raw));
+        }
+
+        Ok(OriginPattern {
+            scheme: scheme.to_string(),
+            host: host.to_string(),
+            port: port.map(str::to_string),
+            wildcard,
+        })
+    }
+
+    /// Whether the `Origin` header value `origin` is allowed by this pattern.
+    pub fn matches(&self, origin: &str) -> bool {
+        let Some((scheme, authority)) = origin.split_once("://") else {
+            return false;
+        };
+        let (host, port) = split_authority(authority);
+        let host_matches = if self.wildcard {
+            host.strip_suffix(self.host.as_str()).is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.'))
+        } else {
+            host == self.host
+        };
+        scheme == self.scheme && host_matches && port == self.port.as_deref()
+    }
+}
+
+/// Split `host[:port]`, leaving IPv6 literals in brackets intact.
+fn split_authority(authority: &str) -> (&str, Option<&str>) {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port)),
+        _ => (authority, None),
+    }
+}
+
+/// Which cross-origin requests browsers may make, from the `cors` settings plus the
+/// origins each organization allows for itself.
+///
+/// Nothing is allowed cross-origin unless it is configured.
+#[derive(Clone)]
+pub struct CorsPolicy {
+    origins: Arc<Vec<OriginPattern>>,
+    /// Origins of each organization, by slug
+    tenants: Arc<RwLock<HashMap<String, Vec<OriginPattern>>>>,
+    tenant_base_domain: Option<String>,
+    methods: Vec<Method>,
+    headers: Vec<header::HeaderName>,
+    max_age: usize,
+    credentials: bool,
+}
+
+impl CorsPolicy {
+    /// Build from the `cors` section; `Settings::load` has already checked every value.
+    pub fn new(settings: &Settings) -> Self {
+        let cors = &settings.cors;
+        CorsPolicy {
+            origins: Arc::new(cors.allowed_origins.iter().filter_map(|origin| OriginPattern::parse(origin).ok()).collect()),
+            tenants: Arc::default(),
+            tenant_base_domain: settings.server.tenant_base_domain.clone(),
+            methods: cors.allowed_methods.iter().filter_map(|method| Method::from_bytes(method.as_bytes()).ok()).collect(),
+            headers: cors.allowed_headers.iter().filter_map(|name| header::HeaderName::from_bytes(name.as_bytes()).ok()).collect(),
+            max_age: cors.max_age_secs,
+            credentials: cors.allow_credentials,
+        }
+    }
+
+    /// Whether `origin` may call the tenant that `head` is addressed to.
+    ///
+    /// Preflights carry no `X-Tenant` header, so organization origins only pass them
+    /// on the organization's own subdomain.
+    pub fn allows(&self, origin: &str, head: &RequestHead) -> bool {
+        if self.origins.iter().any(|pattern| pattern.matches(origin)) {
+            return true;
+        }
+        let header = head.headers().get(TENANT_HEADER).and_then(|value| value.to_str().ok());
+        let host = head.headers().get(header::HOST).and_then(|value| value.to_str().ok())
+            .or_else(|| head.uri.host())
+            .unwrap_or_default()
+            .to_lowercase();
+        let Some(slug) = slug_from(header, &host, self.tenant_base_domain.as_deref()) else {
+            return false;
+        };
+        let tenants = self.tenants.read().unwrap_or_else(|e| e.into_inner());
+        tenants.get(&slug).is_some_and(|patterns| patterns.iter().any(|pattern| pattern.matches(origin)))
+    }
+
+    /// The middleware enforcing this policy; build one per worker.
+    pub fn middleware(&self) -> Cors {
+        let policy = self.clone();
+        let mut cors = Cors::default()
+            .allowed_origin_fn(move |origin, head| {
+                origin.to_str().is_ok_and(|origin| policy.allows(origin, head))
+            })
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.headers.clone())
+            .max_age(self.max_age);
+        if self.credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+
+    /// Reload the origins organizations allow.
+    pub async fn refresh(&self, db: &Database) -> mongodb::error::Result<()> {
+        let orgs: Vec<Organization> = db.collection::<Organization>("organizations")
+            .find(doc! { "settings.allowed_origins.0": { "$exists": true } }, None).await?
+            .try_collect().await?;
+        let tenants = orgs.into_iter()
+            .map(|org| {
+                let patterns = org.settings.allowed_origins.iter().filter_map(|origin| OriginPattern::parse(origin).ok()).collect();
+                (org.slug, patterns)
+            })
+            .collect();
+        *self.tenants.write().unwrap_or_else(|e| e.into_inner()) = tenants;
+        Ok(())
+    }
+}
+
+/// Keep organization origins current, including changes made by other instances.
+pub fn spawn_refresh(db: Database, policy: CorsPolicy) {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + REFRESH_INTERVAL;
+        let mut interval = tokio::time::interval_at(start, REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = policy.refresh(&db).await {
+                log::error!("failed to reload organization CORS origins: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test::{call_service, init_service, TestRequest}, web, App, HttpResponse};
+
+    use super::*;
+    use crate::settings::CorsSettings;
+
+    fn policy(cors: CorsSettings) -> CorsPolicy {
+        let mut settings = Settings { cors, ..Settings::default() };
+        settings.server.tenant_base_domain = Some("auth.example.com".to_string());
+        let policy = CorsPolicy::new(&settings);
+        policy.tenants.write().unwrap().insert(
+            "acme".to_string(),
+            vec![OriginPattern::parse("https://portal.acme.test").unwrap()],
+        );
+        policy
+    }
+
+    fn allowlist() -> CorsSettings {
+        CorsSettings {
+            allowed_origins: vec!["https://app.example.com".to_string(), "https://*.preview.example.com".to_string()],
+            ..CorsSettings::default()
+        }
+    }
+
+    async fn preflight(policy: &CorsPolicy, host: &str, origin: &str, method: &str, headers: &str) -> HttpResponse {
+        let app = init_service(
+            App::new()
+                .wrap(policy.middleware())
+                .route("/graphql", web::post().to(HttpResponse::Ok)),
+        ).await;
+        let req = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/graphql")
+            .insert_header((header::HOST, host))
+            .insert_header((header::ORIGIN, origin))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, method))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, headers))
+            .to_request();
+        call_service(&app, req).await.map_into_boxed_body().into_parts().1
+    }
+
+    fn allowed_origin(res: &HttpResponse) -> Option<&str> {
+        res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).and_then(|value| value.to_str().ok())
+    }
+
+    #[actix_web::test]
+    async fn default_policy_rejects_every_origin() {
+        let res = preflight(&policy(CorsSettings::default()), "localhost", "https://app.example.com", "POST", "content-type").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(allowed_origin(&res), None);
+    }
+
+    #[actix_web::test]
+    async fn listed_origin_is_echoed_without_credentials() {
+        let res = preflight(&policy(allowlist()), "localhost", "https://app.example.com", "POST", "authorization,content-type").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(allowed_origin(&res), Some("https://app.example.com"));
+        assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "3600");
+    }
+
+    #[actix_web::test]
+    async fn unlisted_origin_and_scheme_are_rejected() {
+        let policy = policy(allowlist());
+        for origin in ["https://evil.example", "http://app.example.com", "https://app.example.com:8443", "null"] {
+            let res = preflight(&policy, "localhost", origin, "POST", "content-type").await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", origin);
+        }
+    }
+
+    #[actix_web::test]
+    async fn wildcard_matches_subdomains_only() {
+        let policy = policy(allowlist());
+        let res = preflight(&policy, "localhost", "https://pr-12.preview.example.com", "POST", "content-type").await;
+        assert_eq!(allowed_origin(&res), Some("https://pr-12.preview.example.com"));
+        let res = preflight(&policy, "localhost", "https://a.b.preview.example.com", "POST", "content-type").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        for origin in ["https://preview.example.com", "https://evilpreview.example.com"] {
+            let res = preflight(&policy, "localhost", origin, "POST", "content-type").await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", origin);
+        }
+    }
+
+    #[actix_web::test]
+    async fn methods_and_headers_are_limited() {
+        let policy = policy(allowlist());
+        let res = preflight(&policy, "localhost", "https://app.example.com", "UPDATE", "content-type").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = preflight(&policy, "localhost", "https://app.example.com", "POST", "x-custom").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = preflight(&policy, "localhost", "https://app.example.com", "POST", "x-tenant,x-challenge-response").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn credentials_are_opt_in() {
+        let policy = policy(CorsSettings { allow_credentials: true, ..allowlist() });
+        let res = preflight(&policy, "localhost", "https://app.example.com", "POST", "content-type").await;
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+    }
+
+    #[actix_web::test]
+    async fn tenant_origins_apply_on_their_subdomain() {
+        let policy = policy(allowlist());
+        let res = preflight(&policy, "acme.auth.example.com", "https://portal.acme.test", "POST", "content-type").await;
+        assert_eq!(allowed_origin(&res), Some("https://portal.acme.test"));
+        let res = preflight(&policy, "other.auth.example.com", "https://portal.acme.test", "POST", "content-type").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = preflight(&policy, "localhost", "https://portal.acme.test", "POST", "content-type").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn patterns_must_be_plain_origins() {
+        assert!(OriginPattern::parse("https://app.example.com").is_ok());
+        assert!(OriginPattern::parse("http://localhost:3000").is_ok());
+        assert!(OriginPattern::parse("https://*.example.com").is_ok());
+        for bad in ["*", "https://*", "https://*.com", "app.example.com", "ftp://example.com", "https://example.com/", "https://Example.com", "https://example.com:443"] {
+            assert!(OriginPattern::parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use bcrypt::hash;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, to_document, DateTime, Document},
+    options::UpdateOptions,
+    Collection, Database,
+};
+
+use crate::error::{AppError, AppResult};
+use crate::hashes;
+use crate::models::models::{Credential, User};
+use crate::totp;
+
+pub const KIND_PASSWORD: &str = "password";
+pub const KIND_TOTP: &str = "totp";
+pub const KIND_PASSKEY: &str = "passkey";
+pub const KIND_API_KEY: &str = "api_key";
+pub const KIND_IDENTITY: &str = "identity";
+
+fn collection(db: &Database) -> Collection<Credential> {
+    db.collection::<Credential>("credentials")
+}
+
+/// The user's password, if they have one.
+pub async fn password(db: &Database, user_id: ObjectId) -> AppResult<Option<Credential>> {
+    Ok(collection(db).find_one(doc! { "user_id": user_id, "kind": KIND_PASSWORD }, None).await?)
+}
+
+/// The user's password if `password` matches it; accounts without one never match.
+///
+/// Imported hashes are replaced with a native one, hashed at `bcrypt_cost`, once they have matched.
+pub async fn verify_password(db: &Database, bcrypt_cost: u32, user_id: ObjectId, password: &str) -> AppResult<Option<Credential>> {
+    let Some(mut credential) = self::password(db, user_id).await? else {
+        return Ok(None);
+    };
+    if !hashes::verify(credential.scheme.as_deref(), &credential.secret, password) {
+        return Ok(None);
+    }
+
+    if credential.scheme.is_some() {
+        let upgraded = hash(password, bcrypt_cost)?;
+        // The password itself is unchanged, so `changed_at` stays
+        match collection(db).update_one(
+            doc! { "_id": credential.id, "secret": &credential.secret },
+            doc! { "$set": { "secret": &upgraded, "scheme": null } },
+            None,
+        ).await {
+            Ok(_) => {
+                credential.secret = upgraded;
+                credential.scheme = None;
+            }
+            Err(e) => log::error!("failed to upgrade the password hash of user {}: {}", user_id, e),
+        }
+    }
+    Ok(Some(credential))
+}
+
+/// Create or replace the user's password hash, keeping `history` for reuse checks.
+///
+/// `scheme` names the format of an imported hash and is `None` for native ones.
+pub async fn set_password(
+    db: &Database,
+    user: &User,
+    scheme: Option<&str>,
+    hashed: String,
+    history: Vec<String>,
+) -> AppResult<()> {
+    let Some(user_id) = user.id else {
+        return Ok(());
+    };
+    let now = DateTime::now();
+    collection(db).update_one(
+        doc! { "user_id": user_id, "kind": KIND_PASSWORD },
+        doc! {
+            "$set": { "secret": hashed, "scheme": scheme, "history": history, "changed_at": now },
+            "$setOnInsert": { "org_id": user.org_id, "pending": false, "created_at": now },
+        },
+        UpdateOptions::builder().upsert(true).build(),
+    ).await?;
+    Ok(())
+}
+
+/// The secret of the user's confirmed authenticator app, if any.
+pub async fn totp_secret(db: &Database, user_id: ObjectId) -> AppResult<Option<String>> {
+    Ok(collection(db)
+        .find_one(doc! { "user_id": user_id, "kind": KIND_TOTP, "pending": false }, None).await?
+        .map(|credential| credential.secret))
+}
+
+/// Accept `code` from the user's authenticator app, the one awaiting confirmation if `pending`.
+///
+/// Each time step is accepted once, so a code seen by someone else cannot be replayed
+/// while it is still valid.
+pub async fn verify_totp(db: &Database, user_id: ObjectId, code: &str, pending: bool) -> AppResult<bool> {
+    let Some(credential) = collection(db)
+        .find_one(doc! { "user_id": user_id, "kind": KIND_TOTP, "pending": pending }, None).await?
+    else {
+        return Ok(false);
+    };
+    let Some(step) = totp::matching_step(&credential.secret, code) else {
+        return Ok(false);
+    };
+    let result = collection(db).update_one(
+        doc! { "_id": credential.id, "$or": [{ "last_step": null }, { "last_step": { "$lt": step } }] },
+        doc! { "$set": { "last_step": step, "last_used_at": DateTime::now() } },
+        None,
+    ).await?;
+    Ok(result.modified_count == 1)
+}
+
+/// Store a new authenticator secret awaiting confirmation, replacing an unconfirmed one.
+pub async fn start_totp(db: &Database, user: &User, secret: &str) -> AppResult<()> {
+    let Some(user_id) = user.id else {
+        return Ok(());
+    };
+    let now = DateTime::now();
+    collection(db).update_one(
+        doc! { "user_id": user_id, "kind": KIND_TOTP, "pending": true },
+        doc! {
+            "$set": { "secret": secret, "created_at": now, "changed_at": now },
+            "$setOnInsert": { "org_id": user.org_id },
+        },
+        UpdateOptions::builder().upsert(true).build(),
+    ).await?;
+    Ok(())
+}
+
+/// The unconfirmed authenticator secret from `start_totp`.
+pub async fn pending_totp(db: &Database, user_id: ObjectId) -> AppResult<Option<String>> {
+    Ok(collection(db)
+        .find_one(doc! { "user_id": user_id, "kind": KIND_TOTP, "pending": true }, None).await?
+        .map(|credential| credential.secret))
+}
+
+/// Make the pending authenticator secret the active one.
+pub async fn confirm_totp(db: &Database, user_id: ObjectId) -> AppResult<()> {
+    collection(db).delete_many(doc! { "user_id": user_id, "kind": KIND_TOTP, "pending": false }, None).await?;
+    collection(db).update_one(
+        doc! { "user_id": user_id, "kind": KIND_TOTP, "pending": true },
+        doc! { "$set": { "pending": false, "changed_at": DateTime::now() } },
+        None,
+    ).await?;
+    Ok(())
+}
+
+/// Every credential the user has, secrets included; callers must not expose them.
+pub async fn list(db: &Database, user_id: ObjectId) -> AppResult<Vec<Credential>> {
+    Ok(collection(db).find(doc! { "user_id": user_id }, None).await?.try_collect().await?)
+}
+
+/// Drop the user's credentials of `kind`.
+pub async fn remove(db: &Database, user_id: ObjectId, kind: &str) -> AppResult<()> {
+    collection(db).delete_many(doc! { "user_id": user_id, "kind": kind }, None).await?;
+    Ok(())
+}
+
+/// Drop all of the user's credentials when the account is deleted.
+pub async fn remove_all(db: &Database, user_id: ObjectId) -> AppResult<()> {
+    collection(db).delete_many(doc! { "user_id": user_id }, None).await?;
+    Ok(())
+}
+
+/// Move secrets still stored on user documents into the credentials collection.
+///
+/// Runs at startup; users that were already moved are not matched again.
+pub async fn migrate_user_secrets(db: &Database) -> AppResult<u64> {
+    let users = db.collection::<Document>("users");
+    let legacy = doc! { "$or": [
+        { "password": { "$exists": true } },
+        { "totp_secret": { "$exists": true } },
+        { "totp_pending_secret": { "$exists": true } },
+    ] };
+    let mut cursor = users.find(legacy, None).await?;
+    let mut moved = 0;
+    while let Some(user) = cursor.try_next().await? {
+        let Ok(user_id) = user.get_object_id("_id") else {
+            continue;
+        };
+        let org_id = user.get_object_id("org_id").ok();
+        let changed_at = user.get_datetime("password_changed_at").copied()
+            .unwrap_or_else(|_| user_id.timestamp());
+        let credential = |kind: &str, secret: &str, pending: bool| Credential {
+            id: None,
+            user_id,
+            org_id,
+            kind: kind.to_string(),
+            secret: secret.to_string(),
+            scheme: None,
+            pending,
+            label: None,
+            provider: None,
+            external_id: None,
+            history: Vec::new(),
+            created_at: changed_at,
+            changed_at,
+            last_used_at: None,
+            last_step: None,
+        };
+
+        let mut found = Vec::new();
+        if let Ok(hash) = user.get_str("password") {
+            found.push(Credential {
+                history: user.get_array("password_history").map(|history| {
+                    history.iter().filter_map(|hash| hash.as_str().map(str::to_string)).collect()
+                }).unwrap_or_default(),
+                ..credential(KIND_PASSWORD, hash, false)
+            });
+        }
+        if let Ok(secret) = user.get_str("totp_secret") {
+            found.push(credential(KIND_TOTP, secret, false));
+        }
+        if let Ok(secret) = user.get_str("totp_pending_secret") {
+            found.push(credential(KIND_TOTP, secret, true));
+        }
+        // Skip kinds already moved, so an interrupted run can be repeated
+        for credential in found {
+            collection(db).update_one(
+                doc! { "user_id": user_id, "kind": &credential.kind, "pending": credential.pending },
+                doc! { "$setOnInsert": to_document(&credential).map_err(|e| AppError::internal("credential migration", e))? },
+                UpdateOptions::builder().upsert(true).build(),
+            ).await?;
+        }
+
+        users.update_one(
+            doc! { "_id": user_id },
+            doc! { "$unset": {
+                "password": "",
+                "password_history": "",
+                "password_changed_at": "",
+                "totp_secret": "",
+                "totp_pending_secret": "",
+            } },
+            None,
+        ).await?;
+        moved += 1;
+    }
+    Ok(moved)
+}
@@ -1,18 +1,327 @@
-use mongodb::{Client, options::ClientOptions, Database};
-use std::env;
-use dotenv::dotenv;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    error::{Error, ErrorKind, WriteFailure},
+    options::{ClientOptions, FindOptions, IndexOptions},
+    Client, Database, IndexModel,
+};
 
-pub async fn get_database() -> Database {
-    dotenv().ok();
-    let mongo_uri = env::var("MONGO_URI").expect("MONGO_URI must be set");
+use std::{collections::HashMap, fmt, time::Duration};
 
-    let client_options = ClientOptions::parse(&mongo_uri)
+use crate::credentials;
+use crate::email::normalize_email;
+use crate::settings::DatabaseSettings;
+
+/// Unless the URI says otherwise, operations give up on an unreachable server after this long.
+const SERVER_SELECTION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait before the second startup attempt, doubled after each failure up to `MAX_BACKOFF`.
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Connect to Mongo, waiting until it answers a `ping`, and create the indexes.
+///
+/// The driver connects lazily, so without the ping an unreachable server would
+/// only show up on the first request.
+pub async fn get_database(settings: &DatabaseSettings) -> Database {
+    let mut client_options = ClientOptions::parse(&settings.uri)
         .await
         .expect("Failed to parse MongoDB URI");
+    client_options.server_selection_timeout.get_or_insert(SERVER_SELECTION_TIMEOUT);
 
     let client = Client::with_options(client_options)
-        .expect("Failed to connect to MongoDB");
+        .expect("Failed to create the MongoDB client");
+
+    let db = client.database(&settings.name);
+    wait_until_reachable(&db, settings.connect_attempts)
+        .await
+        .unwrap_or_else(|e| panic!("MongoDB is unreachable after {} attempts: {}", settings.connect_attempts, e));
+    db
+}
+
+/// A failed step of `bootstrap`.
+#[derive(Debug)]
+pub struct BootstrapError {
+    /// What was being done, safe to show in `/readyz`
+    pub step: &'static str,
+    /// The underlying error, which can name hosts or accounts
+    pub detail: String,
+}
+
+impl fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.step, self.detail)
+    }
+}
+
+/// Bring stored data up to date and create the indexes, in that order since the
+/// unique email index needs normalized addresses. Every step can safely run again.
+pub async fn bootstrap(db: &Database) -> Result<(), BootstrapError> {
+    let failed = |step: &'static str| move |detail: String| BootstrapError { step, detail };
+    match normalize_stored_emails(db).await.map_err(failed("normalizing stored emails"))? {
+        0 => {}
+        rewritten => log::info!("normalized the email addresses of {} users", rewritten),
+    }
+    ensure_indexes(db).await
+        .map_err(|e| e.to_string())
+        .map_err(failed("creating indexes"))?;
+    let migrated = credentials::migrate_user_secrets(db).await
+        .map_err(|e| e.to_string())
+        .map_err(failed("moving user secrets to the credentials collection"))?;
+    if migrated > 0 {
+        log::info!("moved secrets of {} users to the credentials collection", migrated);
+    }
+    Ok(())
+}
+
+/// Rewrite stored addresses into their `normalize_email` form, which every lookup uses.
+///
+/// Only addresses with uppercase letters, non-ASCII characters or surrounding
+/// whitespace are looked at. Accounts that would end up sharing an address in
+/// their organization are left alone and reported, since the unique index
+/// cannot be built until someone merges or renames them.
+async fn normalize_stored_emails(db: &Database) -> Result<u64, String> {
+    let users = db.collection::<Document>("users");
+    let candidates = doc! { "email": { "$regex": "[A-Z]|[^\\x00-\\x7F]|^\\s|\\s$" } };
+    let options = FindOptions::builder().projection(doc! { "email": 1, "org_id": 1 }).build();
+    let mut cursor = users.find(candidates, options).await.map_err(|e| e.to_string())?;
+
+    let mut claimed: HashMap<(Option<ObjectId>, String), ObjectId> = HashMap::new();
+    let mut collisions = Vec::new();
+    let mut rewritten = 0;
+    while let Some(user) = cursor.try_next().await.map_err(|e| e.to_string())? {
+        let (Ok(id), Ok(email)) = (user.get_object_id("_id"), user.get_str("email")) else {
+            continue;
+        };
+        let org_id = user.get_object_id("org_id").ok();
+        let normalized = match normalize_email(email) {
+            Ok(normalized) => normalized,
+            Err(e) => {
+                log::warn!("user {} has an unusable email address {:?}: {}", id, email, e);
+                continue;
+            }
+        };
+
+        let existing = users
+            .find_one(doc! { "org_id": org_id, "email": &normalized, "_id": { "$ne": id } }, None).await
+            .map_err(|e| e.to_string())?
+            .and_then(|other| other.get_object_id("_id").ok());
+        let other = existing.or_else(|| claimed.get(&(org_id, normalized.clone())).copied());
+        if let Some(other) = other {
+            collisions.push(format!("{} and {} are both {}", id, other, normalized));
+            continue;
+        }
+
+        claimed.insert((org_id, normalized.clone()), id);
+        users.update_one(doc! { "_id": id }, doc! { "$set": { "email": &normalized } }, None).await
+            .map_err(|e| e.to_string())?;
+        rewritten += 1;
+    }
+
+    if collisions.is_empty() {
+        Ok(rewritten)
+    } else {
+        Err(format!("accounts differ only by email case, merge or rename them: {}", collisions.join("; ")))
+    }
+}
+
+/// Check that the server answers.
+pub async fn ping(db: &Database) -> Result<(), Error> {
+    db.run_command(doc! { "ping": 1 }, None).await.map(|_| ())
+}
+
+async fn wait_until_reachable(db: &Database, attempts: u32) -> Result<(), Error> {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1.. {
+        match ping(db).await {
+            Ok(()) => break,
+            Err(e) if attempt < attempts => {
+                log::warn!("MongoDB is not reachable (attempt {} of {}), retrying in {:?}: {}", attempt, attempts, backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Create the indexes the application relies on. Creating an existing index is a no-op.
+async fn ensure_indexes(db: &Database) -> Result<(), Error> {
+    // Emails are normalized before every write, so a plain unique index enforces
+    // case-insensitive uniqueness while staying usable by ordinary equality lookups.
+    // The same address may be registered once per organization.
+    let users = db.collection::<mongodb::bson::Document>("users");
+    let index_names = match users.list_index_names().await {
+        Ok(names) => names,
+        // A fresh install has no users collection yet, so nothing to drop
+        Err(e) if is_namespace_not_found(&e) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    if index_names.iter().any(|name| name == "email_1") {
+        // Global uniqueness from before organizations existed
+        users.drop_index("email_1", None).await?;
+    }
+    users
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1, "email": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("organizations")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "slug": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("posts")
+        .create_index(IndexModel::builder().keys(doc! { "org_id": 1, "created_at": -1 }).build(), None)
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("audit_events")
+        .create_index(IndexModel::builder().keys(doc! { "created_at": -1 }).build(), None)
+        .await?;
+    // Recent login failures decide when a bot challenge is required
+    db.collection::<mongodb::bson::Document>("audit_events")
+        .create_index(IndexModel::builder().keys(doc! { "action": 1, "created_at": -1 }).build(), None)
+        .await?;
+
+    // Solved proof-of-work puzzles are only kept until they expire
+    db.collection::<mongodb::bson::Document>("used_challenges")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(IndexOptions::builder().expire_after(std::time::Duration::ZERO).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    // Revoked token ids are only kept until the tokens would have expired anyway
+    db.collection::<mongodb::bson::Document>("revoked_tokens")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(IndexOptions::builder().expire_after(std::time::Duration::ZERO).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("otp_codes")
+        .create_index(IndexModel::builder().keys(doc! { "phone": 1, "created_at": -1 }).build(), None)
+        .await?;
+    db.collection::<mongodb::bson::Document>("otp_codes")
+        .create_index(IndexModel::builder().keys(doc! { "user_id": 1, "purpose": 1, "created_at": -1 }).build(), None)
+        .await?;
+
+    // SMS requests only count towards the hourly limit
+    db.collection::<mongodb::bson::Document>("sms_requests")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "created_at": 1 })
+                .options(IndexOptions::builder().expire_after(std::time::Duration::from_secs(60 * 60)).build())
+                .build(),
+            None,
+        )
+        .await?;
+    db.collection::<mongodb::bson::Document>("sms_requests")
+        .create_index(IndexModel::builder().keys(doc! { "phone": 1, "created_at": -1 }).build(), None)
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("invitations")
+        .create_index(IndexModel::builder().keys(doc! { "org_id": 1, "email": 1 }).build(), None)
+        .await?;
 
-    client.database("rust_auth")
+    // Expired exports are removed by `exports::spawn_cleanup`, which also deletes their
+    // GridFS files; a TTL index from older versions would leave those behind
+    let data_exports = db.collection::<mongodb::bson::Document>("data_exports");
+    let indexes = match data_exports.list_indexes(None).await {
+        Ok(cursor) => cursor.try_collect::<Vec<_>>().await?,
+        Err(e) if is_namespace_not_found(&e) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let ttl_index = indexes.into_iter()
+        .find(|index| index.keys == doc! { "expires_at": 1 } && index.options.as_ref().is_some_and(|o| o.expire_after.is_some()));
+    if let Some(name) = ttl_index.and_then(|index| index.options).and_then(|options| options.name) {
+        data_exports.drop_index(name, None).await?;
+    }
+    data_exports
+        .create_index(IndexModel::builder().keys(doc! { "expires_at": 1 }).build(), None)
+        .await?;
+    data_exports
+        .create_index(IndexModel::builder().keys(doc! { "user_id": 1, "created_at": -1 }).build(), None)
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("invite_codes")
+        .create_index(IndexModel::builder().keys(doc! { "code_hash": 1 }).build(), None)
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("email_domain_rules")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1, "domain": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            None,
+        )
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("webhook_subscriptions")
+        .create_index(IndexModel::builder().keys(doc! { "org_id": 1, "events": 1 }).build(), None)
+        .await?;
+
+    // The delivery worker polls for due pending deliveries
+    db.collection::<mongodb::bson::Document>("webhook_deliveries")
+        .create_index(IndexModel::builder().keys(doc! { "status": 1, "next_attempt_at": 1 }).build(), None)
+        .await?;
+    db.collection::<mongodb::bson::Document>("webhook_deliveries")
+        .create_index(IndexModel::builder().keys(doc! { "org_id": 1, "created_at": -1 }).build(), None)
+        .await?;
+
+    db.collection::<mongodb::bson::Document>("sessions")
+        .create_index(IndexModel::builder().keys(doc! { "refresh_token_hash": 1 }).build(), None)
+        .await?;
+
+    // A user has any number of credentials but at most one password
+    db.collection::<mongodb::bson::Document>("credentials")
+        .create_index(IndexModel::builder().keys(doc! { "user_id": 1, "kind": 1 }).build(), None)
+        .await?;
+    db.collection::<mongodb::bson::Document>("credentials")
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "user_id": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .name("user_id_password".to_string())
+                        .unique(true)
+                        .partial_filter_expression(doc! { "kind": "password" })
+                        .build(),
+                )
+                .build(),
+            None,
+        )
+        .await?;
+
+    Ok(())
 }
 
+/// Whether a command failed because its collection does not exist yet.
+fn is_namespace_not_found(error: &Error) -> bool {
+    matches!(error.kind.as_ref(), ErrorKind::Command(e) if e.code == 26)
+}
+
+/// Whether a write failed because it violated a unique index.
+pub fn is_duplicate_key(error: &Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == 11000,
+        _ => false,
+    }
+}
@@ -0,0 +1,51 @@
+/// Canonical form of an email address used for storage and lookups.
+///
+/// Trims whitespace, lowercases the address and converts internationalized
+/// domains to their ASCII (punycode) form, so `Alice@Bücher.de ` and
+/// `alice@xn--bcher-kva.de` identify the same account.
+pub fn normalize_email(raw: &str) -> Result<String, String> {
+    let trimmed = raw.trim();
+    let (local, domain) = trimmed
+        .rsplit_once('@')
+        .ok_or_else(|| "Invalid email address".to_string())?;
+
+    if local.is_empty() || domain.is_empty() || local.chars().any(char::is_whitespace) {
+        return Err("Invalid email address".to_string());
+    }
+
+    let domain = idna::domain_to_ascii(domain).map_err(|_| "Invalid email domain".to_string())?;
+    if domain.is_empty() || !domain.contains('.') {
+        return Err("Invalid email domain".to_string());
+    }
+
+    Ok(format!("{}@{}", local.to_lowercase(), domain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercases_and_trims() {
+        assert_eq!(normalize_email("  Alice.Smith@Example.COM \n").unwrap(), "alice.smith@example.com");
+    }
+
+    #[test]
+    fn converts_idn_domains_to_punycode() {
+        assert_eq!(normalize_email("Alice@Bücher.de").unwrap(), "alice@xn--bcher-kva.de");
+        assert_eq!(normalize_email("alice@xn--bcher-kva.de").unwrap(), "alice@xn--bcher-kva.de");
+        assert_eq!(normalize_email("ÜBER@Example.com").unwrap(), "über@example.com");
+    }
+
+    #[test]
+    fn splits_on_the_last_at_sign() {
+        assert_eq!(normalize_email("\"a@b\"@example.com").unwrap(), "\"a@b\"@example.com");
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for raw in ["", "alice", "@example.com", "alice@", "al ice@example.com", "alice@localhost"] {
+            assert!(normalize_email(raw).is_err(), "{:?}", raw);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::{collections::HashSet, fs, sync::Arc};
+
+use futures::stream::TryStreamExt;
+use mongodb::{bson::doc, Database};
+use trust_dns_resolver::{error::ResolveErrorKind, TokioAsyncResolver};
+
+use crate::error::{AppError, AppResult};
+use crate::models::models::EmailDomainRule;
+use crate::settings::EmailSettings;
+use crate::tenant::Tenant;
+use crate::validation::FieldError;
+
+pub const ACTION_ALLOW: &str = "allow";
+pub const ACTION_DENY: &str = "deny";
+
+/// Bundled list of throwaway mailbox providers.
+const BUNDLED_DISPOSABLE: &str = include_str!("../data/disposable_domains.txt");
+
+/// Screens the domain of addresses used to register or change email.
+///
+/// Checks run in order: syntax, the tenant's allow list (which skips the
+/// rest), its deny list, the disposable provider list and finally an MX lookup.
+#[derive(Clone)]
+pub struct EmailScreening {
+    disposable: Arc<HashSet<String>>,
+    /// `None` when MX checks are turned off
+    resolver: Option<Arc<TokioAsyncResolver>>,
+}
+
+impl EmailScreening {
+    /// Build from the `email` section: extra disposable domains (one per line) and whether to check MX records.
+    pub fn new(settings: &EmailSettings) -> Self {
+        let mut disposable = parse_list(BUNDLED_DISPOSABLE);
+        if let Some(path) = &settings.disposable_domains_file {
+            let extra = fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Failed to read DISPOSABLE_DOMAINS_FILE {}: {}", path, e));
+            disposable.extend(parse_list(&extra));
+        }
+
+        let resolver = settings.mx_check.then(|| {
+            Arc::new(TokioAsyncResolver::tokio_from_system_conf().expect("Failed to read the system DNS configuration"))
+        });
+
+        EmailScreening { disposable: Arc::new(disposable), resolver }
+    }
+
+    /// Reject `email` (already normalized) with a field error on `field` unless its domain passes screening.
+    pub async fn check(&self, db: &Database, tenant: &Tenant, field: &str, email: &str) -> AppResult<()> {
+        let reject = |message: &str| AppError::InvalidFields(vec![FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }]);
+
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default();
+        if !valid_domain(domain) {
+            return Err(reject("has an invalid domain"));
+        }
+
+        let candidates = parent_domains(domain);
+        let rules: Vec<EmailDomainRule> = db.collection::<EmailDomainRule>("email_domain_rules")
+            .find(tenant.scope(doc! { "domain": { "$in": &candidates } }), None).await?
+            .try_collect().await?;
+        match self.listing(&candidates, &rules) {
+            Listing::Allowed => return Ok(()),
+            Listing::Rejected(message) => return Err(reject(message)),
+            Listing::Unlisted => {}
+        }
+        if !self.accepts_mail(domain).await {
+            return Err(reject("has a domain that cannot receive email"));
+        }
+        Ok(())
+    }
+
+    /// Apply the tenant `rules` matching `candidates`, then the disposable list.
+    fn listing(&self, candidates: &[String], rules: &[EmailDomainRule]) -> Listing {
+        if rules.iter().any(|rule| rule.action == ACTION_ALLOW) {
+            Listing::Allowed
+        } else if rules.iter().any(|rule| rule.action == ACTION_DENY) {
+            Listing::Rejected("uses an email domain that is not accepted")
+        } else if candidates.iter().any(|candidate| self.disposable.contains(candidate)) {
+            Listing::Rejected("uses a disposable email provider")
+        } else {
+            Listing::Unlisted
+        }
+    }
+
+    /// Whether `domain` publishes a usable MX record. DNS failures other
+    /// than a definite "no records" let the address through.
+    async fn accepts_mail(&self, domain: &str) -> bool {
+        let Some(resolver) = &self.resolver else {
+            return true;
+        };
+        match resolver.mx_lookup(format!("{}.", domain)).await {
+            // A single "." exchange is a null MX: the domain accepts no mail (RFC 7505)
+            Ok(records) => records.iter().any(|mx| !mx.exchange().is_root()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => false,
+            Err(e) => {
+                log::warn!("MX lookup for {} failed, accepting the address: {}", domain, e);
+                true
+            }
+        }
+    }
+}
+
+/// Outcome of the allow, deny and disposable lists for one domain.
+#[derive(Debug, PartialEq)]
+enum Listing {
+    /// Allowed by the tenant; skips the remaining checks
+    Allowed,
+    Rejected(&'static str),
+    Unlisted,
+}
+
+/// Lowercase ASCII domain, as stored in rules and produced by `normalize_email`.
+pub fn normalize_domain(raw: &str) -> Result<String, String> {
+    let trimmed = raw.trim().trim_start_matches('@');
+    let domain = idna::domain_to_ascii(trimmed).map_err(|_| format!("{} is not a valid domain", trimmed))?;
+    if valid_domain(&domain) {
+        Ok(domain)
+    } else {
+        Err(format!("{} is not a valid domain", trimmed))
+    }
+}
+
+/// Hostname syntax: dot-separated labels of letters, digits and inner hyphens, with an alphabetic TLD.
+fn valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+        && labels.last().is_some_and(|tld| tld.len() >= 2 && (tld.chars().all(|c| c.is_ascii_alphabetic()) || tld.starts_with("xn--")))
+}
+
+/// `a.b.example.com` and every parent down to `example.com`.
+fn parent_domains(domain: &str) -> Vec<String> {
+    let labels: Vec<&str> = domain.split('.').collect();
+    (0..labels.len().saturating_sub(1)).map(|start| labels[start..].join(".")).collect()
+}
+
+fn parse_list(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mongodb::bson::{oid::ObjectId, DateTime};
+
+    fn screening() -> EmailScreening {
+        EmailScreening::new(&EmailSettings { mx_check: false, disposable_domains_file: None })
+    }
+
+    fn rule(domain: &str, action: &str) -> EmailDomainRule {
+        EmailDomainRule {
+            id: None,
+            org_id: None,
+            domain: domain.to_string(),
+            action: action.to_string(),
+            note: None,
+            created_by: ObjectId::new(),
+            created_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn valid_domain_accepts_hostnames() {
+        assert!(valid_domain("example.com"));
+        assert!(valid_domain("mail.example-corp.co.uk"));
+        assert!(valid_domain("xn--bcher-kva.xn--p1ai"));
+        assert!(valid_domain(&format!("{}.com", "a".repeat(63))));
+    }
+
+    #[test]
+    fn valid_domain_rejects_malformed_hostnames() {
+        for domain in ["", "localhost", "example.", ".example.com", "exa mple.com", "-example.com",
+            "example-.com", "example.c", "example.123", "exa_mple.com", "example..com"] {
+            assert!(!valid_domain(domain), "{:?}", domain);
+        }
+        assert!(!valid_domain(&format!("{}.com", "a".repeat(64))));
+        assert!(!valid_domain(&format!("{}.com", vec!["a".repeat(60); 5].join("."))));
+    }
+
+    #[test]
+    fn parent_domains_stop_before_the_tld() {
+        assert_eq!(parent_domains("a.b.example.com"), vec!["a.b.example.com", "b.example.com", "example.com"]);
+        assert_eq!(parent_domains("example.com"), vec!["example.com"]);
+        assert!(parent_domains("com").is_empty());
+    }
+
+    #[test]
+    fn bundled_list_is_parsed() {
+        let screening = screening();
+        assert!(screening.disposable.contains("mailinator.com"));
+        assert!(!screening.disposable.iter().any(|domain| domain.starts_with('#')));
+        assert!(screening.disposable.iter().all(|domain| valid_domain(domain)));
+    }
+
+    #[test]
+    fn allow_beats_deny_beats_disposable() {
+        let screening = screening();
+        let candidates = parent_domains("inbox.mailinator.com");
+
+        assert_eq!(screening.listing(&candidates, &[]), Listing::Rejected("uses a disposable email provider"));
+        assert_eq!(
+            screening.listing(&candidates, &[rule("mailinator.com", ACTION_DENY)]),
+            Listing::Rejected("uses an email domain that is not accepted"),
+        );
+        assert_eq!(
+            screening.listing(&candidates, &[rule("mailinator.com", ACTION_DENY), rule("inbox.mailinator.com", ACTION_ALLOW)]),
+            Listing::Allowed,
+        );
+        assert_eq!(screening.listing(&parent_domains("example.com"), &[]), Listing::Unlisted);
+    }
+}
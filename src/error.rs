@@ -0,0 +1,163 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use async_graphql::ServerError;
+use std::fmt;
+
+use crate::otp::OtpError;
+use crate::validation::FieldError;
+
+/// Errors returned to clients by both the REST endpoints and GraphQL resolvers.
+///
+/// REST renders them as RFC 7807 problem details; GraphQL exposes the same
+/// `code` under `extensions.code`. `Internal` details are logged, never returned.
+#[derive(Debug, Clone)]
+pub enum AppError {
+    Validation(String),
+    /// Rule violations on individual input fields
+    InvalidFields(Vec<FieldError>),
+    NotFound(String),
+    Conflict(String),
+    Unauthenticated(String),
+    Forbidden(String),
+    RateLimited(String),
+    /// A bot challenge has to be solved first
+    ChallengeRequired(String),
+    /// Details are logged where the error is created
+    Internal,
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    /// Log `error` and return an opaque internal error.
+    pub fn internal(context: &str, error: impl fmt::Display) -> Self {
+        log::error!("{}: {}", context, error);
+        AppError::Internal
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) | AppError::InvalidFields(_) => "VALIDATION",
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::Unauthenticated(_) => "UNAUTHENTICATED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::RateLimited(_) => "RATE_LIMITED",
+            AppError::ChallengeRequired(_) => "CHALLENGE_REQUIRED",
+            AppError::Internal => "INTERNAL",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) | AppError::InvalidFields(_) => "Invalid request",
+            AppError::NotFound(_) => "Not found",
+            AppError::Conflict(_) => "Conflict",
+            AppError::Unauthenticated(_) => "Unauthenticated",
+            AppError::Forbidden(_) => "Forbidden",
+            AppError::RateLimited(_) => "Too many requests",
+            AppError::ChallengeRequired(_) => "Challenge required",
+            AppError::Internal => "Internal server error",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Validation(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Unauthenticated(message)
+            | AppError::Forbidden(message)
+            | AppError::RateLimited(message)
+            | AppError::ChallengeRequired(message) => write!(f, "{}", message),
+            AppError::InvalidFields(errors) => {
+                let messages: Vec<String> = errors.iter()
+                    .map(|e| format!("{} {}", e.field, e.message))
+                    .collect();
+                write!(f, "{}", messages.join("; "))
+            }
+            AppError::Internal => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) | AppError::InvalidFields(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unauthenticated(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::ChallengeRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            AppError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = serde_json::json!({
+            "type": "about:blank",
+            "title": self.title(),
+            "status": self.status_code().as_u16(),
+            "detail": self.to_string(),
+            "code": self.code(),
+        });
+        if let AppError::InvalidFields(errors) = self {
+            body["errors"] = serde_json::json!(errors);
+        }
+        HttpResponse::build(self.status_code())
+            .content_type("application/problem+json")
+            .json(body)
+    }
+}
+
+/// Add `extensions.code` (and `extensions.fields` for field errors) to GraphQL
+/// errors raised from an `AppError`.
+pub fn annotate_graphql_error(error: &mut ServerError) {
+    let Some(app_error) = error.source::<AppError>().cloned() else {
+        return;
+    };
+    let extensions = error.extensions.get_or_insert_with(Default::default);
+    extensions.set("code", app_error.code());
+    if let AppError::InvalidFields(errors) = app_error {
+        extensions.set("fields", async_graphql::to_value(errors).unwrap_or_default());
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(e: mongodb::error::Error) -> Self {
+        AppError::internal("database error", e)
+    }
+}
+
+impl From<bcrypt::BcryptError> for AppError {
+    fn from(e: bcrypt::BcryptError) -> Self {
+        AppError::internal("password hashing error", e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        AppError::internal("token encoding error", e)
+    }
+}
+
+/// Resolver context errors, e.g. missing schema data.
+impl From<async_graphql::Error> for AppError {
+    fn from(e: async_graphql::Error) -> Self {
+        AppError::internal("graphql error", e.message)
+    }
+}
+
+impl From<OtpError> for AppError {
+    fn from(e: OtpError) -> Self {
+        match e {
+            OtpError::RateLimited => AppError::RateLimited(e.to_string()),
+            _ => AppError::internal("one-time code error", e),
+        }
+    }
+}
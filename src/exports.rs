@@ -0,0 +1,286 @@
+use std::io::{Cursor, Write};
+
+use actix_web::{http::header, web, HttpResponse};
+use chrono::{Duration, Utc};
+use futures::{io::AsyncReadExt, stream::{self, TryStreamExt}};
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, DateTime, Document},
+    gridfs::GridFsBucket,
+    options::GridFsBucketOptions,
+    Database,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::credentials;
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::mailer::app_url;
+use crate::models::models::DataExport;
+use crate::settings::ServerSettings;
+
+/// Purpose of signed download links.
+pub const DOWNLOAD_LINK: &str = "data_export";
+/// Lifetime of a download link.
+pub const DOWNLOAD_TTL_MINUTES: i64 = 15;
+/// How long a finished archive is kept before the cleanup job removes it.
+const RETENTION_DAYS: i64 = 7;
+/// A pending export older than this was lost, e.g. to a restart while it was being built.
+const STALE_MINUTES: i64 = 10;
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Bytes read from GridFS per response chunk.
+const DOWNLOAD_CHUNK: usize = 64 * 1024;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_READY: &str = "ready";
+pub const STATUS_FAILED: &str = "failed";
+
+/// Queue an export for `user_id` and build it in the background.
+pub async fn start(db: &Database, user_id: ObjectId, org_id: Option<ObjectId>, zip: bool) -> AppResult<DataExport> {
+    let exports = db.collection::<DataExport>("data_exports");
+
+    fail_stale(db, Some(user_id)).await?;
+    let running = exports.find_one(doc! { "user_id": user_id, "status": STATUS_PENDING }, None).await?;
+    if running.is_some() {
+        return Err(AppError::Conflict("An export is already being prepared".to_string()));
+    }
+
+    let mut export = DataExport {
+        id: None,
+        user_id,
+        org_id,
+        format: if zip { "zip" } else { "json" }.to_string(),
+        status: STATUS_PENDING.to_string(),
+        file_id: None,
+        error: None,
+        created_at: DateTime::now(),
+        completed_at: None,
+        expires_at: DateTime::from_millis((Utc::now() + Duration::days(RETENTION_DAYS)).timestamp_millis()),
+    };
+    let export_id = exports.insert_one(&export, None).await?
+        .inserted_id.as_object_id()
+        .ok_or_else(|| AppError::internal("data export", "insert returned no object id"))?;
+    export.id = Some(export_id);
+
+    let db = db.clone();
+    tokio::spawn(async move {
+        if let Err(e) = finish(&db, export_id, user_id, zip).await {
+            log::error!("data export {} failed: {}", export_id, e);
+            let failed = doc! { "$set": { "status": STATUS_FAILED, "error": e.to_string(), "completed_at": DateTime::now() } };
+            if let Err(e) = db.collection::<DataExport>("data_exports")
+                .update_one(doc! { "_id": export_id, "status": STATUS_PENDING }, failed, None).await
+            {
+                log::error!("failed to record data export {} failure: {}", export_id, e);
+            }
+        }
+    });
+
+    Ok(export)
+}
+
+/// Build the archive, store it in GridFS and mark the export ready.
+async fn finish(db: &Database, export_id: ObjectId, user_id: ObjectId, zip: bool) -> AppResult<()> {
+    let archive = build(db, user_id, zip).await?;
+    let file_id = bucket(db)
+        .upload_from_futures_0_3_reader(export_id.to_hex(), futures::io::Cursor::new(archive), None).await?;
+
+    let result = db.collection::<DataExport>("data_exports").update_one(
+        doc! { "_id": export_id, "status": STATUS_PENDING },
+        doc! { "$set": { "status": STATUS_READY, "file_id": file_id, "completed_at": DateTime::now() } },
+        None,
+    ).await?;
+    // The export was given up on or its account purged meanwhile
+    if result.matched_count == 0 {
+        bucket(db).delete(file_id.into()).await?;
+    }
+    Ok(())
+}
+
+/// Archives live in their own GridFS bucket rather than inline in `data_exports`.
+fn bucket(db: &Database) -> GridFsBucket {
+    db.gridfs_bucket(GridFsBucketOptions::builder().bucket_name("export_files".to_string()).build())
+}
+
+/// Mark pending exports that should have finished long ago as failed, for one user or all.
+pub async fn fail_stale(db: &Database, user_id: Option<ObjectId>) -> AppResult<()> {
+    let cutoff = DateTime::from_millis((Utc::now() - Duration::minutes(STALE_MINUTES)).timestamp_millis());
+    let mut filter = doc! { "status": STATUS_PENDING, "created_at": { "$lt": cutoff } };
+    if let Some(user_id) = user_id {
+        filter.insert("user_id", user_id);
+    }
+    db.collection::<DataExport>("data_exports").update_many(
+        filter,
+        doc! { "$set": { "status": STATUS_FAILED, "error": "Export did not finish", "completed_at": DateTime::now() } },
+        None,
+    ).await?;
+    Ok(())
+}
+
+/// Delete the exports matching `filter` together with their archives.
+pub async fn remove(db: &Database, filter: Document) -> AppResult<u64> {
+    let exports = db.collection::<DataExport>("data_exports");
+    let doomed: Vec<DataExport> = exports.find(filter, None).await?.try_collect().await?;
+    let bucket = bucket(db);
+    for export in &doomed {
+        // A failed delete keeps the row so the next run retries
+        if let Some(file_id) = export.file_id {
+            if bucket.find(doc! { "_id": file_id }, None).await?.try_next().await?.is_some() {
+                bucket.delete(file_id.into()).await?;
+            }
+        }
+        exports.delete_one(doc! { "_id": export.id }, None).await?;
+    }
+    Ok(doomed.len() as u64)
+}
+
+/// Every hour, give up on lost exports and delete those past their retention.
+pub fn spawn_cleanup(db: Database) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = fail_stale(&db, None).await {
+                log::error!("failed to expire stale data exports: {}", e);
+            }
+            match remove(&db, doc! { "expires_at": { "$lt": DateTime::now() } }).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("removed {} expired data exports", count),
+                Err(e) => log::error!("data export cleanup failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Everything stored about a user, one JSON value per section.
+async fn collect(db: &Database, user_id: ObjectId) -> AppResult<Vec<(&'static str, Value)>> {
+    let profile = db.collection::<Document>("users")
+        .find_one(doc! { "_id": user_id }, None).await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+    let identities = json!([
+        { "type": "email", "value": profile.get_str("email").ok() },
+        {
+            "type": "phone",
+            "value": profile.get_str("phone_number").ok(),
+            "verified": profile.get_bool("phone_verified").unwrap_or(false),
+            "two_factor": profile.get_bool("sms_two_factor").unwrap_or(false),
+            "login": profile.get_bool("sms_login").unwrap_or(false),
+        },
+        { "type": "totp", "enabled": credentials::totp_secret(db, user_id).await?.is_some() },
+    ]);
+    // Describe credentials without their secrets
+    let credentials: Vec<Value> = credentials::list(db, user_id).await?.into_iter()
+        .filter(|credential| !credential.pending)
+        .map(|credential| json!({
+            "kind": credential.kind,
+            "label": credential.label,
+            "provider": credential.provider,
+            "created_at": credential.created_at.try_to_rfc3339_string().ok(),
+            "last_used_at": credential.last_used_at.and_then(|at| at.try_to_rfc3339_string().ok()),
+        }))
+        .collect();
+
+    let mut sessions = find_all(db, "sessions", doc! { "user_id": user_id }).await?;
+    for session in &mut sessions {
+        session.remove("refresh_token_hash");
+    }
+    let audit_events = find_all(db, "audit_events", doc! {
+        "$or": [{ "actor_id": user_id }, { "target": user_id.to_hex() }],
+    }).await?;
+    let posts = find_all(db, "posts", doc! { "author_id": user_id }).await?;
+
+    Ok(vec![
+        ("profile", to_json(profile)),
+        ("identities", identities),
+        ("credentials", Value::Array(credentials)),
+        ("sessions", Value::Array(sessions.into_iter().map(to_json).collect())),
+        ("audit_events", Value::Array(audit_events.into_iter().map(to_json).collect())),
+        ("posts", Value::Array(posts.into_iter().map(to_json).collect())),
+    ])
+}
+
+async fn find_all(db: &Database, collection: &str, filter: Document) -> AppResult<Vec<Document>> {
+    Ok(db.collection::<Document>(collection).find(filter, None).await?.try_collect().await?)
+}
+
+/// Relaxed extended JSON keeps ids and dates readable.
+fn to_json(document: Document) -> Value {
+    bson::Bson::Document(document).into_relaxed_extjson()
+}
+
+/// Render the archive: one JSON document, or a ZIP with one file per section.
+async fn build(db: &Database, user_id: ObjectId, zip: bool) -> AppResult<Vec<u8>> {
+    let sections = collect(db, user_id).await?;
+    let failed = |e: &dyn std::fmt::Display| AppError::internal("data export", e);
+
+    if !zip {
+        let document: serde_json::Map<String, Value> = sections.into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        return serde_json::to_vec_pretty(&document).map_err(|e| failed(&e));
+    }
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (name, value) in sections {
+        writer.start_file(format!("{}.json", name), options).map_err(|e| failed(&e))?;
+        let body = serde_json::to_vec_pretty(&value).map_err(|e| failed(&e))?;
+        writer.write_all(&body).map_err(|e| failed(&e))?;
+    }
+    Ok(writer.finish().map_err(|e| failed(&e))?.into_inner())
+}
+
+/// Signed URL for downloading a finished export, valid for `DOWNLOAD_TTL_MINUTES`.
+pub fn download_url(jwt: &JwtConfig, server: &ServerSettings, export_id: ObjectId) -> AppResult<String> {
+    let expires_at = (Utc::now() + Duration::minutes(DOWNLOAD_TTL_MINUTES)).timestamp();
+    let token = jwt.sign_link(DOWNLOAD_LINK, export_id.to_hex(), expires_at)?;
+    Ok(app_url(server, &format!("/exports/download?token={}", token)))
+}
+
+#[derive(Deserialize)]
+pub struct DownloadParams {
+    pub token: String,
+}
+
+/// Serve a finished export to the holder of a signed download link.
+pub async fn download(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    params: web::Query<DownloadParams>,
+) -> AppResult<HttpResponse> {
+    let export_id = jwt.verify_link(DOWNLOAD_LINK, &params.token).ok()
+        .and_then(|id| ObjectId::parse_str(id).ok())
+        .ok_or_else(|| AppError::Unauthenticated("Invalid or expired download link".to_string()))?;
+
+    let export = db.collection::<DataExport>("data_exports")
+        .find_one(doc! { "_id": export_id, "status": STATUS_READY }, None).await?
+        .ok_or_else(|| AppError::NotFound("Export not found".to_string()))?;
+    let file_id = export.file_id
+        .ok_or_else(|| AppError::NotFound("Export not found".to_string()))?;
+    let archive = bucket(&db).open_download_stream(file_id.into()).await?;
+
+    // Stream the archive in chunks instead of loading it whole
+    let body = stream::try_unfold(archive, |mut archive| async move {
+        let mut chunk = vec![0; DOWNLOAD_CHUNK];
+        let read = archive.read(&mut chunk).await
+            .map_err(|e| AppError::internal("data export download", e))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        chunk.truncate(read);
+        Ok::<_, AppError>(Some((web::Bytes::from(chunk), archive)))
+    });
+
+    let (content_type, extension) = if export.format == "zip" {
+        ("application/zip", "zip")
+    } else {
+        ("application/json", "json")
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"data-export-{}.{}\"", export_id, extension),
+        ))
+        .streaming(body))
+}
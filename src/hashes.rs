@@ -0,0 +1,220 @@
+use aes::{cipher::{KeyIvInit, StreamCipher}, Aes256};
+use data_encoding::BASE64;
+use hmac::Hmac;
+use sha2::Sha256;
+
+use crate::settings::ImportSettings;
+
+/// Django's `pbkdf2_sha256$<iterations>$<salt>$<base64 hash>`.
+pub const SCHEME_DJANGO_PBKDF2: &str = "pbkdf2_sha256";
+/// Firebase's modified scrypt; the stored secret carries the project's hash parameters.
+pub const SCHEME_FIREBASE_SCRYPT: &str = "firebase_scrypt";
+/// `$6$` SHA-512 crypt from glibc.
+pub const SCHEME_SHA512_CRYPT: &str = "sha512_crypt";
+/// Native hashes; stored without a scheme tag.
+pub const SCHEME_BCRYPT: &str = "bcrypt";
+
+/// Most PBKDF2 iterations accepted, about twice Django's current default, so an
+/// imported hash cannot make every login attempt arbitrarily slow.
+const DJANGO_MAX_ITERATIONS: u32 = 2_000_000;
+
+type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+/// Check `password` against a stored hash; `scheme` is `None` for native bcrypt hashes.
+pub fn verify(scheme: Option<&str>, secret: &str, password: &str) -> bool {
+    match scheme {
+        None | Some(SCHEME_BCRYPT) => bcrypt::verify(password, secret).unwrap_or(false),
+        Some(SCHEME_DJANGO_PBKDF2) => verify_django(secret, password).unwrap_or(false),
+        Some(SCHEME_FIREBASE_SCRYPT) => verify_firebase(secret, password).unwrap_or(false),
+        Some(SCHEME_SHA512_CRYPT) => pwhash::sha512_crypt::verify(password, secret),
+        Some(_) => false,
+    }
+}
+
+/// Project-wide parameters of Firebase's scrypt, as shown in the console's password hash settings.
+#[derive(Debug, Clone)]
+pub struct FirebaseParams {
+    pub signer_key: Vec<u8>,
+    pub salt_separator: Vec<u8>,
+    pub rounds: u32,
+    pub mem_cost: u32,
+}
+
+impl FirebaseParams {
+    /// Take the parameters from the `import` section; they are only required once a Firebase hash is imported.
+    pub fn new(settings: &ImportSettings) -> Result<Self, String> {
+        let required = |value: Option<&str>, name: &str| value.map(str::to_string)
+            .ok_or_else(|| format!("{} must be set to import Firebase hashes", name));
+        let base64 = |value: Option<&String>, name: &str| required(value.map(String::as_str), name).and_then(|value| {
+            BASE64.decode(value.as_bytes()).map_err(|_| format!("{} must be base64", name))
+        });
+        let number = |value: Option<u32>, name: &str| value.ok_or_else(|| format!("{} must be set to import Firebase hashes", name));
+        Ok(FirebaseParams {
+            signer_key: base64(settings.firebase_signer_key.as_ref(), "FIREBASE_HASH_SIGNER_KEY")?,
+            salt_separator: base64(settings.firebase_salt_separator.as_ref(), "FIREBASE_HASH_SALT_SEPARATOR")?,
+            rounds: number(settings.firebase_rounds, "FIREBASE_HASH_ROUNDS")?,
+            mem_cost: number(settings.firebase_mem_cost, "FIREBASE_HASH_MEM_COST")?,
+        })
+    }
+}
+
+/// Turn an exported hash into the `(scheme, secret)` pair kept on the credential.
+///
+/// Without an explicit `scheme` the format is recognized from the hash itself;
+/// Firebase hashes must be named and need the user's `salt` and the project's parameters.
+pub fn import(
+    scheme: Option<&str>,
+    hash: &str,
+    salt: Option<&str>,
+    firebase: impl FnOnce() -> Result<FirebaseParams, String>,
+) -> Result<(Option<String>, String), String> {
+    let hash = hash.trim();
+    let scheme = match scheme.map(|scheme| scheme.trim().to_lowercase()) {
+        Some(scheme) if !scheme.is_empty() => scheme,
+        _ if hash.starts_with("$2") => SCHEME_BCRYPT.to_string(),
+        _ if hash.starts_with("pbkdf2_sha256$") => SCHEME_DJANGO_PBKDF2.to_string(),
+        _ if hash.starts_with("$6$") => SCHEME_SHA512_CRYPT.to_string(),
+        _ => return Err("unrecognized password hash; name its hash_scheme".to_string()),
+    };
+
+    match scheme.as_str() {
+        SCHEME_BCRYPT => {
+            if hash.len() != 60 || !["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix)) {
+                return Err("malformed bcrypt hash".to_string());
+            }
+            Ok((None, hash.to_string()))
+        }
+        SCHEME_DJANGO_PBKDF2 => {
+            parse_django(hash).ok_or_else(|| "malformed Django pbkdf2_sha256 hash".to_string())?;
+            Ok((Some(scheme), hash.to_string()))
+        }
+        SCHEME_SHA512_CRYPT => {
+            if !hash.starts_with("$6$") || hash.split('$').count() < 4 {
+                return Err("malformed SHA-512 crypt hash".to_string());
+            }
+            Ok((Some(scheme), hash.to_string()))
+        }
+        SCHEME_FIREBASE_SCRYPT => {
+            let salt = salt.map(str::trim).filter(|salt| !salt.is_empty())
+                .ok_or_else(|| "Firebase hashes need the user's salt".to_string())?;
+            let (Ok(_), Ok(_)) = (BASE64.decode(hash.as_bytes()), BASE64.decode(salt.as_bytes())) else {
+                return Err("Firebase hash and salt must be base64".to_string());
+            };
+            let params = firebase()?;
+            let secret = [
+                params.rounds.to_string(),
+                params.mem_cost.to_string(),
+                BASE64.encode(&params.salt_separator),
+                BASE64.encode(&params.signer_key),
+                salt.to_string(),
+                hash.to_string(),
+            ].join("$");
+            Ok((Some(scheme), secret))
+        }
+        other => Err(format!("unsupported hash_scheme {}", other)),
+    }
+}
+
+fn parse_django(secret: &str) -> Option<(u32, &str, Vec<u8>)> {
+    let mut parts = secret.split('$');
+    let (Some(SCHEME_DJANGO_PBKDF2), Some(iterations), Some(salt), Some(hash), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    let iterations = iterations.parse().ok().filter(|n| (1..=DJANGO_MAX_ITERATIONS).contains(n))?;
+    Some((iterations, salt, BASE64.decode(hash.as_bytes()).ok()?))
+}
+
+fn verify_django(secret: &str, password: &str) -> Option<bool> {
+    let (iterations, salt, expected) = parse_django(secret)?;
+    let mut derived = vec![0u8; expected.len()];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt.as_bytes(), iterations, &mut derived);
+    Some(constant_time_eq(&derived, &expected))
+}
+
+/// `rounds$mem_cost$salt_separator$signer_key$salt$hash`, as built by `import`.
+fn verify_firebase(secret: &str, password: &str) -> Option<bool> {
+    let parts: Vec<&str> = secret.split('$').collect();
+    let [rounds, mem_cost, salt_separator, signer_key, salt, hash] = parts.as_slice() else {
+        return None;
+    };
+    let decode = |value: &str| BASE64.decode(value.as_bytes()).ok();
+    let mut salt = decode(salt)?;
+    salt.extend(decode(salt_separator)?);
+
+    let params = scrypt::Params::new(mem_cost.parse().ok()?, rounds.parse().ok()?, 1).ok()?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), &salt, &params, &mut key).ok()?;
+
+    // The hash is the signer key encrypted with the derived key
+    let mut block = decode(signer_key)?;
+    Aes256Ctr::new(&key.into(), &[0u8; 16].into()).apply_keystream(&mut block);
+    Some(constant_time_eq(&block, &decode(hash)?))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_firebase() -> Result<FirebaseParams, String> {
+        Err("not configured".to_string())
+    }
+
+    #[test]
+    fn django_pbkdf2_known_answers() {
+        // RFC 7914 section 11: PBKDF2-HMAC-SHA256, P = "passwd", S = "salt", c = 1, first 32 bytes
+        let rfc = "pbkdf2_sha256$1$salt$VawEblbjCJ/sFpHCJUS2BflBhSFt3gRl5oudV8INrLw=";
+        assert!(verify(Some(SCHEME_DJANGO_PBKDF2), rfc, "passwd"));
+        assert!(!verify(Some(SCHEME_DJANGO_PBKDF2), rfc, "password"));
+
+        let django = "pbkdf2_sha256$1000$seasalt$JgZryXe2Ga8ysg6XbzkLpTdyPQrHqsinbL9BnnhgX4A=";
+        assert!(verify(Some(SCHEME_DJANGO_PBKDF2), django, "lètmein"));
+        assert!(!verify(Some(SCHEME_DJANGO_PBKDF2), django, "letmein"));
+    }
+
+    #[test]
+    fn django_iterations_are_capped() {
+        let hash = |iterations: u32| format!("pbkdf2_sha256${}$salt$VawEblbjCJ/sFpHCJUS2BflBhSFt3gRl5oudV8INrLw=", iterations);
+        assert!(import(None, &hash(DJANGO_MAX_ITERATIONS), None, no_firebase).is_ok());
+        assert!(import(None, &hash(DJANGO_MAX_ITERATIONS + 1), None, no_firebase).is_err());
+        assert!(import(None, &hash(0), None, no_firebase).is_err());
+        assert!(!verify(Some(SCHEME_DJANGO_PBKDF2), &hash(u32::MAX), "passwd"));
+    }
+
+    #[test]
+    fn firebase_scrypt_known_answer() {
+        // The sample project parameters and user from Firebase's scrypt reference implementation
+        let params = FirebaseParams {
+            signer_key: BASE64.decode(b"jxspr8Ki0RYycVU8zykbdLGjFQ3McFUH0uiiTvC8pVMXAn210wjLNmdZJzxUECKbm0QsEmYUSDzZvpjeJ9WmXA==").unwrap(),
+            salt_separator: BASE64.decode(b"Bw==").unwrap(),
+            rounds: 8,
+            mem_cost: 14,
+        };
+        let (scheme, secret) = import(
+            Some("firebase_scrypt"),
+            "lSrfV15cpx95/sZS2W9c9Kp6i/LVgQNDNC/qzrCnh1SAyZvqmZqAjTdn3aoItz+VHjoZilo78198JAdRuid5lQ==",
+            Some("42xEC+ixf3L2lw=="),
+            || Ok(params),
+        ).unwrap();
+        assert!(verify(scheme.as_deref(), &secret, "user1password"));
+        assert!(!verify(scheme.as_deref(), &secret, "user2password"));
+    }
+
+    #[test]
+    fn sha512_crypt_known_answers() {
+        // Test vectors from the SHA-crypt specification
+        let default_rounds = "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1";
+        let (scheme, secret) = import(None, default_rounds, None, no_firebase).unwrap();
+        assert_eq!(scheme.as_deref(), Some(SCHEME_SHA512_CRYPT));
+        assert!(verify(scheme.as_deref(), &secret, "Hello world!"));
+        assert!(!verify(scheme.as_deref(), &secret, "Hello world"));
+
+        let explicit_rounds = "$6$rounds=10000$saltstringsaltst$OW1/O6BYHV6BcXZu8QVeXbDWra3Oeqh0sbHbbMCVNSnCM/UrjmM0Dp8vOuZeHBy/YTBmSK6H9qs/y3RnOaw5v.";
+        assert!(verify(Some(SCHEME_SHA512_CRYPT), explicit_rounds, "Hello world!"));
+    }
+}
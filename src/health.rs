@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::{web, HttpResponse};
+use mongodb::Database;
+use serde::Serialize;
+
+use crate::db;
+use crate::jwt::JwtConfig;
+
+/// `/readyz` reports Mongo as down when a ping takes longer than this.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Startup work that has to finish before the service takes traffic.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    bootstrap: Arc<AtomicBool>,
+    /// Step at which the last bootstrap attempt failed
+    bootstrap_failure: Arc<Mutex<Option<&'static str>>>,
+}
+
+/// Run `db::bootstrap` in the background until it succeeds, then mark `readiness`.
+/// Failed attempts are logged and retried with the backoff of the startup ping.
+pub fn spawn_bootstrap(db: Database, readiness: Readiness) {
+    tokio::spawn(async move {
+        let mut backoff = db::INITIAL_BACKOFF;
+        loop {
+            match db::bootstrap(&db).await {
+                Ok(()) => {
+                    *readiness.bootstrap_failure.lock().unwrap() = None;
+                    readiness.bootstrap.store(true, Ordering::Release);
+                    return;
+                }
+                Err(e) => {
+                    log::error!("database bootstrap failed, retrying in {:?}: {}", backoff, e);
+                    *readiness.bootstrap_failure.lock().unwrap() = Some(e.step);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(db::MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
+
+#[derive(Debug, Serialize)]
+pub struct Component {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Component {
+    fn ok() -> Self {
+        Component { status: "ok", latency_ms: None, error: None }
+    }
+
+    fn down(error: impl Into<String>) -> Self {
+        Component { status: "down", latency_ms: None, error: Some(error.into()) }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<&'static str, Component>,
+}
+
+/// Liveness: answers as long as the process can serve requests.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Report { status: "ok", components: BTreeMap::new() })
+}
+
+/// Readiness: Mongo answers a ping, the startup migrations and indexes are in place
+/// and the signing key is loaded. Answers 503 with the failing components otherwise.
+pub async fn readyz(db: web::Data<Database>, jwt: web::Data<JwtConfig>, readiness: web::Data<Readiness>) -> HttpResponse {
+    let mut components = BTreeMap::new();
+
+    let started = Instant::now();
+    let mongo = match tokio::time::timeout(PING_TIMEOUT, db::ping(&db)).await {
+        Ok(Ok(())) => Component { latency_ms: Some(started.elapsed().as_millis()), ..Component::ok() },
+        // Driver errors can name hosts and credentials; they only go to the log
+        Ok(Err(e)) => {
+            log::warn!("readiness ping failed: {}", e);
+            Component::down("ping failed")
+        }
+        Err(_) => Component::down(format!("no answer within {:?}", PING_TIMEOUT)),
+    };
+    components.insert("mongo", mongo);
+    components.insert("bootstrap", if readiness.bootstrap.load(Ordering::Acquire) {
+        Component::ok()
+    } else {
+        match *readiness.bootstrap_failure.lock().unwrap() {
+            Some(step) => Component::down(format!("{} failed, retrying", step)),
+            None => Component::down("migrations and indexes are not in place yet"),
+        }
+    });
+    components.insert("keys", if jwt.has_signing_key() {
+        Component::ok()
+    } else {
+        Component::down("no token signing key")
+    });
+
+    if components.values().all(|component| component.status == "ok") {
+        HttpResponse::Ok().json(Report { status: "ok", components })
+    } else {
+        HttpResponse::ServiceUnavailable().json(Report { status: "unavailable", components })
+    }
+}
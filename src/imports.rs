@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use mongodb::{bson::{doc, oid::ObjectId}, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{self, RequestMeta};
+use crate::auth::{self, authorize};
+use crate::credentials;
+use crate::db::is_duplicate_key;
+use crate::email::normalize_email;
+use crate::error::{AppError, AppResult};
+use crate::hashes::{self, FirebaseParams};
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome, User};
+use crate::settings::{ImportSettings, Settings};
+use crate::sms::normalize_phone;
+use crate::tenant::Tenant;
+
+/// Largest import body accepted by the REST endpoint.
+pub const MAX_IMPORT_BYTES: usize = 50 * 1024 * 1024;
+
+/// One user to import; CSV columns and NDJSON keys share these names.
+#[derive(Debug, Deserialize)]
+pub struct ImportRow {
+    pub email: String,
+    #[serde(default)]
+    pub full_name: Option<String>,
+    #[serde(default)]
+    pub phone_number: Option<String>,
+    /// Comma-separated role names
+    #[serde(default)]
+    pub roles: Option<String>,
+    /// Hash exported from the old system; rows without one get no password
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    /// `bcrypt`, `pbkdf2_sha256`, `sha512_crypt` or `firebase_scrypt`; recognized from the hash when empty
+    #[serde(default)]
+    pub hash_scheme: Option<String>,
+    /// Per-user salt of Firebase hashes
+    #[serde(default)]
+    pub salt: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    /// 1-based record number, not counting the CSV header
+    pub row: usize,
+    pub email: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total: usize,
+    /// Users created, or that would be created in a dry run
+    pub imported: usize,
+    pub failed: usize,
+    pub errors: Vec<RowError>,
+}
+
+/// A validated row, ready to insert.
+struct Prepared {
+    user: User,
+    password: Option<(Option<String>, String)>,
+}
+
+/// Parse `data` as `csv` or `ndjson` and create the users into `tenant`.
+///
+/// Rows are checked independently and reported one by one; a dry run only
+/// checks them. Imported accounts skip the registration policy and email
+/// screening, and keep their foreign password hashes until their first login.
+#[allow(clippy::too_many_arguments)]
+pub async fn import_users(
+    db: &Database,
+    settings: &ImportSettings,
+    tenant: &Tenant,
+    meta: &RequestMeta,
+    actor_id: Option<ObjectId>,
+    format: &str,
+    data: &[u8],
+    dry_run: bool,
+) -> AppResult<ImportReport> {
+    let rows = parse(format, data)?;
+    let mut report = ImportReport { dry_run, total: rows.len(), ..ImportReport::default() };
+    let mut seen = HashSet::new();
+    let mut firebase = None;
+
+    for (index, row) in rows.into_iter().enumerate() {
+        let number = index + 1;
+        let result = match row {
+            Ok(row) => prepare(db, settings, tenant, row, &mut seen, &mut firebase).await,
+            Err(message) => Err((None, message)),
+        };
+        let outcome = match result {
+            Ok(_) if dry_run => Ok(()),
+            Ok(prepared) => insert(db, prepared).await,
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(()) => report.imported += 1,
+            Err((email, message)) => {
+                report.failed += 1;
+                report.errors.push(RowError { row: number, email, message });
+            }
+        }
+    }
+
+    if !dry_run {
+        audit::record(db, meta, AuditEvent {
+            org_id: tenant.org_id(),
+            actor_id,
+            detail: Some(format!("{} imported, {} failed", report.imported, report.failed)),
+            ..audit::event("user.import", AuditOutcome::Success)
+        }).await;
+    }
+    Ok(report)
+}
+
+fn parse(format: &str, data: &[u8]) -> AppResult<Vec<Result<ImportRow, String>>> {
+    match format {
+        "csv" => Ok(csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data)
+            .deserialize::<ImportRow>()
+            .map(|row| row.map_err(|e| e.to_string()))
+            .collect()),
+        "ndjson" => {
+            let text = std::str::from_utf8(data).map_err(|_| AppError::Validation("Import must be UTF-8".to_string()))?;
+            Ok(text.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str::<ImportRow>(line).map_err(|e| e.to_string()))
+                .collect())
+        }
+        _ => Err(AppError::Validation("Unsupported format, use csv or ndjson".to_string())),
+    }
+}
+
+type RowResult<T> = Result<T, (Option<String>, String)>;
+
+async fn prepare(
+    db: &Database,
+    settings: &ImportSettings,
+    tenant: &Tenant,
+    row: ImportRow,
+    seen: &mut HashSet<String>,
+    firebase: &mut Option<Result<FirebaseParams, String>>,
+) -> RowResult<Prepared> {
+    let email = normalize_email(&row.email).map_err(|e| (Some(row.email.clone()), e))?;
+    let fail = |message: String| (Some(email.clone()), message);
+
+    if !seen.insert(email.clone()) {
+        return Err(fail("email appears more than once in the import".to_string()));
+    }
+    let exists = db.collection::<User>("users")
+        .find_one(tenant.scope(doc! { "email": &email }), None).await
+        .map_err(|e| fail(e.to_string()))?;
+    if exists.is_some() {
+        return Err(fail("email is already registered".to_string()));
+    }
+
+    let phone_number = row.phone_number.as_deref().map(str::trim).filter(|phone| !phone.is_empty())
+        .map(normalize_phone).transpose()
+        .map_err(fail)?;
+    let password = match row.password_hash.as_deref().filter(|hash| !hash.trim().is_empty()) {
+        Some(hash) => Some(hashes::import(row.hash_scheme.as_deref(), hash, row.salt.as_deref(), || {
+            // Only needed, and only required to be configured, when the import has Firebase rows
+            firebase.get_or_insert_with(|| FirebaseParams::new(settings)).clone()
+        }).map_err(fail)?),
+        None => None,
+    };
+
+    Ok(Prepared {
+        user: User {
+            id: None,
+            org_id: tenant.org_id(),
+            email,
+            full_name: row.full_name.filter(|name| !name.trim().is_empty()),
+            phone_number,
+            roles: row.roles.unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|role| !role.is_empty())
+                .map(str::to_string)
+                .collect(),
+            phone_verified: false,
+            sms_two_factor: false,
+            sms_login: false,
+            deactivated_at: None,
+            purge_after: None,
+            removed_by: None,
+            pending_approval: false,
+            tokens_valid_after: None,
+        },
+        password,
+    })
+}
+
+async fn insert(db: &Database, prepared: Prepared) -> RowResult<()> {
+    let Prepared { mut user, password } = prepared;
+    let fail = |message: String| (Some(user.email.clone()), message);
+
+    match db.collection::<User>("users").insert_one(&user, None).await {
+        Ok(result) => user.id = result.inserted_id.as_object_id(),
+        // Registered since the row was checked
+        Err(e) if is_duplicate_key(&e) => return Err(fail("email is already registered".to_string())),
+        Err(e) => return Err(fail(e.to_string())),
+    }
+    if let Some((scheme, secret)) = password {
+        if let Err(e) = credentials::set_password(db, &user, scheme.as_deref(), secret, Vec::new()).await {
+            auth::discard_user(db, &user).await;
+            return Err(fail(e.to_string()));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportParams {
+    pub format: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Import users from a CSV (`?format=csv`, default) or NDJSON (`?format=ndjson`) body,
+/// answering with a per-row report. Add `?dry_run=true` to only validate. Admin only.
+pub async fn import(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    settings: web::Data<Settings>,
+    tenant: Tenant,
+    req: HttpRequest,
+    params: web::Query<ImportParams>,
+    body: web::Bytes,
+) -> AppResult<HttpResponse> {
+    let claims = authorize(&req, &jwt, &db, "admin").await?;
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    let actor_id = ObjectId::parse_str(&claims.sub).ok();
+
+    let format = params.format.as_deref().unwrap_or("csv");
+    let report = import_users(&db, &settings.import, &tenant, &meta, actor_id, format, &body, params.dry_run).await?;
+    Ok(HttpResponse::Ok().json(report))
+}
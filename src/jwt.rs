@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, errors::Result, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::models::models::{Actor, Claims, User};
+use crate::settings::JwtSettings;
+
+/// Registered claim names that a `ClaimsProvider` is not allowed to override.
+const RESERVED_CLAIMS: &[&str] = &["iss", "aud", "sub", "exp", "iat", "nbf", "jti", "roles", "auth_time", "amr", "act", "org"];
+
+/// Claims of a signed link such as an invitation.
+///
+/// The audience is suffixed with the link's purpose so a link is never accepted
+/// as an access token or as a link of another kind.
+#[derive(Debug, Serialize, Deserialize)]
+struct LinkClaims {
+    iss: String,
+    aud: String,
+    sub: String,
+    exp: usize,
+}
+
+/// Hook for adding custom claims (tenant, plan, ...) to a user's access token.
+pub trait ClaimsProvider: Send + Sync {
+    fn custom_claims(&self, user: &User) -> Map<String, Value>;
+}
+
+/// Default provider: tokens only carry the standard claims and roles.
+pub struct NoCustomClaims;
+
+impl ClaimsProvider for NoCustomClaims {
+    fn custom_claims(&self, _user: &User) -> Map<String, Value> {
+        Map::new()
+    }
+}
+
+/// Adds the OpenID-style `email` and `name` profile claims.
+pub struct ProfileClaims;
+
+impl ClaimsProvider for ProfileClaims {
+    fn custom_claims(&self, user: &User) -> Map<String, Value> {
+        let mut claims = Map::new();
+        claims.insert("email".to_string(), Value::from(user.email.clone()));
+        if let Some(name) = &user.full_name {
+            claims.insert("name".to_string(), Value::from(name.clone()));
+        }
+        claims
+    }
+}
+
+/// Settings used to issue and verify access tokens.
+#[derive(Clone)]
+pub struct JwtConfig {
+    secret: String,
+    pub issuer: String,
+    pub audience: String,
+    pub access_token_ttl: Duration,
+    /// Lifetime of the short-lived token issued by `reauthenticate`.
+    pub elevated_token_ttl: Duration,
+    /// How long after authenticating a user may still perform sensitive operations.
+    pub reauth_max_age: Duration,
+    /// Lifetime of tokens issued to admins impersonating a user.
+    pub impersonation_ttl: Duration,
+    /// Lifetime of a login session and its refresh token.
+    pub refresh_token_ttl: Duration,
+    /// Clock skew tolerated when checking `exp` and `nbf`, in seconds.
+    pub leeway: u64,
+    claims_provider: Arc<dyn ClaimsProvider>,
+}
+
+impl JwtConfig {
+    /// Build the token settings from the `jwt` section of the configuration.
+    pub fn new(settings: &JwtSettings) -> Self {
+        JwtConfig {
+            secret: settings.secret.clone(),
+            issuer: settings.issuer.clone(),
+            audience: settings.audience.clone(),
+            access_token_ttl: Duration::seconds(settings.access_ttl_secs),
+            elevated_token_ttl: Duration::seconds(settings.elevated_ttl_secs),
+            reauth_max_age: Duration::seconds(settings.reauth_max_age_secs),
+            impersonation_ttl: Duration::seconds(settings.impersonation_ttl_secs),
+            refresh_token_ttl: Duration::seconds(settings.refresh_ttl_secs),
+            leeway: settings.leeway_secs as u64,
+            claims_provider: Arc::new(NoCustomClaims),
+        }
+    }
+
+    /// Whether tokens can be signed and verified.
+    pub fn has_signing_key(&self) -> bool {
+        !self.secret.is_empty()
+    }
+
+    pub fn with_claims_provider(mut self, provider: Arc<dyn ClaimsProvider>) -> Self {
+        self.claims_provider = provider;
+        self
+    }
+
+    /// Build the claims for a freshly issued access token.
+    ///
+    /// `amr` lists the methods the user just authenticated with (`pwd`, `otp`, ...).
+    pub fn claims_for(&self, user: &User, subject: String, amr: &[&str]) -> Claims {
+        self.build_claims(user, subject, amr, self.access_token_ttl)
+    }
+
+    /// Claims for a short-lived token proving the user re-authenticated just now.
+    pub fn elevated_claims_for(&self, user: &User, subject: String, amr: &[&str]) -> Claims {
+        self.build_claims(user, subject, amr, self.elevated_token_ttl)
+    }
+
+    /// Claims letting the admin `admin_id` act as `user`.
+    ///
+    /// The token carries the target's roles and no authentication methods, so it
+    /// never satisfies step-up checks.
+    pub fn impersonation_claims_for(&self, user: &User, subject: String, admin_id: String) -> Claims {
+        let mut claims = self.build_claims(user, subject, &[], self.impersonation_ttl);
+        claims.auth_time = 0;
+        claims.act = Some(Actor { sub: admin_id });
+        claims
+    }
+
+    fn build_claims(&self, user: &User, subject: String, amr: &[&str], ttl: Duration) -> Claims {
+        let now = Utc::now();
+        let mut custom = self.claims_provider.custom_claims(user);
+        custom.retain(|name, _| !RESERVED_CLAIMS.contains(&name.as_str()));
+
+        Claims {
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            sub: subject,
+            exp: (now + ttl).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            nbf: now.timestamp() as usize,
+            jti: ObjectId::new().to_hex(),
+            auth_time: now.timestamp() as usize,
+            amr: amr.iter().map(|method| method.to_string()).collect(),
+            act: None,
+            org: user.org_id.map(|id| id.to_hex()),
+            roles: user.roles.clone(),
+            custom,
+        }
+    }
+
+    pub fn encode(&self, claims: &Claims) -> Result<String> {
+        encode(
+            &Header::new(Algorithm::HS256),
+            claims,
+            &EncodingKey::from_secret(self.secret.as_ref()),
+        )
+    }
+
+    /// Decode a token, checking signature, `exp`, `nbf`, `iss` and `aud`.
+    pub fn verify(&self, token: &str) -> Result<Claims> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = self.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud", "sub"]);
+
+        decode::<Claims>(token, &DecodingKey::from_secret(self.secret.as_ref()), &validation)
+            .map(|data| data.claims)
+    }
+
+    /// Sign a link token for `purpose` naming `subject`, valid until `expires_at` (Unix seconds).
+    pub fn sign_link(&self, purpose: &str, subject: String, expires_at: i64) -> Result<String> {
+        let claims = LinkClaims {
+            iss: self.issuer.clone(),
+            aud: format!("{}:{}", self.audience, purpose),
+            sub: subject,
+            exp: expires_at as usize,
+        };
+        encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(self.secret.as_ref()))
+    }
+
+    /// Check a link token signed for `purpose` and return its subject.
+    pub fn verify_link(&self, purpose: &str, token: &str) -> Result<String> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = self.leeway;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[format!("{}:{}", self.audience, purpose)]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        decode::<LinkClaims>(token, &DecodingKey::from_secret(self.secret.as_ref()), &validation)
+            .map(|data| data.claims.sub)
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use async_graphql::Schema;
+use mongodb::Database;
+
+use crate::accounts::AccountConfig;
+use crate::challenge::ChallengeConfig;
+use crate::cors::CorsPolicy;
+use crate::email_domains::EmailScreening;
+use crate::jwt::JwtConfig;
+use crate::mailer::Mailer;
+use crate::passwords::PasswordPolicy;
+use crate::schema::{MutationRoot, QueryRoot};
+use crate::settings::Settings;
+use crate::sms::SmsConfig;
+
+pub mod models;
+pub mod accounts;
+pub mod audit;
+pub mod auth;
+pub mod challenge;
+pub mod cors;
+pub mod credentials;
+pub mod db;
+pub mod email;
+pub mod email_domains;
+pub mod error;
+pub mod exports;
+pub mod hashes;
+pub mod health;
+pub mod imports;
+pub mod jwt;
+pub mod mailer;
+pub mod otp;
+pub mod passwords;
+pub mod registration;
+pub mod schema;
+pub mod sessions;
+pub mod settings;
+pub mod sms;
+pub mod tenant;
+pub mod tokens;
+pub mod totp;
+pub mod validation;
+pub mod webhooks;
+
+pub type MySchema = Schema<QueryRoot, MutationRoot, async_graphql::EmptySubscription>;
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_schema(
+    db: Database,
+    jwt: JwtConfig,
+    mailer: Arc<dyn Mailer>,
+    sms: SmsConfig,
+    accounts: AccountConfig,
+    challenges: ChallengeConfig,
+    screening: EmailScreening,
+    passwords: PasswordPolicy,
+    settings: Settings,
+    cors: CorsPolicy,
+) -> MySchema {
+    Schema::build(QueryRoot::default(), MutationRoot::default(), async_graphql::EmptySubscription)
+        .data(db)
+        .data(jwt)
+        .data(mailer)
+        .data(sms)
+        .data(accounts)
+        .data(challenges)
+        .data(screening)
+        .data(passwords)
+        .data(settings)
+        .data(cors)
+        .finish()
+}
+
+/// The GraphQL schema in SDL form; resolvers are not run, so no data is needed.
+pub fn schema_sdl() -> String {
+    Schema::build(QueryRoot::default(), MutationRoot::default(), async_graphql::EmptySubscription)
+        .finish()
+        .sdl()
+}
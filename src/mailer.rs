@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+
+use crate::settings::ServerSettings;
+
+/// Outgoing email transport.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+/// Development mailer that writes messages to the log instead of sending them.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        log::info!("email to {}: {}\n{}", to, subject, body);
+        Ok(())
+    }
+}
+
+/// Build an absolute link to the frontend, e.g. for confirmation emails.
+pub fn app_url(server: &ServerSettings, path: &str) -> String {
+    let base = &server.app_base_url;
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
@@ -1,61 +1,97 @@
-use actix_web::{http::header, web, App, HttpServer};
-use async_graphql::Schema;
+use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
-use mongodb::Database;
-use actix_cors::Cors;
-use schema::{MutationRoot, QueryRoot};
-use crate::schema::{graphql_handler, public_graphql_playground};
-
-
-mod models;
-mod auth;
-mod db;
-mod schema;
-
-
-pub type MySchema = Schema<QueryRoot, MutationRoot, async_graphql::EmptySubscription>;
-
-fn create_schema(db: Database) -> MySchema {
-    Schema::build(QueryRoot::default(), MutationRoot::default(), async_graphql::EmptySubscription)
-        .data(db)
-        .finish()
-}
-
+use rust_auth::schema::{graphql_handler, public_graphql_playground};
+use rust_auth::accounts::{self, AccountConfig};
+use rust_auth::challenge::{self, ChallengeConfig};
+use rust_auth::cors::{self, CorsPolicy};
+use rust_auth::email_domains::EmailScreening;
+use rust_auth::passwords::{self, PasswordPolicy};
+use rust_auth::jwt::{JwtConfig, ProfileClaims};
+use rust_auth::mailer::{LogMailer, Mailer};
+use rust_auth::settings::Settings;
+use rust_auth::health::{self, Readiness};
+use rust_auth::sms::SmsConfig;
+use rust_auth::{audit, auth, create_schema, db, exports, imports, webhooks};
+use std::sync::Arc;
 
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
+    env_logger::init();
 
-    let db = db::get_database().await;
-    println!("Connected to database: {}", db.name());
-    let schema = create_schema(db.clone());
-
-    HttpServer::new(move || {
-
-         let cors = Cors::permissive()
-            .allowed_methods(vec!["GET", "POST", "DELETE", "UPDATE"])
-            .allowed_headers(vec![
-                header::CONTENT_TYPE,
-                header::AUTHORIZATION,
-                header::ACCESS_CONTROL_ALLOW_ORIGIN,
-                header::ACCEPT,
-            ])
-            .allow_any_header()
-            .max_age(3600)
-            .supports_credentials();
+    let settings = Settings::load().unwrap_or_else(|e| {
+        eprint!("{}", e);
+        std::process::exit(1);
+    });
 
+    let db = db::get_database(&settings.database).await;
+    log::info!("connected to database {}", db.name());
+    // `/readyz` reports not ready until the bootstrap has finished
+    let readiness = Readiness::default();
+    health::spawn_bootstrap(db.clone(), readiness.clone());
+    let jwt = JwtConfig::new(&settings.jwt).with_claims_provider(Arc::new(ProfileClaims));
+    let mailer: Arc<dyn Mailer> = Arc::new(LogMailer);
+    let sms = SmsConfig::new(&settings.sms);
+    let account_config = AccountConfig::new(&settings.accounts);
+    accounts::spawn_purge_job(db.clone(), account_config.clone());
+    webhooks::spawn_delivery_worker(db.clone(), settings.webhooks.clone());
+    exports::spawn_cleanup(db.clone());
+    let challenges = ChallengeConfig::new(&settings.challenge, &db);
+    let screening = EmailScreening::new(&settings.email);
+    let password_policy = PasswordPolicy::new(&settings.passwords);
+    let cors_policy = CorsPolicy::new(&settings);
+    cors_policy.refresh(&db).await.expect("Failed to load organization CORS origins");
+    cors::spawn_refresh(db.clone(), cors_policy.clone());
+    let schema = create_schema(
+        db.clone(),
+        jwt.clone(),
+        mailer,
+        sms.clone(),
+        account_config,
+        challenges.clone(),
+        screening.clone(),
+        password_policy.clone(),
+        settings.clone(),
+        cors_policy.clone(),
+    );
+    let bind = settings.server.bind.clone();
 
+    HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db.clone()))
             .app_data(web::Data::new(schema.clone()))
+            .app_data(web::Data::new(jwt.clone()))
+            .app_data(web::Data::new(sms.clone()))
+            .app_data(web::Data::new(challenges.clone()))
+            .app_data(web::Data::new(screening.clone()))
+            .app_data(web::Data::new(password_policy.clone()))
+            .app_data(web::Data::new(settings.clone()))
+            .app_data(web::Data::new(readiness.clone()))
+            .route("/healthz", web::get().to(health::healthz))
+            .route("/readyz", web::get().to(health::readyz))
+            .route("/challenge", web::get().to(challenge::issue))
             .route("/register", web::post().to(auth::register_user))
             .route("/login", web::post().to(auth::login_user))
+            .route("/login/2fa", web::post().to(auth::verify_two_factor))
+            .route("/password/change", web::post().to(passwords::change_expired_password))
+            .route("/login/sms", web::post().to(auth::request_sms_login))
+            .route("/login/sms/verify", web::post().to(auth::verify_sms_login))
+            .route("/token/refresh", web::post().to(auth::refresh_token))
+            .route("/logout", web::post().to(auth::logout))
+            .route("/account/restore", web::post().to(auth::restore))
+            .route("/exports/download", web::get().to(exports::download))
+            .route("/admin/audit/export", web::get().to(audit::export_events))
+            .service(
+                web::resource("/admin/users/import")
+                    .app_data(web::PayloadConfig::new(imports::MAX_IMPORT_BYTES))
+                    .route(web::post().to(imports::import)),
+            )
             .route("/graphql", web::post().to(graphql_handler))
             .service(public_graphql_playground)
-            .wrap(cors)
+            .wrap(cors_policy.middleware())
     })
-    .bind("127.0.0.1:8080")?
+    .bind(bind)?
     .run()
     .await
 }
\ No newline at end of file
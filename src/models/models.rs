@@ -8,10 +8,79 @@ use serde::{Deserialize, Serialize};
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    // Owning organization; `None` for users of the default tenant
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
     pub email: String,
-    pub password: String,
     pub full_name: Option<String>,
     pub phone_number: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub phone_verified: bool,
+    // Require an SMS code after the password at login
+    #[serde(default)]
+    pub sms_two_factor: bool,
+    // Accept a texted code alone at login; off unless the user opts in
+    #[serde(default)]
+    pub sms_login: bool,
+    // Set while the account is deactivated; logins are refused
+    #[serde(default)]
+    pub deactivated_at: Option<DateTime>,
+    // End of the grace period, after which the purge job deletes the account
+    #[serde(default)]
+    pub purge_after: Option<DateTime>,
+    // Admin who removed the account from its organization; the user cannot restore it
+    #[serde(default)]
+    pub removed_by: Option<ObjectId>,
+    // Self-registered account waiting for an admin; logins are refused
+    #[serde(default)]
+    pub pending_approval: bool,
+    // Access tokens issued before this are rejected; moved forward when all sessions are revoked
+    #[serde(default)]
+    pub tokens_valid_after: Option<DateTime>,
+}
+
+// A way for a user to prove who they are, kept out of the user document so
+// profile queries never load secrets. A user may have none or several.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Credential {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    // password, totp, passkey, api_key or identity
+    pub kind: String,
+    // Password or API key hash, base32 TOTP secret or passkey public key; empty for linked identities
+    #[serde(default)]
+    pub secret: String,
+    // Format of an imported password hash; unset for native bcrypt hashes
+    #[serde(default)]
+    pub scheme: Option<String>,
+    // TOTP secret awaiting confirmation with a first valid code
+    #[serde(default)]
+    pub pending: bool,
+    // Name given by the user, e.g. for a passkey or API key
+    #[serde(default)]
+    pub label: Option<String>,
+    // Identity provider of a linked identity
+    #[serde(default)]
+    pub provider: Option<String>,
+    // Passkey credential ID, API key prefix or the account ID at the identity provider
+    #[serde(default)]
+    pub external_id: Option<String>,
+    // Hashes of earlier passwords, newest first, so they cannot be reused
+    #[serde(default)]
+    pub history: Vec<String>,
+    pub created_at: DateTime,
+    // When the secret was last set
+    pub changed_at: DateTime,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime>,
+    // TOTP time step of the last accepted code, which cannot be used again
+    #[serde(default)]
+    pub last_step: Option<i64>,
 }
 
 // For login request
@@ -28,25 +97,298 @@ pub struct AuthUser {
     pub password: String,
     pub full_name: Option<String>,
     pub phone_number: Option<String>,
+    // Required when the tenant only accepts registrations with an invite code
+    #[serde(default)]
+    pub invite_code: Option<String>,
 }
 
 // For JWT claims
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
-    pub sub: String, // Subject (email)
+    pub iss: String, // Issuer
+    pub aud: String, // Audience
+    pub sub: String, // Subject (user ObjectId hex)
     pub exp: usize,  // Expiration timestamp
+    pub iat: usize,  // Issued at
+    pub nbf: usize,  // Not valid before
+    pub jti: String, // Unique token id
+    #[serde(default)]
+    pub auth_time: usize, // When the user last proved their identity
+    #[serde(default)]
+    pub amr: Vec<String>, // Authentication methods used (pwd, otp, ...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>, // Admin acting on behalf of `sub` (impersonation)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>, // Organization (tenant) id of `sub`
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Extra claims added by a `ClaimsProvider`
+    #[serde(flatten)]
+    pub custom: serde_json::Map<String, serde_json::Value>,
+}
+
+// Actor claim (RFC 8693): the party actually using an impersonation token
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Actor {
+    pub sub: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Post {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
     pub title: String,
     pub thumbnail: String,
-    #[serde(rename = "author_id")]
-    pub author: ObjectId,
+    // `None` once the author's account was purged with the anonymize policy
+    #[serde(rename = "author_id", default)]
+    pub author: Option<ObjectId>,
     pub desc: String,
     pub created_at: Option<DateTime>,
     pub updated_at: Option<DateTime>,
 }
 
+
+// Pending email change, confirmed through a token sent to the new address
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailChange {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub new_email: String,
+    pub token_hash: String,
+    pub expires_at: DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+// Append-only record of an authentication or account event
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    // Organization the event happened in; `None` for the default tenant
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    pub actor_id: Option<ObjectId>,
+    pub actor_email: Option<String>,
+    // Admin who performed the action while impersonating the actor
+    #[serde(default)]
+    pub impersonator_id: Option<ObjectId>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: AuditOutcome,
+    pub detail: Option<String>,
+    pub created_at: DateTime,
+}
+
+// One-time code sent by SMS
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OtpCode {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub phone: String,
+    pub purpose: String,
+    pub code_hash: String,
+    // Set for second-factor codes, identifies the pending login
+    pub challenge_hash: Option<String>,
+    pub attempts: i32,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+// Login session backing a refresh token
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub refresh_token_hash: String,
+    // Authentication methods used when the session was created
+    #[serde(default)]
+    pub amr: Vec<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: DateTime,
+    pub expires_at: DateTime,
+    pub revoked_at: Option<DateTime>,
+}
+
+/// A tenant: one client brand sharing this deployment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Organization {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    // Used as subdomain and in the `X-Tenant` header
+    pub slug: String,
+    pub name: String,
+    #[serde(default)]
+    pub settings: OrganizationSettings,
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationSettings {
+    // Any of "password", "sms"
+    pub allowed_login_methods: Vec<String>,
+    #[serde(default)]
+    pub registration: RegistrationPolicy,
+    // Browser origins allowed to call this tenant, on top of the deployment's list
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for OrganizationSettings {
+    fn default() -> Self {
+        OrganizationSettings {
+            allowed_login_methods: vec!["password".to_string(), "sms".to_string()],
+            registration: RegistrationPolicy::default(),
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+/// Who may create an account on their own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationPolicy {
+    // "open", "closed", "invite_code", "domain" or "approval"
+    pub mode: String,
+    // Email domains accepted in "domain" mode
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for RegistrationPolicy {
+    fn default() -> Self {
+        RegistrationPolicy {
+            mode: "open".to_string(),
+            allowed_domains: Vec::new(),
+        }
+    }
+}
+
+/// A shareable code that lets its holders register while registration is invite-only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteCode {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    pub code_hash: String,
+    pub created_by: ObjectId,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: Option<DateTime>,
+    pub created_at: DateTime,
+    pub revoked_at: Option<DateTime>,
+}
+
+/// A pending or settled invitation to join an organization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invitation {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    pub email: String,
+    pub role: String,
+    pub invited_by: ObjectId,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+    pub accepted_at: Option<DateTime>,
+    pub revoked_at: Option<DateTime>,
+}
+
+/// A personal data export requested by a user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataExport {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    // "json" or "zip"
+    pub format: String,
+    // "pending", "ready" or "failed"
+    pub status: String,
+    // GridFS file holding the finished archive
+    #[serde(default)]
+    pub file_id: Option<ObjectId>,
+    pub error: Option<String>,
+    pub created_at: DateTime,
+    pub completed_at: Option<DateTime>,
+    // Removed by a TTL index after this time
+    pub expires_at: DateTime,
+}
+
+/// An endpoint that receives signed event notifications for an organization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSubscription {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    pub url: String,
+    // Event names, or "*" for every event
+    pub events: Vec<String>,
+    // HMAC key for the X-Webhook-Signature header
+    pub secret: String,
+    pub active: bool,
+    pub created_at: DateTime,
+}
+
+/// One event queued for one subscription, with its delivery history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub subscription_id: ObjectId,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    pub event: String,
+    // JSON body sent to the endpoint
+    pub payload: String,
+    // "pending", "delivered" or "failed"
+    pub status: String,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime,
+    #[serde(default)]
+    pub log: Vec<DeliveryAttempt>,
+    pub created_at: DateTime,
+    pub delivered_at: Option<DateTime>,
+}
+
+/// Outcome of a single POST to a webhook endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryAttempt {
+    pub at: DateTime,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+/// An admin-managed exception to email domain screening.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailDomainRule {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(default)]
+    pub org_id: Option<ObjectId>,
+    // Lowercase ASCII domain; also applies to its subdomains
+    pub domain: String,
+    // "allow" or "deny"
+    pub action: String,
+    pub note: Option<String>,
+    pub created_by: ObjectId,
+    pub created_at: DateTime,
+}
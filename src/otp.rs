@@ -0,0 +1,138 @@
+use chrono::{Duration, Utc};
+use mongodb::{bson::{doc, oid::ObjectId, DateTime, Document}, options::FindOneAndUpdateOptions, Database};
+use rand::Rng;
+use std::fmt;
+
+use crate::models::models::OtpCode;
+use crate::sms::SmsConfig;
+use crate::tokens::hash_token;
+
+/// How long a code stays valid.
+const CODE_TTL_MINUTES: i64 = 10;
+/// Wrong guesses allowed before a code is discarded.
+const MAX_ATTEMPTS: i32 = 5;
+
+pub const PURPOSE_VERIFY_PHONE: &str = "verify_phone";
+pub const PURPOSE_LOGIN: &str = "login";
+pub const PURPOSE_TWO_FACTOR: &str = "two_factor";
+
+#[derive(Debug)]
+pub enum OtpError {
+    RateLimited,
+    Send(String),
+    Database(mongodb::error::Error),
+}
+
+impl fmt::Display for OtpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OtpError::RateLimited => write!(f, "Too many codes requested, please try again later"),
+            OtpError::Send(e) => write!(f, "Failed to send code: {}", e),
+            OtpError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for OtpError {
+    fn from(e: mongodb::error::Error) -> Self {
+        OtpError::Database(e)
+    }
+}
+
+/// Count a request to text `phone`, refusing it once the number has had
+/// `max_per_hour` requests in the last hour or one within `resend_interval`.
+///
+/// Requests count whether or not a code is sent, so the limit answers the same
+/// for numbers that belong to no account.
+pub async fn throttle(db: &Database, sms: &SmsConfig, phone: &str) -> Result<(), OtpError> {
+    let sends = db.collection::<Document>("sms_requests");
+    let now = Utc::now();
+
+    let hour_ago = DateTime::from_millis((now - Duration::hours(1)).timestamp_millis());
+    let sent_last_hour = sends.count_documents(doc! { "phone": phone, "created_at": { "$gt": hour_ago } }, None).await?;
+    if sent_last_hour >= sms.max_per_hour {
+        return Err(OtpError::RateLimited);
+    }
+
+    let interval_ago = DateTime::from_millis((now - sms.resend_interval).timestamp_millis());
+    if sends.count_documents(doc! { "phone": phone, "created_at": { "$gt": interval_ago } }, None).await? > 0 {
+        return Err(OtpError::RateLimited);
+    }
+
+    sends.insert_one(doc! { "phone": phone, "created_at": DateTime::from_millis(now.timestamp_millis()) }, None).await?;
+    Ok(())
+}
+
+/// Generate a code for `purpose`, store its hash and text it to `phone`, subject to `throttle`.
+pub async fn send_code(
+    db: &Database,
+    sms: &SmsConfig,
+    user_id: ObjectId,
+    phone: &str,
+    purpose: &str,
+    challenge_hash: Option<String>,
+) -> Result<(), OtpError> {
+    throttle(db, sms, phone).await?;
+    deliver_code(db, sms, user_id, phone, purpose, challenge_hash).await
+}
+
+/// `send_code` for callers that already called `throttle`.
+pub async fn deliver_code(
+    db: &Database,
+    sms: &SmsConfig,
+    user_id: ObjectId,
+    phone: &str,
+    purpose: &str,
+    challenge_hash: Option<String>,
+) -> Result<(), OtpError> {
+    let codes = db.collection::<OtpCode>("otp_codes");
+    let now = Utc::now();
+    let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+
+    // Only the most recent code for a purpose is accepted
+    codes.update_many(
+        doc! { "user_id": user_id, "purpose": purpose, "expires_at": { "$gt": DateTime::now() } },
+        doc! { "$set": { "expires_at": consumed() } },
+        None,
+    ).await?;
+    codes.insert_one(OtpCode {
+        id: None,
+        user_id,
+        phone: phone.to_string(),
+        purpose: purpose.to_string(),
+        code_hash: hash_token(&code),
+        challenge_hash,
+        attempts: 0,
+        expires_at: DateTime::from_millis((now + Duration::minutes(CODE_TTL_MINUTES)).timestamp_millis()),
+        created_at: DateTime::from_millis(now.timestamp_millis()),
+    }, None).await?;
+
+    sms.sender
+        .send(phone, &format!("Your verification code is {}. It expires in {} minutes.", code, CODE_TTL_MINUTES))
+        .await
+        .map_err(OtpError::Send)
+}
+
+/// Check `code` against the live code matching `filter`, consuming it on a match.
+///
+/// Matching and consuming is a single update, so a code is accepted at most once
+/// even under concurrent guesses; wrong guesses count towards `MAX_ATTEMPTS`.
+pub async fn verify_code(db: &Database, mut filter: Document, code: &str) -> Result<Option<OtpCode>, OtpError> {
+    let codes = db.collection::<OtpCode>("otp_codes");
+    filter.insert("expires_at", doc! { "$gt": DateTime::now() });
+    filter.insert("attempts", doc! { "$lt": MAX_ATTEMPTS });
+    let newest = || FindOneAndUpdateOptions::builder().sort(doc! { "created_at": -1 }).build();
+
+    let mut matching = filter.clone();
+    matching.insert("code_hash", hash_token(code.trim()));
+    let used = codes.find_one_and_update(matching, doc! { "$set": { "expires_at": consumed() } }, newest()).await?;
+    if used.is_none() {
+        codes.find_one_and_update(filter, doc! { "$inc": { "attempts": 1 } }, newest()).await?;
+    }
+    Ok(used)
+}
+
+/// Expiry marking a code as used; far enough in the past that no clock skew revives it.
+fn consumed() -> DateTime {
+    DateTime::from_millis(0)
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use bcrypt::hash;
+use chrono::{Duration, Utc};
+use mongodb::{bson::{doc, oid::ObjectId}, Database};
+use serde::Deserialize;
+
+use crate::audit::{self, RequestMeta};
+use crate::challenge::{self, ChallengeConfig};
+use crate::credentials;
+use crate::error::{AppError, AppResult};
+use crate::hashes;
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome, Credential, User};
+use crate::settings::{PasswordSettings, Settings};
+use crate::tokens::hash_token;
+use crate::validation::{check, FieldError, Rule, Valid, Validate};
+
+/// Purpose of the token handed out when a login needs a new password first.
+pub const CHANGE_LINK: &str = "password_change";
+const CHANGE_TTL_MINUTES: i64 = 15;
+
+/// Password reuse and expiry rules.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    /// bcrypt work factor for new hashes
+    pub bcrypt_cost: u32,
+    /// How many of the most recent passwords, including the current one, cannot be chosen again
+    pub history_size: usize,
+    /// Maximum password age per role; users get the shortest of their roles
+    pub max_age: HashMap<String, Duration>,
+}
+
+impl PasswordPolicy {
+    /// Build from the `passwords` section.
+    pub fn new(settings: &PasswordSettings) -> Self {
+        PasswordPolicy {
+            bcrypt_cost: settings.bcrypt_cost,
+            history_size: settings.history_size,
+            max_age: settings.max_age_days.iter().map(|(role, days)| (role.clone(), Duration::days(*days))).collect(),
+        }
+    }
+
+    /// When `user`'s `password` stops being accepted, if any of their roles has a maximum age.
+    pub fn expires_at(&self, user: &User, password: &Credential) -> Option<chrono::DateTime<Utc>> {
+        let max_age = user.roles.iter().filter_map(|role| self.max_age.get(role)).min()?;
+        let changed_at = chrono::DateTime::from_timestamp_millis(password.changed_at.timestamp_millis())?;
+        Some(changed_at + *max_age)
+    }
+
+    pub fn is_expired(&self, user: &User, password: &Credential) -> bool {
+        self.expires_at(user, password).is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+/// Set a new password for `user`, refusing any of the last `history_size` passwords;
+/// a reused password is reported as an error on `field`.
+pub async fn change(db: &Database, policy: &PasswordPolicy, user: &User, field: &str, new_password: &str) -> AppResult<()> {
+    let user_id = user.id.ok_or_else(|| AppError::internal("password change", "user has no id"))?;
+    let current = credentials::password(db, user_id).await?;
+    let mut history = Vec::with_capacity(policy.history_size);
+    if let Some(current) = current.clone() {
+        history.push(current.secret);
+        history.extend(current.history);
+    }
+
+    // The current hash may still be in an imported format; earlier ones are always native
+    let reused = history.iter().take(policy.history_size).enumerate().any(|(i, previous)| {
+        let scheme = if i == 0 { current.as_ref().and_then(|current| current.scheme.as_deref()) } else { None };
+        hashes::verify(scheme, previous, new_password)
+    });
+    if reused {
+        return Err(AppError::InvalidFields(vec![FieldError {
+            field: field.to_string(),
+            message: format!("must differ from your last {} passwords", policy.history_size),
+        }]));
+    }
+    history.truncate(policy.history_size.saturating_sub(1));
+
+    credentials::set_password(db, user, None, hash(new_password, policy.bcrypt_cost)?, history).await
+}
+
+/// Token that lets a signed-in user whose password expired choose a new one.
+///
+/// It names the current password hash, so it stops working once the password changes.
+pub fn change_token(jwt: &JwtConfig, password: &Credential) -> AppResult<String> {
+    let expires_at = (Utc::now() + Duration::minutes(CHANGE_TTL_MINUTES)).timestamp();
+    jwt.sign_link(CHANGE_LINK, format!("{}:{}", password.user_id.to_hex(), hash_token(&password.secret)), expires_at)
+        .map_err(|e| AppError::internal("password change token", e))
+}
+
+/// Replace an expired password using a token from `change_token`; the user then logs in again.
+pub async fn change_expired(
+    db: &Database,
+    jwt: &JwtConfig,
+    policy: &PasswordPolicy,
+    meta: &RequestMeta,
+    token: &str,
+    new_password: &str,
+) -> AppResult<()> {
+    let invalid = || AppError::Unauthenticated("Invalid or expired password change token".to_string());
+    let subject = jwt.verify_link(CHANGE_LINK, token).map_err(|_| invalid())?;
+    let (user_id, password_hash) = subject.split_once(':').ok_or_else(invalid)?;
+    let user_id = ObjectId::parse_str(user_id).map_err(|_| invalid())?;
+
+    credentials::password(db, user_id).await?
+        .filter(|password| hash_token(&password.secret) == password_hash)
+        .ok_or_else(invalid)?;
+    let user = db.collection::<User>("users")
+        .find_one(doc! { "_id": user_id, "deactivated_at": null }, None).await?
+        .ok_or_else(invalid)?;
+    change(db, policy, &user, "new_password", new_password).await?;
+
+    audit::record(db, meta, AuditEvent {
+        org_id: user.org_id,
+        actor_id: user.id,
+        actor_email: Some(user.email.clone()),
+        detail: Some("expired password replaced".to_string()),
+        ..audit::event("user.password_reset", AuditOutcome::Success)
+    }).await;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordChangeRequest {
+    pub change_token: String,
+    pub new_password: String,
+}
+
+impl Validate for PasswordChangeRequest {
+    fn validate(&mut self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        check(&mut errors, "new_password", &self.new_password, Rule::Password);
+        errors
+    }
+}
+
+/// Choose a new password after a login answered `password_change_required`; like `/register`,
+/// needs a solved challenge in `X-Challenge-Response` when challenges are configured.
+pub async fn change_expired_password(
+    db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    policy: web::Data<PasswordPolicy>,
+    challenges: web::Data<ChallengeConfig>,
+    settings: web::Data<Settings>,
+    req: HttpRequest,
+    body: Valid<PasswordChangeRequest>,
+) -> AppResult<HttpResponse> {
+    let meta = RequestMeta::from_request(&req, &settings.server);
+    challenges.require(&meta, challenge::response_from(&req).as_deref()).await?;
+    change_expired(&db, &jwt, &policy, &meta, &body.change_token, &body.new_password).await?;
+    Ok(HttpResponse::Ok().body("Password changed; log in with the new password."))
+}
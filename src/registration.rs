@@ -0,0 +1,70 @@
+use mongodb::{bson::{doc, oid::ObjectId, DateTime}, Database};
+
+use crate::error::{AppError, AppResult};
+use crate::models::models::InviteCode;
+use crate::tenant::Tenant;
+use crate::tokens::hash_token;
+
+pub const MODE_OPEN: &str = "open";
+pub const MODE_CLOSED: &str = "closed";
+pub const MODE_INVITE_CODE: &str = "invite_code";
+pub const MODE_DOMAIN: &str = "domain";
+pub const MODE_APPROVAL: &str = "approval";
+
+pub const MODES: &[&str] = &[MODE_OPEN, MODE_CLOSED, MODE_INVITE_CODE, MODE_DOMAIN, MODE_APPROVAL];
+
+/// How a self-registration was let in.
+#[derive(Debug, Default)]
+pub struct Admission {
+    /// The account has to be approved by an admin before it can log in
+    pub pending_approval: bool,
+    invite_code_id: Option<ObjectId>,
+}
+
+/// Apply the tenant's registration policy to a self-registration of `email` (already normalized).
+///
+/// An invite code is used up here; hand the admission to `release` if the account is not created after all.
+pub async fn admit(db: &Database, tenant: &Tenant, email: &str, invite_code: Option<&str>) -> AppResult<Admission> {
+    let policy = tenant.registration_policy();
+    match policy.mode.as_str() {
+        MODE_OPEN => Ok(Admission::default()),
+        MODE_APPROVAL => Ok(Admission { pending_approval: true, invite_code_id: None }),
+        MODE_DOMAIN => {
+            let domain = email.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default();
+            if policy.allowed_domains.iter().any(|allowed| allowed == domain) {
+                Ok(Admission::default())
+            } else {
+                Err(AppError::Forbidden("Registration is limited to approved email domains".to_string()))
+            }
+        }
+        MODE_INVITE_CODE => {
+            let code = invite_code.map(str::trim).filter(|code| !code.is_empty())
+                .ok_or_else(|| AppError::Forbidden("An invite code is required to register".to_string()))?;
+            let used = db.collection::<InviteCode>("invite_codes").find_one_and_update(
+                tenant.scope(doc! {
+                    "code_hash": hash_token(code),
+                    "revoked_at": null,
+                    "$expr": { "$lt": ["$uses", "$max_uses"] },
+                    "$or": [{ "expires_at": null }, { "expires_at": { "$gt": DateTime::now() } }],
+                }),
+                doc! { "$inc": { "uses": 1 } },
+                None,
+            ).await?
+            .ok_or_else(|| AppError::Forbidden("Invalid or expired invite code".to_string()))?;
+            Ok(Admission { pending_approval: false, invite_code_id: used.id })
+        }
+        _ => Err(AppError::Forbidden("Registration is closed".to_string())),
+    }
+}
+
+/// Give back the invite code use taken by `admit`.
+pub async fn release(db: &Database, admission: &Admission) {
+    let Some(code_id) = admission.invite_code_id else {
+        return;
+    };
+    if let Err(e) = db.collection::<InviteCode>("invite_codes")
+        .update_one(doc! { "_id": code_id, "uses": { "$gt": 0 } }, doc! { "$inc": { "uses": -1 } }, None).await
+    {
+        log::error!("failed to release invite code {}: {}", code_id, e);
+    }
+}
@@ -0,0 +1,64 @@
+use async_graphql::Context;
+use mongodb::{bson::{doc, oid::ObjectId}, Database};
+
+use crate::audit::{self, RequestMeta};
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, Claims, User};
+use crate::tenant::Tenant;
+
+/// Id of the authenticated user, taken from the verified token subject.
+pub fn current_user_id(ctx: &Context<'_>) -> AppResult<ObjectId> {
+    let claims = ctx
+        .data_opt::<Claims>()
+        .ok_or_else(|| AppError::Unauthenticated("Not authenticated".to_string()))?;
+
+    ObjectId::parse_str(&claims.sub).map_err(|_| AppError::Unauthenticated("Invalid token subject".to_string()))
+}
+
+/// Id of the admin using an impersonation token, if any.
+pub fn impersonator_id(ctx: &Context<'_>) -> Option<ObjectId> {
+    let actor = ctx.data_opt::<Claims>()?.act.as_ref()?;
+    ObjectId::parse_str(&actor.sub).ok()
+}
+
+/// The organization the request was resolved to.
+pub fn tenant<'a>(ctx: &Context<'a>) -> AppResult<&'a Tenant> {
+    Ok(ctx.data::<Tenant>()?)
+}
+
+/// Load the authenticated user's document.
+pub async fn current_user(ctx: &Context<'_>) -> AppResult<User> {
+    let user_id = current_user_id(ctx)?;
+    let db = ctx.data::<Database>()?;
+
+    let user = db.collection::<User>("users")
+        .find_one(tenant(ctx)?.scope(doc! { "_id": user_id }), None)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    // Access tokens issued before deactivation stay valid until they expire
+    if user.deactivated_at.is_some() {
+        return Err(AppError::Unauthenticated("Account is deactivated".to_string()));
+    }
+    Ok(user)
+}
+
+/// Record an audit event for the current GraphQL request.
+///
+/// The actor defaults to the authenticated user when the event does not name one.
+pub async fn record_audit(ctx: &Context<'_>, mut event: AuditEvent) {
+    let Ok(db) = ctx.data::<Database>() else {
+        return;
+    };
+    if event.actor_id.is_none() {
+        event.actor_id = current_user_id(ctx).ok();
+    }
+    if event.org_id.is_none() {
+        event.org_id = ctx.data_opt::<Tenant>().and_then(Tenant::org_id);
+    }
+    if event.impersonator_id.is_none() {
+        event.impersonator_id = impersonator_id(ctx);
+    }
+    let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+    audit::record(db, &meta, event).await;
+}
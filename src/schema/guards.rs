@@ -0,0 +1,74 @@
+use async_graphql::{Context, ErrorExtensions, Guard, Result};
+use chrono::Utc;
+
+use crate::{error::AppError, jwt::JwtConfig, models::models::Claims};
+
+/// Requires that the caller proved their identity within `reauth_max_age`,
+/// either at login or through the `reauthenticate` mutation.
+pub struct RecentAuth;
+
+impl Guard for RecentAuth {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let claims = ctx
+            .data_opt::<Claims>()
+            .ok_or_else(|| AppError::Unauthenticated("Not authenticated".to_string()))?;
+        let max_age = ctx.data::<JwtConfig>()?.reauth_max_age;
+
+        let age = Utc::now().timestamp() - claims.auth_time as i64;
+        if age > max_age.num_seconds() {
+            return Err(async_graphql::Error::from(AppError::Forbidden("Recent authentication required".to_string()))
+                .extend_with(|_, e| e.set("reason", "REAUTHENTICATION_REQUIRED")));
+        }
+        Ok(())
+    }
+}
+
+/// Requires the authenticated user to hold the given role.
+pub struct RoleGuard {
+    role: &'static str,
+}
+
+impl RoleGuard {
+    pub fn new(role: &'static str) -> Self {
+        RoleGuard { role }
+    }
+}
+
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        let claims = ctx
+            .data_opt::<Claims>()
+            .ok_or_else(|| AppError::Unauthenticated("Not authenticated".to_string()))?;
+
+        if !claims.roles.iter().any(|role| role == self.role) {
+            return Err(AppError::Forbidden(format!("{} role required", self.role)).into());
+        }
+        Ok(())
+    }
+}
+
+/// Rejects requests made with an impersonation token.
+pub struct NotImpersonating;
+
+impl Guard for NotImpersonating {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if ctx.data_opt::<Claims>().is_some_and(|claims| claims.act.is_some()) {
+            return Err(async_graphql::Error::from(AppError::Forbidden("Not allowed while impersonating".to_string()))
+                .extend_with(|_, e| e.set("reason", "IMPERSONATION_FORBIDDEN")));
+        }
+        Ok(())
+    }
+}
+
+/// Requires an admin of the default tenant, who manages organizations themselves.
+pub struct PlatformAdmin;
+
+impl Guard for PlatformAdmin {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        RoleGuard::new("admin").check(ctx).await?;
+        if ctx.data_opt::<Claims>().is_some_and(|claims| claims.org.is_some()) {
+            return Err(AppError::Forbidden("Platform admin required".to_string()).into());
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+mod context;
+mod guards;
 mod queries;
 mod mutations;
 
@@ -11,12 +13,28 @@ pub use mutations::*;
 #[derive(MergedObject, Default)]
 pub struct QueryRoot(
     UserQuery,
-    CmsQuery
+    CmsQuery,
+    AuditQuery,
+    OrganizationQuery,
+    MemberQuery,
+    ExportQuery,
+    WebhookQuery,
+    RegistrationQuery,
+    EmailDomainQuery,
+    CredentialQuery
 );
 
 
 #[derive(MergedObject, Default)]
 pub struct MutationRoot(
     UserMutation,
-    CMSMutation
+    CMSMutation,
+    SecurityMutation,
+    AdminMutation,
+    OrganizationMutation,
+    MemberMutation,
+    ExportMutation,
+    WebhookMutation,
+    RegistrationMutation,
+    EmailDomainMutation
 );
\ No newline at end of file
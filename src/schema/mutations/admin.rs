@@ -0,0 +1,83 @@
+use async_graphql::{Context, Object, SimpleObject};
+use mongodb::{bson::{doc, oid::ObjectId}, Database};
+
+use crate::audit;
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome, Claims, User};
+use crate::schema::context::{current_user_id, impersonator_id, record_audit, tenant};
+use crate::schema::guards::{NotImpersonating, RecentAuth, RoleGuard};
+use crate::schema::GQLUser;
+use crate::sessions;
+use super::MutationResponse;
+
+#[derive(SimpleObject)]
+pub struct ImpersonationToken {
+    pub token: String,
+    pub expires_at: i64,
+    pub user: GQLUser,
+}
+
+#[derive(Default)]
+pub struct AdminMutation;
+
+#[Object]
+impl AdminMutation {
+    /// Issue a short-lived token to see the service as `user_id` does. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating).and(RecentAuth)")]
+    async fn impersonate(&self, ctx: &Context<'_>, user_id: String) -> AppResult<ImpersonationToken> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let admin_id = current_user_id(ctx)?;
+
+        let target_oid = ObjectId::parse_str(&user_id)
+            .map_err(|_| AppError::Validation("Invalid user ID".to_string()))?;
+        if target_oid == admin_id {
+            return Err(AppError::Validation("Cannot impersonate yourself".to_string()));
+        }
+
+        let target = db.collection::<User>("users")
+            .find_one(tenant(ctx)?.scope(doc! { "_id": target_oid }), None).await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+        if target.roles.iter().any(|role| role == "admin") {
+            return Err(AppError::Forbidden("Admins cannot be impersonated".to_string()));
+        }
+
+        let claims = jwt.impersonation_claims_for(&target, target_oid.to_hex(), admin_id.to_hex());
+        let token = jwt.encode(&claims)?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(target_oid.to_hex()),
+            detail: Some(format!("token {}", claims.jti)),
+            ..audit::event("admin.impersonation_started", AuditOutcome::Success)
+        }).await;
+
+        Ok(ImpersonationToken {
+            token,
+            expires_at: claims.exp as i64,
+            user: GQLUser::from(target),
+        })
+    }
+
+    /// End an impersonation session; the token stops working right away.
+    async fn stop_impersonation(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let admin_id = impersonator_id(ctx)
+            .ok_or_else(|| AppError::Validation("Not impersonating".to_string()))?;
+        let claims = ctx.data::<Claims>()?;
+        sessions::revoke_token(db, claims).await?;
+
+        record_audit(ctx, AuditEvent {
+            actor_id: Some(admin_id),
+            impersonator_id: Some(admin_id),
+            target: Some(claims.sub.clone()),
+            detail: Some(format!("token {}", claims.jti)),
+            ..audit::event("admin.impersonation_stopped", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Impersonation ended".to_string(),
+        })
+    }
+}
@@ -1,8 +1,13 @@
 use std::time::SystemTime;
-use async_graphql::{Context, InputObject, Object, Result, SimpleObject, ID};
+use async_graphql::{Context, InputObject, Object, SimpleObject, ID};
 use mongodb::{bson::{doc, oid::ObjectId, Bson, DateTime as BsonDateTime}, Database};
 use chrono::Utc;
-use crate::models::models::{Post, User};
+use crate::audit;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome, Post, User};
+use crate::schema::context::{record_audit, tenant};
+use crate::validation::{trim, trim_optional, Field, DESC_MAX, TITLE_MAX};
+use crate::webhooks;
 
 #[derive(SimpleObject)]
 pub struct CmsResponse {
@@ -12,17 +17,23 @@ pub struct CmsResponse {
 
 #[derive(InputObject)]
 struct PostInput {
+    #[graphql(process_with = "trim", validator(custom = "Field::length(\"title\", 1, TITLE_MAX)"))]
     pub title: String,
+    #[graphql(process_with = "trim", validator(custom = "Field::url(\"thumbnail\")"))]
     pub thumbnail: String,
     pub author_id: String,
+    #[graphql(process_with = "trim", validator(custom = "Field::length(\"desc\", 1, DESC_MAX)"))]
     pub desc: String,
 }
 
 #[derive(InputObject)]
 struct PostUpdateInput {
+    #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"title\", 1, TITLE_MAX)"))]
     pub title: Option<String>,
+    #[graphql(process_with = "trim_optional", validator(custom = "Field::url(\"thumbnail\")"))]
     pub thumbnail: Option<String>,
     pub author_id: Option<String>,
+    #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"desc\", 1, DESC_MAX)"))]
     pub desc: Option<String>,
 }
 
@@ -31,42 +42,50 @@ pub struct CMSMutation;
 
 #[Object]
 impl CMSMutation {
-    async fn create_post(&self, ctx: &Context<'_>, input: PostInput) -> Result<CmsResponse> {
+    async fn create_post(&self, ctx: &Context<'_>, input: PostInput) -> AppResult<CmsResponse> {
         let db = ctx.data::<Database>()?;
         let user_collection = db.collection::<User>("users");
         let post_collection = db.collection::<Post>("posts");
 
         let author_oid = ObjectId::parse_str(&input.author_id)
-            .map_err(|_| async_graphql::Error::new("Invalid author ID"))?;
+            .map_err(|_| AppError::Validation("Invalid author ID".to_string()))?;
 
+        let tenant = tenant(ctx)?;
         let author_exist = user_collection
-            .find_one(doc! {"_id": &author_oid}, None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?
+            .find_one(tenant.scope(doc! {"_id": &author_oid}), None)
+            .await?
             .is_some();
 
         if !author_exist {
-            return Err(async_graphql::Error::new("Author not found"));
+            return Err(AppError::NotFound("Author not found".to_string()));
         }
 
         let created_at = Utc::now(); // Get chrono::DateTime<Utc>
         let system_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(created_at.timestamp() as u64); // Convert to SystemTime
         let bson_datetime = BsonDateTime::from_system_time(system_time); // Convert to BsonDateTime
 
-        let post = Post {
+        let mut post = Post {
             id: None,
+            org_id: tenant.org_id(),
             title: input.title,
             thumbnail: input.thumbnail,
-            author: author_oid,
+            author: Some(author_oid),
             desc: input.desc,
             created_at: Some(bson_datetime), // Use converted created_at
             updated_at: None,
         };
 
-        post_collection
+        let insert_res = post_collection
             .insert_one(post.clone(), None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+            .await?;
+
+        post.id = insert_res.inserted_id.as_object_id();
+
+        record_audit(ctx, AuditEvent {
+            target: post.id.map(|oid| oid.to_hex()),
+            ..audit::event("post.create", AuditOutcome::Success)
+        }).await;
+        webhooks::emit(db, post.org_id, "post.created", webhooks::post_data(&post)).await;
 
         Ok(CmsResponse {
             success: true,
@@ -74,7 +93,7 @@ impl CMSMutation {
         })
     }
 
-    async fn update_post(&self, ctx: &Context<'_>, id: ID, input: PostUpdateInput) -> Result<CmsResponse> {
+    async fn update_post(&self, ctx: &Context<'_>, id: ID, input: PostUpdateInput) -> AppResult<CmsResponse> {
         let db = ctx.data::<Database>()?;
         let post_collection = db.collection::<Post>("posts");
 
@@ -83,8 +102,8 @@ impl CMSMutation {
         let system_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(now.timestamp() as u64);
         let bson_datetime = BsonDateTime::from_system_time(system_time);
 
-        let post_oid = ObjectId::parse_str(&id.as_str())
-            .map_err(|_| async_graphql::Error::new("Invalid post ID"))?;
+        let post_oid = ObjectId::parse_str(id.as_str())
+            .map_err(|_| AppError::Validation("Invalid post ID".to_string()))?;
 
         let mut update_doc = doc! {};
         if let Some(title) = &input.title {
@@ -97,45 +116,65 @@ impl CMSMutation {
             update_doc.insert("thumbnail", thumbnail);
         }
         if let Some(author_id) = &input.author_id {
-            let author_oid = ObjectId::parse_str(&author_id)
-                .map_err(|_| async_graphql::Error::new("Invalid author ID"))?;
-            update_doc.insert("author", author_oid);
+            let author_oid = ObjectId::parse_str(author_id)
+                .map_err(|_| AppError::Validation("Invalid author ID".to_string()))?;
+            let author_exists = db.collection::<User>("users")
+                .find_one(tenant(ctx)?.scope(doc! {"_id": &author_oid}), None)
+                .await?
+                .is_some();
+            if !author_exists {
+                return Err(AppError::NotFound("Author not found".to_string()));
+            }
+            update_doc.insert("author_id", author_oid);
         }
 
-        // Always update `updated_at`
-        update_doc.insert("updated_at", Bson::DateTime(bson_datetime));
-
         if update_doc.is_empty() {
-            return Err(async_graphql::Error::new("No data to update"));
+            return Err(AppError::Validation("No data to update".to_string()));
         }
 
-        let update_res = post_collection
-            .update_one(doc! {"_id": &post_oid}, doc! {"$set": update_doc}, None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        // Always update `updated_at`
+        update_doc.insert("updated_at", Bson::DateTime(bson_datetime));
 
-        if update_res.modified_count > 0 {
+        let update_res = post_collection
+            .update_one(tenant(ctx)?.scope(doc! {"_id": &post_oid}), doc! {"$set": update_doc}, None)
+            .await?;
+
+        if update_res.matched_count > 0 {
+            record_audit(ctx, AuditEvent {
+                target: Some(post_oid.to_hex()),
+                ..audit::event("post.update", AuditOutcome::Success)
+            }).await;
+            if let Some(post) = post_collection.find_one(doc! {"_id": &post_oid}, None).await? {
+                webhooks::emit(db, post.org_id, "post.updated", webhooks::post_data(&post)).await;
+            }
             Ok(CmsResponse {
                 success: true,
                 message: "Post updated successfully".to_string(),
             })
         } else {
-            Err(async_graphql::Error::new("Failed to update post"))
+            Err(AppError::NotFound("Post not found".to_string()))
         }
     }
 
-    async fn remove_post(&self, ctx: &Context<'_>, id: ID) -> Result<bool> {
+    async fn remove_post(&self, ctx: &Context<'_>, id: ID) -> AppResult<bool> {
         let db = ctx.data::<Database>()?;
         let post_collection = db.collection::<Post>("posts");
 
-        let post_oid = ObjectId::parse_str(&id.as_str())
-            .map_err(|_| async_graphql::Error::new("Invalid post ID"))?;
+        let post_oid = ObjectId::parse_str(id.as_str())
+            .map_err(|_| AppError::Validation("Invalid post ID".to_string()))?;
 
-        let delete_res = post_collection
-            .delete_one(doc! {"_id": post_oid}, None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        let removed = post_collection
+            .find_one_and_delete(tenant(ctx)?.scope(doc! {"_id": &post_oid}), None)
+            .await?;
+
+        if let Some(post) = &removed {
+            record_audit(ctx, AuditEvent {
+                target: Some(post_oid.to_hex()),
+                ..audit::event("post.remove", AuditOutcome::Success)
+            }).await;
+            webhooks::emit(db, post.org_id, "post.removed", webhooks::post_data(post)).await;
+        }
 
-        Ok(delete_res.deleted_count > 0)
+        Ok(removed.is_some())
     }
 }
@@ -0,0 +1,85 @@
+use async_graphql::{Context, Object};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Database,
+};
+
+use crate::audit;
+use crate::email_domains::normalize_domain;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome, EmailDomainRule};
+use crate::schema::context::{current_user_id, record_audit, tenant};
+use crate::schema::guards::{NotImpersonating, RoleGuard};
+use crate::schema::mutations::MutationResponse;
+use crate::schema::queries::{DomainRuleAction, GQLEmailDomainRule};
+use crate::validation::{trim_optional, Field, FieldError};
+
+#[derive(Default)]
+pub struct EmailDomainMutation;
+
+#[Object]
+impl EmailDomainMutation {
+    /// Allow or deny an email domain and its subdomains for registration and
+    /// email changes, replacing any earlier rule for it. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn set_email_domain_rule(
+        &self,
+        ctx: &Context<'_>,
+        domain: String,
+        action: DomainRuleAction,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"note\", 1, 500)"))]
+        note: Option<String>,
+    ) -> AppResult<GQLEmailDomainRule> {
+        let db = ctx.data::<Database>()?;
+        let tenant = tenant(ctx)?;
+        let domain = normalize_domain(&domain)
+            .map_err(|message| AppError::InvalidFields(vec![FieldError { field: "domain".to_string(), message }]))?;
+
+        let rule = db.collection::<EmailDomainRule>("email_domain_rules")
+            .find_one_and_update(
+                tenant.scope(doc! { "domain": &domain }),
+                doc! {
+                    "$set": { "action": action.as_str(), "note": note },
+                    "$setOnInsert": { "created_by": current_user_id(ctx)?, "created_at": DateTime::now() },
+                },
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            ).await?
+            .ok_or_else(|| AppError::internal("email domain rule", "upsert returned no document"))?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(domain),
+            detail: Some(action.as_str().to_string()),
+            ..audit::event("email_domain.set", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLEmailDomainRule::from(rule))
+    }
+
+    /// Drop the rule for a domain so the default screening applies again. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn remove_email_domain_rule(&self, ctx: &Context<'_>, domain: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let domain = normalize_domain(&domain)
+            .map_err(|message| AppError::InvalidFields(vec![FieldError { field: "domain".to_string(), message }]))?;
+
+        let result = db.collection::<EmailDomainRule>("email_domain_rules")
+            .delete_one(tenant(ctx)?.scope(doc! { "domain": &domain }), None).await?;
+        if result.deleted_count == 0 {
+            return Err(AppError::NotFound("No rule for this domain".to_string()));
+        }
+
+        record_audit(ctx, AuditEvent {
+            target: Some(domain),
+            ..audit::event("email_domain.remove", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Domain rule removed".to_string(),
+        })
+    }
+}
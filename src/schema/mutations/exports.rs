@@ -0,0 +1,40 @@
+use async_graphql::{Context, Object};
+use mongodb::Database;
+
+use crate::audit;
+use crate::error::{AppError, AppResult};
+use crate::exports;
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome};
+use crate::schema::context::{current_user, record_audit};
+use crate::schema::guards::{NotImpersonating, RecentAuth};
+use crate::schema::queries::GQLDataExport;
+use crate::settings::Settings;
+
+#[derive(Default)]
+pub struct ExportMutation;
+
+#[Object]
+impl ExportMutation {
+    /// Start assembling a copy of everything stored about the current user.
+    ///
+    /// The archive is built in the background; `dataExports` returns a download link once it is ready.
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn request_data_export(&self, ctx: &Context<'_>, #[graphql(default = false)] zip: bool) -> AppResult<GQLDataExport> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let settings = ctx.data::<Settings>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        let export = exports::start(db, user_id, user.org_id, zip).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: export.id.map(|id| id.to_hex()),
+            detail: Some(export.format.clone()),
+            ..audit::event("user.data_export_requested", AuditOutcome::Success)
+        }).await;
+
+        GQLDataExport::new(export, jwt, &settings.server)
+    }
+}
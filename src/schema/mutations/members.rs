@@ -0,0 +1,310 @@
+use std::sync::Arc;
+use async_graphql::{Context, Object, SimpleObject};
+use chrono::{Duration, Utc};
+use mongodb::{bson::{doc, oid::ObjectId, DateTime}, Database};
+
+use crate::accounts::{self, AccountConfig};
+use crate::audit::{self, RequestMeta};
+use crate::auth::{self, LoginOutcome};
+use crate::challenge::ChallengeConfig;
+use crate::email::normalize_email;
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::mailer::{app_url, Mailer};
+use crate::models::models::{AuditEvent, AuditOutcome, AuthUser, Invitation, User};
+use crate::passwords::PasswordPolicy;
+use crate::schema::context::{current_user_id, record_audit, tenant};
+use crate::schema::guards::{NotImpersonating, RecentAuth, RoleGuard};
+use crate::schema::mutations::{AuthPayload, MutationResponse};
+use crate::schema::mutations::users::login_payload;
+use crate::schema::queries::{GQLInvitation, MemberRole};
+use crate::sessions;
+use crate::settings::Settings;
+use crate::sms::SmsConfig;
+use crate::validation::{trim, trim_optional, Field, NAME_MAX};
+
+const INVITATION_TTL_DAYS: i64 = 7;
+const INVITATION_LINK: &str = "invitation";
+
+/// A new invitation with the signed link that was emailed to the invitee.
+#[derive(SimpleObject)]
+pub struct InvitationCreated {
+    pub invitation: GQLInvitation,
+    pub link: String,
+}
+
+#[derive(Default)]
+pub struct MemberMutation;
+
+#[Object]
+impl MemberMutation {
+    /// Invite someone to the current organization by email. Admin only.
+    ///
+    /// Any earlier pending invitation for the same address is revoked.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn invite_member(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(process_with = "trim", validator(custom = "Field::email(\"email\")"))]
+        email: String,
+        role: MemberRole,
+    ) -> AppResult<InvitationCreated> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let settings = ctx.data::<Settings>()?;
+        let tenant = tenant(ctx)?;
+        let email = normalize_email(&email).map_err(AppError::Validation)?;
+        let invitations = db.collection::<Invitation>("invitations");
+
+        let already_member = db.collection::<User>("users")
+            .find_one(tenant.scope(doc! { "email": &email }), None).await?
+            .is_some();
+        if already_member {
+            return Err(AppError::Conflict("Already a member of this organization".to_string()));
+        }
+
+        invitations.update_many(
+            tenant.scope(doc! { "email": &email, "accepted_at": null, "revoked_at": null }),
+            doc! { "$set": { "revoked_at": DateTime::now() } },
+            None,
+        ).await?;
+
+        let expires_at = Utc::now() + Duration::days(INVITATION_TTL_DAYS);
+        let mut invitation = Invitation {
+            id: None,
+            org_id: tenant.org_id(),
+            email: email.clone(),
+            role: role.as_str().to_string(),
+            invited_by: current_user_id(ctx)?,
+            created_at: DateTime::now(),
+            expires_at: DateTime::from_millis(expires_at.timestamp_millis()),
+            accepted_at: None,
+            revoked_at: None,
+        };
+        let invitation_id = invitations.insert_one(&invitation, None).await?
+            .inserted_id.as_object_id()
+            .ok_or_else(|| AppError::internal("invite member", "insert returned no object id"))?;
+        invitation.id = Some(invitation_id);
+
+        let token = jwt.sign_link(INVITATION_LINK, invitation_id.to_hex(), expires_at.timestamp())?;
+        let link = app_url(&settings.server, &format!("/invitations/accept?token={}", token));
+        let org_name = tenant.org.as_ref().map(|org| org.name.as_str()).unwrap_or("our service");
+        mailer.send(
+            &email,
+            &format!("You have been invited to join {}", org_name),
+            &format!("Accept the invitation within {} days by opening this link:\n{}", INVITATION_TTL_DAYS, link),
+        ).await
+        .map_err(|e| AppError::internal("invitation email", e))?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(email),
+            detail: Some(role.as_str().to_string()),
+            ..audit::event("member.invited", AuditOutcome::Success)
+        }).await;
+
+        Ok(InvitationCreated {
+            invitation: GQLInvitation::from(invitation),
+            link,
+        })
+    }
+
+    /// Withdraw a pending invitation. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn revoke_invitation(&self, ctx: &Context<'_>, id: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let invitation_id = ObjectId::parse_str(&id)
+            .map_err(|_| AppError::Validation("Invalid invitation ID".to_string()))?;
+
+        let result = db.collection::<Invitation>("invitations").update_one(
+            tenant(ctx)?.scope(doc! { "_id": invitation_id, "accepted_at": null, "revoked_at": null }),
+            doc! { "$set": { "revoked_at": DateTime::now() } },
+            None,
+        ).await?;
+        if result.matched_count == 0 {
+            return Err(AppError::NotFound("Invitation not found".to_string()));
+        }
+
+        record_audit(ctx, AuditEvent {
+            target: Some(invitation_id.to_hex()),
+            ..audit::event("member.invitation_revoked", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Invitation revoked".to_string(),
+        })
+    }
+
+    /// Join the organization named by an invitation link.
+    ///
+    /// Accounts belong to a single organization, so this creates an account in it,
+    /// unless the address has registered here since being invited. That account is
+    /// signed in with its password, as by `login`, and given the invited role. The
+    /// invited role only ever raises an existing account's role; `changeMemberRole`
+    /// lowers it. Repeated wrong passwords require a solved `botChallenge`.
+    async fn accept_invitation(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        #[graphql(validator(custom = "Field::password(\"password\")"))]
+        password: String,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"fullName\", 1, NAME_MAX)"))]
+        full_name: Option<String>,
+        bot_challenge: Option<String>,
+    ) -> async_graphql::Result<AuthPayload> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let tenant = tenant(ctx)?;
+        let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+        let invitations = db.collection::<Invitation>("invitations");
+        let users = db.collection::<User>("users");
+        let invalid = || AppError::Validation("Invalid or expired invitation".to_string());
+
+        let invitation_id = jwt.verify_link(INVITATION_LINK, &token).ok()
+            .and_then(|id| ObjectId::parse_str(id).ok())
+            .ok_or_else(invalid)?;
+        let pending = tenant.scope(doc! {
+            "_id": invitation_id,
+            "accepted_at": null,
+            "revoked_at": null,
+            "expires_at": { "$gt": DateTime::now() },
+        });
+        let invitation = invitations.find_one(pending.clone(), None).await?.ok_or_else(invalid)?;
+        let role = MemberRole::parse(&invitation.role);
+        let joined = |user: &User, detail: &str| AuditEvent {
+            actor_id: user.id,
+            actor_email: Some(user.email.clone()),
+            target: Some(invitation_id.to_hex()),
+            detail: Some(detail.to_string()),
+            ..audit::event("member.joined", AuditOutcome::Success)
+        };
+
+        // Someone registered with the address after being invited
+        if let Some(existing) = users.find_one(tenant.scope(doc! { "email": &invitation.email }), None).await? {
+            ctx.data::<ChallengeConfig>()?
+                .require_for_login(db, tenant, &meta, &invitation.email, bot_challenge.as_deref()).await?;
+            let sms = ctx.data::<SmsConfig>()?;
+            let mut outcome = auth::password_login(
+                db, tenant, sms, ctx.data::<PasswordPolicy>()?, &meta, &invitation.email, &password,
+            ).await?;
+
+            invitations.find_one_and_update(pending, doc! { "$set": { "accepted_at": DateTime::now() } }, None).await?
+                .ok_or_else(invalid)?;
+            if role == MemberRole::Admin {
+                let mut roles = existing.roles.clone();
+                role.apply(&mut roles);
+                users.update_one(doc! { "_id": existing.id }, doc! { "$set": { "roles": &roles } }, None).await?;
+                if let LoginOutcome::Authenticated(user) = &mut outcome {
+                    user.roles = roles;
+                }
+            }
+
+            record_audit(ctx, joined(&existing, &format!("existing account, {}", role.as_str()))).await;
+            return login_payload(db, jwt, &meta, outcome, &["pwd"]).await;
+        }
+
+        // Claim the invitation before acting on it so it can only be used once
+        let accepted_at = DateTime::now();
+        invitations.find_one_and_update(pending, doc! { "$set": { "accepted_at": accepted_at } }, None).await?
+            .ok_or_else(invalid)?;
+
+        let input = AuthUser {
+            email: invitation.email.clone(),
+            password,
+            full_name,
+            phone_number: None,
+            invite_code: None,
+        };
+        // The invitation stands in for the registration policy
+        let user = match auth::register(db, tenant, ctx.data::<PasswordPolicy>()?, &meta, &input, vec![role.as_str().to_string()], false).await {
+            Ok(user) => user,
+            Err(e) => {
+                release(db, invitation_id, accepted_at).await;
+                return Err(e.into());
+            }
+        };
+
+        record_audit(ctx, joined(&user, role.as_str())).await;
+
+        let tokens = auth::issue_tokens(db, jwt, &meta, &user, &["pwd"]).await?;
+        Ok(AuthPayload::new(tokens, user))
+    }
+
+    /// Change another member's role and sign them out everywhere. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating).and(RecentAuth)")]
+    async fn change_member_role(&self, ctx: &Context<'_>, user_id: String, role: MemberRole) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let users = db.collection::<User>("users");
+        let member_id = other_member_id(ctx, &user_id)?;
+
+        let mut member = users.find_one(tenant(ctx)?.scope(doc! { "_id": member_id }), None).await?
+            .ok_or_else(|| AppError::NotFound("Member not found".to_string()))?;
+        role.apply(&mut member.roles);
+        users.update_one(doc! { "_id": member_id }, doc! { "$set": { "roles": &member.roles } }, None).await?;
+
+        // Outstanding access tokens still carry the old roles; this also rejects them
+        sessions::revoke_all(db, member_id).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(member_id.to_hex()),
+            detail: Some(role.as_str().to_string()),
+            ..audit::event("member.role_changed", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Role updated".to_string(),
+        })
+    }
+
+    /// Remove another member from the organization. Admin only.
+    ///
+    /// Accounts belong to a single organization, so the account is deactivated and
+    /// signed out everywhere, then purged with its credentials and data exports once
+    /// the grace period ends; its posts follow the post deletion policy. The member
+    /// cannot restore it, but an operator can `unlock` it until then.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating).and(RecentAuth)")]
+    async fn remove_member(&self, ctx: &Context<'_>, user_id: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let config = ctx.data::<AccountConfig>()?;
+        let member_id = other_member_id(ctx, &user_id)?;
+
+        db.collection::<User>("users")
+            .find_one(tenant(ctx)?.scope(doc! { "_id": member_id }), None).await?
+            .ok_or_else(|| AppError::NotFound("Member not found".to_string()))?;
+        let purge_after = accounts::remove(db, config, member_id, current_user_id(ctx)?).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(member_id.to_hex()),
+            detail: purge_after.try_to_rfc3339_string().ok(),
+            ..audit::event("member.removed", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Member removed".to_string(),
+        })
+    }
+}
+
+/// Give back an invitation claimed at `accepted_at` whose account could not be created.
+async fn release(db: &Database, invitation_id: ObjectId, accepted_at: DateTime) {
+    if let Err(e) = db.collection::<Invitation>("invitations").update_one(
+        doc! { "_id": invitation_id, "accepted_at": accepted_at },
+        doc! { "$set": { "accepted_at": null } },
+        None,
+    ).await {
+        log::error!("failed to release invitation {}: {}", invitation_id, e);
+    }
+}
+
+/// Parse a member id, refusing the caller's own so admins cannot lock themselves out.
+fn other_member_id(ctx: &Context<'_>, user_id: &str) -> AppResult<ObjectId> {
+    let member_id = ObjectId::parse_str(user_id)
+        .map_err(|_| AppError::Validation("Invalid user ID".to_string()))?;
+    if member_id == current_user_id(ctx)? {
+        return Err(AppError::Validation("Cannot change your own membership".to_string()));
+    }
+    Ok(member_id)
+}
@@ -1,5 +1,21 @@
 mod users;
 mod cms;
+mod security;
+mod admin;
+mod organizations;
+mod members;
+mod exports;
+mod webhooks;
+mod registrations;
+mod email_domains;
 
 pub use users::*;
-pub use cms::*; 
\ No newline at end of file
+pub use cms::*;
+pub use security::*;
+pub use admin::*;
+pub use organizations::*;
+pub use members::*;
+pub use exports::*;
+pub use webhooks::*;
+pub use registrations::*;
+pub use email_domains::*;
\ No newline at end of file
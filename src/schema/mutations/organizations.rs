@@ -0,0 +1,198 @@
+use async_graphql::{Context, Object};
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Database,
+};
+
+use crate::audit;
+use crate::cors::{CorsPolicy, OriginPattern};
+use crate::db::is_duplicate_key;
+use crate::email_domains::normalize_domain;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome, Organization, OrganizationSettings, RegistrationPolicy};
+use crate::schema::context::{record_audit, tenant};
+use crate::schema::guards::{PlatformAdmin, RoleGuard};
+use crate::schema::queries::{GQLOrganization, LoginMethod, RegistrationMode};
+use crate::validation::{trim, Field, FieldError, NAME_MAX};
+
+#[derive(Default)]
+pub struct OrganizationMutation;
+
+#[Object]
+impl OrganizationMutation {
+    /// Add a tenant reachable at `<slug>.TENANT_BASE_DOMAIN` or with `X-Tenant: <slug>`.
+    #[graphql(guard = "PlatformAdmin")]
+    async fn create_organization(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(process_with = "trim", validator(custom = "Field::slug(\"slug\")"))]
+        slug: String,
+        #[graphql(process_with = "trim", validator(custom = "Field::length(\"name\", 1, NAME_MAX)"))]
+        name: String,
+    ) -> AppResult<GQLOrganization> {
+        let db = ctx.data::<Database>()?;
+
+        let mut org = Organization {
+            id: None,
+            slug,
+            name,
+            settings: OrganizationSettings::default(),
+            created_at: DateTime::now(),
+        };
+        let result = db.collection::<Organization>("organizations")
+            .insert_one(&org, None).await
+            .map_err(|e| if is_duplicate_key(&e) {
+                AppError::Conflict("Slug is already taken".to_string())
+            } else {
+                AppError::from(e)
+            })?;
+        org.id = result.inserted_id.as_object_id();
+
+        record_audit(ctx, AuditEvent {
+            target: org.id.map(|id| id.to_hex()),
+            detail: Some(org.slug.clone()),
+            ..audit::event("org.create", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLOrganization::from(org))
+    }
+
+    /// Choose how members of the current organization may sign in. Org admins only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn update_login_methods(
+        &self,
+        ctx: &Context<'_>,
+        allowed_login_methods: Vec<LoginMethod>,
+    ) -> AppResult<GQLOrganization> {
+        let db = ctx.data::<Database>()?;
+        let org_id = tenant(ctx)?.org_id()
+            .ok_or_else(|| AppError::Validation("No organization selected".to_string()))?;
+        if allowed_login_methods.is_empty() {
+            return Err(AppError::Validation("At least one login method is required".to_string()));
+        }
+
+        let mut methods: Vec<&str> = allowed_login_methods.iter().map(|method| method.as_str()).collect();
+        methods.sort();
+        methods.dedup();
+        let org = db.collection::<Organization>("organizations")
+            .find_one_and_update(
+                doc! { "_id": org_id },
+                doc! { "$set": { "settings.allowed_login_methods": &methods } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            ).await?
+            .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(org_id.to_hex()),
+            detail: Some(methods.join(",")),
+            ..audit::event("org.login_methods_update", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLOrganization::from(org))
+    }
+
+    /// Set who may register in the current organization. Org admins only.
+    ///
+    /// `allowedEmailDomains` is only consulted in `DOMAIN_ALLOWLIST` mode.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn update_registration_policy(
+        &self,
+        ctx: &Context<'_>,
+        mode: RegistrationMode,
+        #[graphql(default)]
+        allowed_email_domains: Vec<String>,
+    ) -> AppResult<GQLOrganization> {
+        let db = ctx.data::<Database>()?;
+        let org_id = tenant(ctx)?.org_id()
+            .ok_or_else(|| AppError::Validation("No organization selected".to_string()))?;
+
+        let mut domains = Vec::new();
+        let mut errors = Vec::new();
+        for domain in &allowed_email_domains {
+            match normalize_domain(domain) {
+                Ok(domain) => domains.push(domain),
+                Err(message) => errors.push(FieldError { field: "allowedEmailDomains".to_string(), message }),
+            }
+        }
+        if mode == RegistrationMode::DomainAllowlist && domains.is_empty() && errors.is_empty() {
+            errors.push(FieldError {
+                field: "allowedEmailDomains".to_string(),
+                message: "must name at least one domain".to_string(),
+            });
+        }
+        if !errors.is_empty() {
+            return Err(AppError::InvalidFields(errors));
+        }
+        domains.sort();
+        domains.dedup();
+
+        let policy = RegistrationPolicy { mode: mode.as_str().to_string(), allowed_domains: domains };
+        let policy = mongodb::bson::to_bson(&policy).map_err(|e| AppError::internal("registration policy", e))?;
+        let org = db.collection::<Organization>("organizations")
+            .find_one_and_update(
+                doc! { "_id": org_id },
+                doc! { "$set": { "settings.registration": policy } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            ).await?
+            .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(org_id.to_hex()),
+            detail: Some(mode.as_str().to_string()),
+            ..audit::event("org.registration_policy_update", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLOrganization::from(org))
+    }
+
+    /// Set the browser origins, such as `https://app.example.com` or `https://*.example.com`,
+    /// that may call the current organization from its subdomain. Org admins only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn update_allowed_origins(
+        &self,
+        ctx: &Context<'_>,
+        allowed_origins: Vec<String>,
+    ) -> AppResult<GQLOrganization> {
+        let db = ctx.data::<Database>()?;
+        let org_id = tenant(ctx)?.org_id()
+            .ok_or_else(|| AppError::Validation("No organization selected".to_string()))?;
+
+        let errors: Vec<FieldError> = allowed_origins.iter()
+            .filter_map(|origin| OriginPattern::parse(origin).err())
+            .map(|message| FieldError { field: "allowedOrigins".to_string(), message })
+            .collect();
+        if !errors.is_empty() {
+            return Err(AppError::InvalidFields(errors));
+        }
+        let mut origins: Vec<&str> = allowed_origins.iter().map(|origin| origin.trim()).collect();
+        origins.sort();
+        origins.dedup();
+
+        let org = db.collection::<Organization>("organizations")
+            .find_one_and_update(
+                doc! { "_id": org_id },
+                doc! { "$set": { "settings.allowed_origins": &origins } },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            ).await?
+            .ok_or_else(|| AppError::NotFound("Organization not found".to_string()))?;
+        // Other instances pick the change up on their next refresh
+        if let Err(e) = ctx.data::<CorsPolicy>()?.refresh(db).await {
+            log::error!("failed to reload organization CORS origins: {}", e);
+        }
+
+        record_audit(ctx, AuditEvent {
+            target: Some(org_id.to_hex()),
+            detail: Some(origins.join(",")),
+            ..audit::event("org.allowed_origins_update", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLOrganization::from(org))
+    }
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use async_graphql::{Context, Object, SimpleObject};
+use chrono::{Duration, Utc};
+use mongodb::{bson::{doc, oid::ObjectId, DateTime}, Database};
+
+use crate::audit;
+use crate::credentials;
+use crate::error::{AppError, AppResult};
+use crate::mailer::Mailer;
+use crate::models::models::{AuditEvent, AuditOutcome, InviteCode, User};
+use crate::schema::context::{current_user_id, record_audit, tenant};
+use crate::schema::guards::{NotImpersonating, RoleGuard};
+use crate::schema::mutations::MutationResponse;
+use crate::schema::queries::GQLInviteCode;
+use crate::tokens::{generate_token, hash_token};
+use crate::validation::{trim_optional, Field};
+
+/// A new invite code; share `code` with the people who may register.
+#[derive(SimpleObject)]
+pub struct InviteCodeCreated {
+    pub invite_code: GQLInviteCode,
+    pub code: String,
+}
+
+#[derive(Default)]
+pub struct RegistrationMutation;
+
+#[Object]
+impl RegistrationMutation {
+    /// Let a pending registration sign in. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn approve_registration(&self, ctx: &Context<'_>, user_id: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let user = pending_user(ctx, &user_id).await?;
+
+        db.collection::<User>("users").update_one(
+            doc! { "_id": user.id, "pending_approval": true },
+            doc! { "$set": { "pending_approval": false } },
+            None,
+        ).await?;
+
+        if let Err(e) = mailer.send(
+            &user.email,
+            "Your account has been approved",
+            "An administrator approved your registration. You can now log in.",
+        ).await {
+            log::warn!("failed to notify {} of approval: {}", user.email, e);
+        }
+
+        record_audit(ctx, AuditEvent {
+            target: user.id.map(|id| id.to_hex()),
+            ..audit::event("user.registration_approved", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Registration approved".to_string(),
+        })
+    }
+
+    /// Turn down a pending registration and delete the account. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn reject_registration(
+        &self,
+        ctx: &Context<'_>,
+        user_id: String,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"reason\", 1, 500)"))]
+        reason: Option<String>,
+    ) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let user = pending_user(ctx, &user_id).await?;
+
+        let deleted = db.collection::<User>("users")
+            .delete_one(doc! { "_id": user.id, "pending_approval": true }, None).await?;
+        if let (1, Some(user_id)) = (deleted.deleted_count, user.id) {
+            credentials::remove_all(db, user_id).await?;
+        }
+
+        let mut body = "Your registration was not approved.".to_string();
+        if let Some(reason) = &reason {
+            body.push_str(&format!("\n\nReason: {}", reason));
+        }
+        if let Err(e) = mailer.send(&user.email, "Your registration was not approved", &body).await {
+            log::warn!("failed to notify {} of rejection: {}", user.email, e);
+        }
+
+        record_audit(ctx, AuditEvent {
+            target: user.id.map(|id| id.to_hex()),
+            detail: reason,
+            ..audit::event("user.registration_rejected", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Registration rejected".to_string(),
+        })
+    }
+
+    /// Create a code that lets up to `maxUses` people register while registration is invite-only. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn create_invite_code(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 1, validator(minimum = 1, maximum = 10000))]
+        max_uses: u32,
+        #[graphql(validator(minimum = 1, maximum = 365))]
+        expires_in_days: Option<i64>,
+    ) -> AppResult<InviteCodeCreated> {
+        let db = ctx.data::<Database>()?;
+        let code = generate_token();
+
+        let mut invite_code = InviteCode {
+            id: None,
+            org_id: tenant(ctx)?.org_id(),
+            code_hash: hash_token(&code),
+            created_by: current_user_id(ctx)?,
+            max_uses,
+            uses: 0,
+            expires_at: expires_in_days
+                .map(|days| DateTime::from_millis((Utc::now() + Duration::days(days)).timestamp_millis())),
+            created_at: DateTime::now(),
+            revoked_at: None,
+        };
+        invite_code.id = db.collection::<InviteCode>("invite_codes")
+            .insert_one(&invite_code, None).await?
+            .inserted_id.as_object_id();
+
+        record_audit(ctx, AuditEvent {
+            target: invite_code.id.map(|id| id.to_hex()),
+            detail: Some(format!("max uses {}", max_uses)),
+            ..audit::event("invite_code.create", AuditOutcome::Success)
+        }).await;
+
+        Ok(InviteCodeCreated {
+            invite_code: GQLInviteCode::from(invite_code),
+            code,
+        })
+    }
+
+    /// Stop an invite code from being used. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn revoke_invite_code(&self, ctx: &Context<'_>, id: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let code_id = ObjectId::parse_str(&id)
+            .map_err(|_| AppError::Validation("Invalid invite code ID".to_string()))?;
+
+        let result = db.collection::<InviteCode>("invite_codes").update_one(
+            tenant(ctx)?.scope(doc! { "_id": code_id, "revoked_at": null }),
+            doc! { "$set": { "revoked_at": DateTime::now() } },
+            None,
+        ).await?;
+        if result.matched_count == 0 {
+            return Err(AppError::NotFound("Invite code not found".to_string()));
+        }
+
+        record_audit(ctx, AuditEvent {
+            target: Some(code_id.to_hex()),
+            ..audit::event("invite_code.revoke", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Invite code revoked".to_string(),
+        })
+    }
+}
+
+/// Load a pending registration of the current tenant.
+async fn pending_user(ctx: &Context<'_>, user_id: &str) -> AppResult<User> {
+    let db = ctx.data::<Database>()?;
+    let user_id = ObjectId::parse_str(user_id)
+        .map_err(|_| AppError::Validation("Invalid user ID".to_string()))?;
+    db.collection::<User>("users")
+        .find_one(tenant(ctx)?.scope(doc! { "_id": user_id, "pending_approval": true }), None).await?
+        .ok_or_else(|| AppError::NotFound("Pending registration not found".to_string()))
+}
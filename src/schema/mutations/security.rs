@@ -0,0 +1,309 @@
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use mongodb::{bson::doc, Database};
+
+use crate::jwt::JwtConfig;
+use crate::audit;
+use crate::credentials;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome, User};
+use crate::schema::context::{current_user, record_audit};
+use crate::schema::guards::{NotImpersonating, RecentAuth};
+use crate::passwords::PasswordPolicy;
+use crate::otp::{self, PURPOSE_VERIFY_PHONE};
+use crate::sms::SmsConfig;
+use crate::totp;
+use super::MutationResponse;
+
+/// Ways to prove identity again.
+///
+/// Passkeys are not offered yet: there is no passkey registration, so no account
+/// could use them. `credentials::KIND_PASSKEY` is reserved for when there is.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum ReauthMethod {
+    Password,
+    Totp,
+}
+
+#[derive(SimpleObject)]
+pub struct ElevatedToken {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+#[derive(SimpleObject)]
+pub struct TotpSetup {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Default)]
+pub struct SecurityMutation;
+
+#[Object]
+impl SecurityMutation {
+    /// Prove identity again and receive a short-lived token accepted by sensitive operations.
+    #[graphql(guard = "NotImpersonating")]
+    async fn reauthenticate(&self, ctx: &Context<'_>, method: ReauthMethod, secret: String) -> AppResult<ElevatedToken> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        let (verified, amr) = match method {
+            ReauthMethod::Password => {
+                let bcrypt_cost = ctx.data::<PasswordPolicy>()?.bcrypt_cost;
+                (credentials::verify_password(db, bcrypt_cost, user_id, &secret).await?.is_some(), "pwd")
+            }
+            ReauthMethod::Totp => {
+                if credentials::totp_secret(db, user_id).await?.is_none() {
+                    return Err(AppError::Validation("Authenticator app is not enabled for this account".to_string()));
+                }
+                (credentials::verify_totp(db, user_id, &secret, false).await?, "otp")
+            }
+        };
+        if !verified {
+            record_audit(ctx, AuditEvent {
+                detail: Some(amr.to_string()),
+                ..audit::event("security.reauthenticate", AuditOutcome::Failure)
+            }).await;
+            return Err(AppError::Unauthenticated("Invalid credentials".to_string()));
+        }
+
+        let user_id = user.id.map(|id| id.to_hex()).unwrap_or_default();
+        let claims = jwt.elevated_claims_for(&user, user_id, &[amr]);
+        let token = jwt.encode(&claims)?;
+
+        record_audit(ctx, AuditEvent {
+            detail: Some(amr.to_string()),
+            ..audit::event("security.reauthenticate", AuditOutcome::Success)
+        }).await;
+
+        Ok(ElevatedToken {
+            token,
+            expires_at: claims.exp as i64,
+        })
+    }
+
+    /// Generate a new authenticator secret; it is activated by `confirmTotp`.
+    ///
+    /// An account with an active authenticator app has to disable it first.
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn enable_totp(&self, ctx: &Context<'_>) -> AppResult<TotpSetup> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+        if credentials::totp_secret(db, user_id).await?.is_some() {
+            return Err(AppError::Conflict("An authenticator app is already enabled; disable it first".to_string()));
+        }
+
+        let secret = totp::generate_secret();
+        credentials::start_totp(db, &user, &secret).await?;
+
+        Ok(TotpSetup {
+            provisioning_uri: totp::provisioning_uri(&secret, &user.email, &jwt.issuer),
+            secret,
+        })
+    }
+
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn confirm_totp(&self, ctx: &Context<'_>, code: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        if credentials::pending_totp(db, user_id).await?.is_none() {
+            return Err(AppError::Validation("No authenticator setup in progress".to_string()));
+        }
+        if credentials::totp_secret(db, user_id).await?.is_some() {
+            return Err(AppError::Conflict("An authenticator app is already enabled; disable it first".to_string()));
+        }
+        if !credentials::verify_totp(db, user_id, &code, true).await? {
+            return Ok(MutationResponse {
+                success: false,
+                message: "Invalid code".to_string(),
+            });
+        }
+
+        credentials::confirm_totp(db, user_id).await?;
+
+        record_audit(ctx, audit::event("security.totp_enabled", AuditOutcome::Success)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Authenticator app enabled".to_string(),
+        })
+    }
+
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn disable_totp(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        credentials::remove(db, user_id, credentials::KIND_TOTP).await?;
+
+        record_audit(ctx, audit::event("security.totp_disabled", AuditOutcome::Success)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Authenticator app disabled".to_string(),
+        })
+    }
+
+    /// Text a verification code to the phone number on the account.
+    #[graphql(guard = "NotImpersonating")]
+    async fn send_phone_verification(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let sms = ctx.data::<SmsConfig>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        let phone = user.phone_number
+            .ok_or_else(|| AppError::Validation("No phone number on this account".to_string()))?;
+        if user.phone_verified {
+            return Ok(MutationResponse {
+                success: false,
+                message: "Phone number is already verified".to_string(),
+            });
+        }
+
+        otp::send_code(db, sms, user_id, &phone, PURPOSE_VERIFY_PHONE, None).await?;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Verification code sent".to_string(),
+        })
+    }
+
+    #[graphql(guard = "NotImpersonating")]
+    async fn verify_phone(&self, ctx: &Context<'_>, code: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let users = db.collection::<User>("users");
+        let user = current_user(ctx).await?;
+
+        let filter = doc! { "user_id": user.id, "purpose": PURPOSE_VERIFY_PHONE };
+        let Some(otp) = otp::verify_code(db, filter, &code).await? else {
+            return Ok(MutationResponse {
+                success: false,
+                message: "Invalid or expired code".to_string(),
+            });
+        };
+
+        // The number may have been edited after the code was sent
+        if user.phone_number.as_deref() != Some(otp.phone.as_str()) {
+            return Ok(MutationResponse {
+                success: false,
+                message: "Phone number changed, please request a new code".to_string(),
+            });
+        }
+
+        // A number can only log in to one account
+        let claimed = users.find_one(doc! {
+            "_id": { "$ne": user.id },
+            "phone_number": &otp.phone,
+            "phone_verified": true,
+        }, None).await?
+        .is_some();
+        if claimed {
+            return Err(AppError::Conflict("Phone number is already verified on another account".to_string()));
+        }
+
+        users.update_one(doc! { "_id": user.id }, doc! { "$set": { "phone_verified": true } }, None).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(otp.phone),
+            ..audit::event("security.phone_verified", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Phone number verified".to_string(),
+        })
+    }
+
+    #[graphql(guard = "NotImpersonating")]
+    async fn enable_sms_two_factor(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+
+        if !user.phone_verified {
+            return Err(AppError::Validation("Verify your phone number first".to_string()));
+        }
+
+        db.collection::<User>("users").update_one(
+            doc! { "_id": user.id },
+            doc! { "$set": { "sms_two_factor": true } },
+            None
+        ).await?;
+
+        record_audit(ctx, audit::event("security.sms_two_factor_enabled", AuditOutcome::Success)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "SMS two-factor authentication enabled".to_string(),
+        })
+    }
+
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn disable_sms_two_factor(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+
+        db.collection::<User>("users").update_one(
+            doc! { "_id": user.id },
+            doc! { "$set": { "sms_two_factor": false } },
+            None
+        ).await?;
+
+        record_audit(ctx, audit::event("security.sms_two_factor_disabled", AuditOutcome::Success)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "SMS two-factor authentication disabled".to_string(),
+        })
+    }
+
+    /// Allow signing in with only a code texted to the verified phone number.
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn enable_sms_login(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+
+        if !user.phone_verified {
+            return Err(AppError::Validation("Verify your phone number first".to_string()));
+        }
+
+        db.collection::<User>("users").update_one(
+            doc! { "_id": user.id },
+            doc! { "$set": { "sms_login": true } },
+            None
+        ).await?;
+
+        record_audit(ctx, audit::event("security.sms_login_enabled", AuditOutcome::Success)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "SMS login enabled".to_string(),
+        })
+    }
+
+    #[graphql(guard = "NotImpersonating")]
+    async fn disable_sms_login(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+
+        db.collection::<User>("users").update_one(
+            doc! { "_id": user.id },
+            doc! { "$set": { "sms_login": false } },
+            None
+        ).await?;
+
+        record_audit(ctx, audit::event("security.sms_login_disabled", AuditOutcome::Success)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "SMS login disabled".to_string(),
+        })
+    }
+}
@@ -1,12 +1,70 @@
-use async_graphql::{Context, Object, Result, SimpleObject};
-use mongodb::{bson::doc, Database};
-use crate::models::models::User;
-use bcrypt::{hash, verify, DEFAULT_COST};
+use std::sync::Arc;
+use async_graphql::{Context, ErrorExtensions, InputObject, Object, SimpleObject};
+use chrono::{Duration, Utc};
+use mongodb::{bson::{doc, DateTime}, Database};
+use crate::credentials;
+use crate::mailer::{app_url, Mailer};
+use crate::passwords::{self, PasswordPolicy};
+use crate::accounts::{self, AccountConfig};
+use crate::audit::{self, RequestMeta};
+use crate::auth::{self, AuthTokens, LoginOutcome, RefreshOutcome};
+use crate::challenge::ChallengeConfig;
+use crate::db::is_duplicate_key;
+use crate::email::normalize_email;
+use crate::email_domains::EmailScreening;
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::models::models::{AuditEvent, AuditOutcome, AuthUser, Credential, EmailChange, User};
+use crate::schema::queries::GQLUser;
+use crate::schema::context::{current_user, current_user_id, record_audit, tenant};
+use crate::schema::guards::{NotImpersonating, RecentAuth};
+use crate::sessions;
+use crate::settings::Settings;
+use crate::sms::{normalize_phone, SmsConfig};
+use crate::tokens::{generate_token, hash_token};
+use crate::validation::{trim, trim_optional, Field, NAME_MAX};
+use crate::webhooks;
 
 #[derive(SimpleObject)]
-struct MutationResponse {
-    success: bool,
-    message: String,
+pub struct MutationResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(InputObject)]
+pub struct RegisterInput {
+    #[graphql(process_with = "trim", validator(custom = "Field::email(\"email\")"))]
+    pub email: String,
+    #[graphql(validator(custom = "Field::password(\"password\")"))]
+    pub password: String,
+    #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"fullName\", 1, NAME_MAX)"))]
+    pub full_name: Option<String>,
+    #[graphql(process_with = "trim_optional", validator(custom = "Field::phone(\"phoneNumber\")"))]
+    pub phone_number: Option<String>,
+    /// Needed when registration is invite-only
+    #[graphql(process_with = "trim_optional")]
+    pub invite_code: Option<String>,
+}
+
+/// Tokens for a new session along with the signed-in user.
+#[derive(SimpleObject)]
+pub struct AuthPayload {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Access token expiry as a Unix timestamp
+    pub expires_at: i64,
+    pub user: GQLUser,
+}
+
+impl AuthPayload {
+    pub fn new(tokens: AuthTokens, user: User) -> Self {
+        AuthPayload {
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            expires_at: tokens.expires_at,
+            user: GQLUser::from(user),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -14,21 +72,149 @@ pub struct UserMutation;
 
 #[Object]
 impl UserMutation {
-    async fn update_user(
+    /// Create an account and sign it in.
+    ///
+    /// `botChallenge` is the solved challenge from the `botChallenge` query, when one is configured.
+    /// When registrations need approval the account is created but the mutation fails
+    /// with reason `APPROVAL_PENDING` instead of signing in.
+    async fn register(
+        &self,
+        ctx: &Context<'_>,
+        input: RegisterInput,
+        bot_challenge: Option<String>,
+    ) -> async_graphql::Result<AuthPayload> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+        ctx.data::<ChallengeConfig>()?.require(&meta, bot_challenge.as_deref()).await?;
+
+        let screening = ctx.data::<EmailScreening>()?;
+        let user = auth::self_register(db, tenant(ctx)?, screening, ctx.data::<PasswordPolicy>()?, &meta, &AuthUser {
+            email: input.email,
+            password: input.password,
+            full_name: input.full_name,
+            phone_number: input.phone_number,
+            invite_code: input.invite_code,
+        }).await?;
+        if user.pending_approval {
+            return Err(async_graphql::Error::from(AppError::Forbidden(
+                "Registration received; an administrator has to approve it before you can log in".to_string(),
+            ))
+            .extend_with(|_, e| e.set("reason", "APPROVAL_PENDING")));
+        }
+        let tokens = auth::issue_tokens(db, jwt, &meta, &user, &["pwd"]).await?;
+        Ok(AuthPayload::new(tokens, user))
+    }
+
+    /// Sign in with email and password.
+    ///
+    /// Accounts with SMS two-factor fail with reason `TWO_FACTOR_REQUIRED` and a
+    /// `challenge` extension to pass to `verifyTwoFactor` with the texted code.
+    /// After repeated failures a solved bot challenge has to be passed as `botChallenge`.
+    async fn login(
         &self,
         ctx: &Context<'_>,
         email: String,
+        password: String,
+        bot_challenge: Option<String>,
+    ) -> async_graphql::Result<AuthPayload> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let sms = ctx.data::<SmsConfig>()?;
+        let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+        let challenges = ctx.data::<ChallengeConfig>()?;
+        challenges.require_for_login(db, tenant(ctx)?, &meta, &email, bot_challenge.as_deref()).await?;
+
+        let passwords = ctx.data::<PasswordPolicy>()?;
+        let outcome = auth::password_login(db, tenant(ctx)?, sms, passwords, &meta, &email, &password).await?;
+        login_payload(db, jwt, &meta, outcome, &["pwd"]).await
+    }
+
+    /// Finish a login with the SMS code sent for a two-factor challenge.
+    async fn verify_two_factor(&self, ctx: &Context<'_>, challenge: String, code: String) -> async_graphql::Result<AuthPayload> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let passwords = ctx.data::<PasswordPolicy>()?;
+        let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+
+        let outcome = auth::two_factor_login(db, tenant(ctx)?, passwords, &meta, &challenge, &code).await?;
+        login_payload(db, jwt, &meta, outcome, &["pwd", "sms"]).await
+    }
+
+    /// Replace an expired password with the `changeToken` from a `PASSWORD_CHANGE_REQUIRED`
+    /// login error, then log in again with the new password. Needs a solved `botChallenge`
+    /// when challenges are configured.
+    async fn change_expired_password(
+        &self,
+        ctx: &Context<'_>,
+        change_token: String,
+        #[graphql(validator(custom = "Field::password(\"newPassword\")"))]
+        new_password: String,
+        bot_challenge: Option<String>,
+    ) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+        ctx.data::<ChallengeConfig>()?.require(&meta, bot_challenge.as_deref()).await?;
+        passwords::change_expired(db, jwt, ctx.data::<PasswordPolicy>()?, &meta, &change_token, &new_password).await
+            .map_err(|e| match e {
+                // Report reuse against the GraphQL argument name
+                AppError::InvalidFields(mut errors) => {
+                    errors.iter_mut().for_each(|error| error.field = "newPassword".to_string());
+                    AppError::InvalidFields(errors)
+                }
+                e => e,
+            })?;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Password changed; log in with the new password.".to_string(),
+        })
+    }
+
+    /// Exchange a refresh token for a new token pair; the old refresh token stops working.
+    ///
+    /// Fails with reason `PASSWORD_CHANGE_REQUIRED` once the password has expired, as `login` does.
+    async fn refresh_token(&self, ctx: &Context<'_>, refresh_token: String) -> async_graphql::Result<AuthPayload> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let passwords = ctx.data::<PasswordPolicy>()?;
+
+        match auth::refresh_tokens(db, jwt, passwords, &refresh_token).await? {
+            RefreshOutcome::Refreshed(user, tokens) => Ok(AuthPayload::new(tokens, *user)),
+            RefreshOutcome::PasswordChangeRequired(password) => Err(password_change_required(jwt, &password)?),
+        }
+    }
+
+    /// End the session behind a refresh token.
+    async fn logout(&self, ctx: &Context<'_>, refresh_token: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+
+        let revoked = sessions::revoke(db, &refresh_token).await?;
+        Ok(MutationResponse {
+            success: revoked,
+            message: if revoked { "Logged out".to_string() } else { "Session not found".to_string() },
+        })
+    }
+
+    async fn update_user(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"fullName\", 1, NAME_MAX)"))]
         full_name: Option<String>,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::phone(\"phoneNumber\")"))]
         phone_number: Option<String>,
-    ) -> Result<MutationResponse> {
+    ) -> AppResult<MutationResponse> {
         let db = ctx.data::<Database>()?;
         let collection = db.collection::<User>("users");
-    
+
         // Find existing user
-        let user = collection.find_one(doc! { "email": &email }, None).await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?
-            .ok_or_else(|| async_graphql::Error::new("User not found"))?;
-    
+        let user = current_user(ctx).await?;
+
+        // Store phone numbers in E.164 form
+        let phone_number = phone_number.as_deref().map(normalize_phone).transpose()
+            .map_err(AppError::Validation)?;
+
         // Check if provided values are the same as existing ones
         if user.full_name == full_name && user.phone_number == phone_number {
             return Ok(MutationResponse {
@@ -36,61 +222,71 @@ impl UserMutation {
                 message: "No changes detected. Please update with new information.".to_string(),
             });
         }
-    
-        let filter = doc! { "email": &email };
-        let update = doc! {
-            "$set": {
-                "full_name": full_name.clone(),
-                "phone_number": phone_number.clone()
-            }
+
+        let filter = doc! { "_id": user.id };
+        let mut update = doc! {
+            "full_name": full_name.clone(),
+            "phone_number": phone_number.clone()
         };
-    
-        let result = collection.update_one(filter, update, None).await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
-    
+        // A new number has to be verified again before it can receive login codes
+        if user.phone_number != phone_number {
+            update.insert("phone_verified", false);
+            update.insert("sms_two_factor", false);
+            update.insert("sms_login", false);
+        }
+        let update = doc! { "$set": update };
+
+        let result = collection.update_one(filter, update, None).await?;
+
         if result.modified_count == 0 {
             return Ok(MutationResponse {
                 success: false,
                 message: "Failed to update user. Please try again.".to_string(),
             });
         }
-    
+
+        record_audit(ctx, AuditEvent {
+            target: user.id.map(|id| id.to_hex()),
+            ..audit::event("user.update", AuditOutcome::Success)
+        }).await;
+        let updated = User { full_name, phone_number, ..user };
+        webhooks::emit(db, updated.org_id, "user.updated", webhooks::user_data(&updated)).await;
+
         Ok(MutationResponse {
             success: true,
             message: "User updated successfully".to_string(),
         })
     }
 
+    /// Change the password of the logged-in user.
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
     async fn reset_password(
         &self,
         ctx: &Context<'_>,
-        email: String,
         old_password: String,
+        #[graphql(validator(custom = "Field::password(\"newPassword\")"))]
         new_password: String,
-    ) -> Result<MutationResponse> {
+    ) -> AppResult<MutationResponse> {
         let db = ctx.data::<Database>()?;
-        let collection = db.collection::<User>("users");
 
-        let user = collection.find_one(doc! { "email": &email }, None).await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?
-            .ok_or_else(|| async_graphql::Error::new("User not found!"))?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-        if !verify(&old_password, &user.password).map_err(|e| async_graphql::Error::new(e.to_string()))? {
+        let passwords = ctx.data::<PasswordPolicy>()?;
+        if credentials::verify_password(db, passwords.bcrypt_cost, user_id, &old_password).await?.is_none() {
+            record_audit(ctx, AuditEvent {
+                detail: Some("incorrect old password".to_string()),
+                ..audit::event("user.password_reset", AuditOutcome::Failure)
+            }).await;
             return Ok(MutationResponse {
                 success: false,
                 message: "Incorrect old password!".to_string(),
             });
         }
 
-        let hashed_password = hash(new_password, DEFAULT_COST)
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        passwords::change(db, passwords, &user, "newPassword", &new_password).await?;
 
-        collection.update_one(
-            doc! { "email": &email },
-            doc! { "$set": { "password": hashed_password } },
-            None
-        ).await
-        .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        record_audit(ctx, audit::event("user.password_reset", AuditOutcome::Success)).await;
 
         Ok(MutationResponse {
             success: true,
@@ -98,23 +294,202 @@ impl UserMutation {
         })
     }
 
-    async fn delete_account(&self, ctx: &Context<'_>, email: String) -> Result<MutationResponse> {
+    /// Start an email change; the new address only takes effect once confirmed.
+    #[graphql(guard = "NotImpersonating")]
+    async fn request_email_change(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(process_with = "trim", validator(custom = "Field::email(\"newEmail\")"))]
+        new_email: String,
+    ) -> AppResult<MutationResponse> {
         let db = ctx.data::<Database>()?;
-        let collection = db.collection::<User>("users");
-
-        let delete_result = collection.delete_one(doc! { "email": &email }, None).await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let settings = ctx.data::<Settings>()?;
+        let user = current_user(ctx).await?;
+        let user_id = user.id.ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+        let new_email = normalize_email(&new_email).map_err(AppError::Validation)?;
 
-        if delete_result.deleted_count == 0 {
+        if new_email == user.email {
             return Ok(MutationResponse {
                 success: false,
-                message: "User not found!".to_string(),
+                message: "New email is the same as the current one.".to_string(),
             });
         }
+        ctx.data::<EmailScreening>()?.check(db, tenant(ctx)?, "newEmail", &new_email).await?;
+
+        let taken = db.collection::<User>("users")
+            .find_one(tenant(ctx)?.scope(doc! { "email": &new_email }), None).await?
+            .is_some();
+        if taken {
+            return Err(AppError::Conflict("Email is already in use".to_string()));
+        }
+
+        // Only the latest request for a user stays valid
+        let changes = db.collection::<EmailChange>("email_changes");
+        changes.delete_many(doc! { "user_id": user_id }, None).await?;
+
+        let token = generate_token();
+        let expires_at = DateTime::from_millis((Utc::now() + Duration::hours(24)).timestamp_millis());
+        changes.insert_one(EmailChange {
+            id: None,
+            user_id,
+            new_email: new_email.clone(),
+            token_hash: hash_token(&token),
+            expires_at,
+        }, None).await?;
+
+        let link = app_url(&settings.server, &format!("/confirm-email?token={}", token));
+        mailer.send(
+            &new_email,
+            "Confirm your new email address",
+            &format!("Confirm your new email address by opening this link within 24 hours:\n{}", link),
+        ).await
+        .map_err(|e| AppError::internal("email confirmation", e))?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(new_email),
+            ..audit::event("user.email_change_requested", AuditOutcome::Success)
+        }).await;
 
         Ok(MutationResponse {
             success: true,
-            message: "Account deleted successfully!".to_string(),
+            message: "Confirmation sent to the new email address.".to_string(),
         })
     }
+
+    /// Apply a pending email change using the token from the confirmation email.
+    /// Every session of the account is ended, so the user logs in again with the new address.
+    async fn confirm_email_change(&self, ctx: &Context<'_>, token: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let mailer = ctx.data::<Arc<dyn Mailer>>()?;
+        let users = db.collection::<User>("users");
+        let changes = db.collection::<EmailChange>("email_changes");
+
+        let change = changes.find_one_and_delete(doc! {
+            "token_hash": hash_token(&token),
+            "expires_at": { "$gt": DateTime::now() },
+        }, None).await?
+        .ok_or_else(|| AppError::Validation("Invalid or expired confirmation token".to_string()))?;
+
+        let user = users.find_one(doc! { "_id": change.user_id }, None).await?
+            .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+        // The address may have been claimed since the request was made
+        users.update_one(
+            doc! { "_id": change.user_id },
+            doc! { "$set": { "email": &change.new_email } },
+            None
+        ).await
+        .map_err(|e| if is_duplicate_key(&e) {
+            AppError::Conflict("Email is already in use".to_string())
+        } else {
+            AppError::from(e)
+        })?;
+        // Access tokens carry the old address in their `email` claim
+        sessions::revoke_all(db, change.user_id).await?;
+
+        // Let the previous owner of the address know, in case the change was not theirs
+        if let Err(e) = mailer.send(
+            &user.email,
+            "Your email address was changed",
+            &format!("The email address on your account was changed to {}. If you did not make this change, contact support.", change.new_email),
+        ).await {
+            log::warn!("failed to notify {} of email change: {}", user.email, e);
+        }
+
+        record_audit(ctx, AuditEvent {
+            actor_id: Some(change.user_id),
+            actor_email: Some(user.email.clone()),
+            target: Some(change.new_email.clone()),
+            ..audit::event("user.email_changed", AuditOutcome::Success)
+        }).await;
+        let updated = User { email: change.new_email, ..user };
+        webhooks::emit(db, updated.org_id, "user.updated", webhooks::user_data(&updated)).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Email address updated successfully! Log in again with the new address.".to_string(),
+        })
+    }
+
+    /// Deactivate the account; it is deleted once the grace period ends unless restored.
+    #[graphql(guard = "NotImpersonating.and(RecentAuth)")]
+    async fn delete_account(&self, ctx: &Context<'_>) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let config = ctx.data::<AccountConfig>()?;
+        let user_id = current_user_id(ctx)?;
+
+        let purge_after = accounts::deactivate(db, config, user_id).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(user_id.to_hex()),
+            detail: purge_after.try_to_rfc3339_string().ok(),
+            ..audit::event("user.deactivate", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: format!(
+                "Account deactivated. It will be deleted on {} unless you restore it.",
+                purge_after.try_to_rfc3339_string().unwrap_or_default(),
+            ),
+        })
+    }
+
+    /// Reactivate a deactivated account during its grace period, then sign in as usual.
+    ///
+    /// After repeated failures a solved bot challenge has to be passed as `botChallenge`, as for `login`.
+    async fn restore_account(
+        &self,
+        ctx: &Context<'_>,
+        email: String,
+        password: String,
+        bot_challenge: Option<String>,
+    ) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let meta = ctx.data_opt::<RequestMeta>().cloned().unwrap_or_default();
+        let challenges = ctx.data::<ChallengeConfig>()?;
+        challenges.require_for_login(db, tenant(ctx)?, &meta, &email, bot_challenge.as_deref()).await?;
+
+        auth::restore_account(db, tenant(ctx)?, ctx.data::<PasswordPolicy>()?, &meta, &email, &password).await?;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Account restored, you can sign in again.".to_string(),
+        })
+    }
+}
+
+/// GraphQL answer for each login outcome; incomplete logins fail with a `reason` extension.
+pub(super) async fn login_payload(
+    db: &Database,
+    jwt: &JwtConfig,
+    meta: &RequestMeta,
+    outcome: LoginOutcome,
+    amr: &[&str],
+) -> async_graphql::Result<AuthPayload> {
+    match outcome {
+        LoginOutcome::Authenticated(user) => {
+            let tokens = auth::issue_tokens(db, jwt, meta, &user, amr).await?;
+            Ok(AuthPayload::new(tokens, *user))
+        }
+        LoginOutcome::TwoFactorRequired { challenge } => {
+            Err(async_graphql::Error::from(AppError::Unauthenticated("Two-factor code required".to_string()))
+                .extend_with(|_, e| {
+                    e.set("reason", "TWO_FACTOR_REQUIRED");
+                    e.set("challenge", challenge);
+                }))
+        }
+        LoginOutcome::PasswordChangeRequired(password) => Err(password_change_required(jwt, &password)?),
+    }
+}
+
+/// Error asking the client to replace an expired password using `changeToken`.
+fn password_change_required(jwt: &JwtConfig, password: &Credential) -> AppResult<async_graphql::Error> {
+    let change_token = passwords::change_token(jwt, password)?;
+    Ok(async_graphql::Error::from(AppError::Forbidden("Password has expired and must be changed".to_string()))
+        .extend_with(|_, e| {
+            e.set("reason", "PASSWORD_CHANGE_REQUIRED");
+            e.set("changeToken", change_token);
+        }))
 }
@@ -0,0 +1,190 @@
+use async_graphql::{Context, Object, SimpleObject};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Database,
+};
+
+use crate::audit;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome, WebhookDelivery, WebhookSubscription};
+use crate::schema::context::{record_audit, tenant};
+use crate::schema::guards::{NotImpersonating, RoleGuard};
+use crate::schema::mutations::MutationResponse;
+use crate::schema::queries::{GQLWebhook, GQLWebhookDelivery};
+use crate::settings::Settings;
+use crate::tokens::generate_token;
+use crate::validation::{trim, trim_optional, Field, FieldError};
+use crate::webhooks;
+
+/// A new subscription with the secret used to sign its deliveries.
+#[derive(SimpleObject)]
+pub struct WebhookCreated {
+    pub webhook: GQLWebhook,
+    /// Shown only once; verify `X-Webhook-Signature` with it
+    pub secret: String,
+}
+
+#[derive(Default)]
+pub struct WebhookMutation;
+
+#[Object]
+impl WebhookMutation {
+    /// Subscribe an endpoint to events of the current organization. Admin only.
+    ///
+    /// The endpoint must use HTTPS and a public address. A signing secret is
+    /// generated unless one is given.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn create_webhook(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(process_with = "trim", validator(custom = "Field::url(\"url\")"))]
+        url: String,
+        events: Vec<String>,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::length(\"secret\", 16, 256)"))]
+        secret: Option<String>,
+    ) -> AppResult<WebhookCreated> {
+        let db = ctx.data::<Database>()?;
+
+        let mut subscription = WebhookSubscription {
+            id: None,
+            org_id: tenant(ctx)?.org_id(),
+            url: check_url(ctx, url)?,
+            events: check_events(events)?,
+            secret: secret.unwrap_or_else(generate_token),
+            active: true,
+            created_at: DateTime::now(),
+        };
+        subscription.id = db.collection::<WebhookSubscription>("webhook_subscriptions")
+            .insert_one(&subscription, None).await?
+            .inserted_id.as_object_id();
+
+        record_audit(ctx, AuditEvent {
+            target: subscription.id.map(|id| id.to_hex()),
+            detail: Some(subscription.url.clone()),
+            ..audit::event("webhook.create", AuditOutcome::Success)
+        }).await;
+
+        Ok(WebhookCreated {
+            secret: subscription.secret.clone(),
+            webhook: GQLWebhook::from(subscription),
+        })
+    }
+
+    /// Change a subscription's endpoint or events, or pause it. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn update_webhook(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+        #[graphql(process_with = "trim_optional", validator(custom = "Field::url(\"url\")"))]
+        url: Option<String>,
+        events: Option<Vec<String>>,
+        active: Option<bool>,
+    ) -> AppResult<GQLWebhook> {
+        let db = ctx.data::<Database>()?;
+        let webhook_id = parse_id(&id, "webhook")?;
+
+        let mut update = doc! {};
+        if let Some(url) = url {
+            update.insert("url", check_url(ctx, url)?);
+        }
+        if let Some(events) = events {
+            update.insert("events", check_events(events)?);
+        }
+        if let Some(active) = active {
+            update.insert("active", active);
+        }
+        if update.is_empty() {
+            return Err(AppError::Validation("No data to update".to_string()));
+        }
+
+        let subscription = db.collection::<WebhookSubscription>("webhook_subscriptions")
+            .find_one_and_update(
+                tenant(ctx)?.scope(doc! { "_id": webhook_id }),
+                doc! { "$set": update },
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            ).await?
+            .ok_or_else(|| AppError::NotFound("Webhook not found".to_string()))?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(webhook_id.to_hex()),
+            ..audit::event("webhook.update", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLWebhook::from(subscription))
+    }
+
+    /// Remove a subscription and drop its queued deliveries. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn delete_webhook(&self, ctx: &Context<'_>, id: String) -> AppResult<MutationResponse> {
+        let db = ctx.data::<Database>()?;
+        let webhook_id = parse_id(&id, "webhook")?;
+
+        let result = db.collection::<WebhookSubscription>("webhook_subscriptions")
+            .delete_one(tenant(ctx)?.scope(doc! { "_id": webhook_id }), None).await?;
+        if result.deleted_count == 0 {
+            return Err(AppError::NotFound("Webhook not found".to_string()));
+        }
+        db.collection::<WebhookDelivery>("webhook_deliveries")
+            .delete_many(doc! { "subscription_id": webhook_id, "status": webhooks::STATUS_PENDING }, None).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(webhook_id.to_hex()),
+            ..audit::event("webhook.delete", AuditOutcome::Success)
+        }).await;
+
+        Ok(MutationResponse {
+            success: true,
+            message: "Webhook deleted".to_string(),
+        })
+    }
+
+    /// Send an earlier delivery again as a new delivery. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\").and(NotImpersonating)")]
+    async fn redeliver_webhook(&self, ctx: &Context<'_>, delivery_id: String) -> AppResult<GQLWebhookDelivery> {
+        let db = ctx.data::<Database>()?;
+        let delivery_id = parse_id(&delivery_id, "delivery")?;
+
+        let delivery = db.collection::<WebhookDelivery>("webhook_deliveries")
+            .find_one(tenant(ctx)?.scope(doc! { "_id": delivery_id }), None).await?
+            .ok_or_else(|| AppError::NotFound("Delivery not found".to_string()))?;
+        let copy = webhooks::redeliver(db, &delivery).await?;
+
+        record_audit(ctx, AuditEvent {
+            target: Some(delivery_id.to_hex()),
+            detail: Some(delivery.event),
+            ..audit::event("webhook.redeliver", AuditOutcome::Success)
+        }).await;
+
+        Ok(GQLWebhookDelivery::from(copy))
+    }
+}
+
+fn parse_id(id: &str, what: &str) -> AppResult<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| AppError::Validation(format!("Invalid {} ID", what)))
+}
+
+/// Refuse endpoints deliveries may not be sent to.
+fn check_url(ctx: &Context<'_>, url: String) -> AppResult<String> {
+    webhooks::check_endpoint(&url, ctx.data::<Settings>()?.webhooks.allow_http)
+        .map_err(|message| AppError::InvalidFields(vec![FieldError { field: "url".to_string(), message }]))?;
+    Ok(url)
+}
+
+/// Reject unknown event names and empty lists, removing duplicates.
+fn check_events(mut events: Vec<String>) -> AppResult<Vec<String>> {
+    let unknown: Vec<&str> = events.iter().map(String::as_str).filter(|event| !webhooks::is_event(event)).collect();
+    let message = if events.is_empty() {
+        "must name at least one event".to_string()
+    } else if !unknown.is_empty() {
+        format!("has unknown events: {}", unknown.join(", "))
+    } else {
+        events.sort();
+        events.dedup();
+        return Ok(events);
+    };
+    Err(AppError::InvalidFields(vec![FieldError { field: "events".to_string(), message }]))
+}
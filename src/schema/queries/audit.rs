@@ -0,0 +1,88 @@
+use async_graphql::{Context, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::doc, options::FindOptions, Database};
+
+use crate::audit::AuditFilter;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{AuditEvent, AuditOutcome};
+use crate::schema::context::tenant;
+use crate::schema::guards::RoleGuard;
+
+#[derive(SimpleObject)]
+pub struct GQLAuditEvent {
+    pub id: String,
+    pub actor_id: Option<String>,
+    pub actor_email: Option<String>,
+    pub impersonator_id: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub outcome: String,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+impl From<AuditEvent> for GQLAuditEvent {
+    fn from(event: AuditEvent) -> Self {
+        GQLAuditEvent {
+            id: event.id.map(|id| id.to_hex()).unwrap_or_default(),
+            actor_id: event.actor_id.map(|id| id.to_hex()),
+            actor_email: event.actor_email,
+            impersonator_id: event.impersonator_id.map(|id| id.to_hex()),
+            action: event.action,
+            target: event.target,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            outcome: match event.outcome {
+                AuditOutcome::Success => "success".to_string(),
+                AuditOutcome::Failure => "failure".to_string(),
+            },
+            detail: event.detail,
+            created_at: event.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct AuditEventPage {
+    pub total: u64,
+    pub items: Vec<GQLAuditEvent>,
+}
+
+#[derive(Default)]
+pub struct AuditQuery;
+
+#[Object]
+impl AuditQuery {
+    /// Audit events, newest first. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn audit_events(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<AuditFilter>,
+        #[graphql(default = 0)] offset: u64,
+        #[graphql(default = 50)] limit: i64,
+    ) -> AppResult<AuditEventPage> {
+        let db = ctx.data::<Database>()?;
+        let collection = db.collection::<AuditEvent>("audit_events");
+
+        let filter = tenant(ctx)?.scope(filter.unwrap_or_default().to_document()
+            .map_err(AppError::Validation)?);
+
+        let total = collection.count_documents(filter.clone(), None).await?;
+
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .skip(offset)
+            .limit(limit.clamp(1, 100))
+            .build();
+        let events: Vec<AuditEvent> = collection.find(filter, options).await?
+            .try_collect().await?;
+
+        Ok(AuditEventPage {
+            total,
+            items: events.into_iter().map(GQLAuditEvent::from).collect(),
+        })
+    }
+}
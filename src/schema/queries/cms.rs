@@ -1,9 +1,11 @@
-use async_graphql::{Context, Object, Result, SimpleObject};
+use async_graphql::{Context, Object, SimpleObject};
 use mongodb::{bson::doc, Database};
 use futures::stream::TryStreamExt;
 
 
+use crate::error::AppResult;
 use crate::models::models::{Post, User};
+use crate::schema::context::tenant;
 
 #[derive(SimpleObject)]
 pub struct Author {
@@ -21,7 +23,7 @@ pub struct GQLPost {
     pub thumbnail: String,
     pub created_at: String,
     pub updated_at: Option<String>,
-    pub author: Author,  // Author as an object
+    pub author: Option<Author>,  // Author as an object, if the account still exists
 }
 
 #[derive(Default)]
@@ -29,23 +31,21 @@ pub struct CmsQuery;
 
 #[Object]
 impl CmsQuery {
-    async fn posts(&self, ctx: &Context<'_>) -> Result<Vec<GQLPost>> {
+    async fn posts(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLPost>> {
         let db = ctx.data::<Database>()?;
         let post_collection = db.collection::<Post>("posts");
         let user_collection = db.collection::<User>("users");
 
-        let mut cursor = post_collection.find(doc! {}, None).await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        let mut cursor = post_collection.find(tenant(ctx)?.scope(doc! {}), None).await?;
 
         let mut posts = Vec::new();
-        while let Some(post) = cursor.try_next().await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))? 
+        while let Some(post) = cursor.try_next().await? 
         {
-            let author = user_collection
-            .find_one(doc! { "_id": &post.author }, None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?
-            .ok_or_else(|| async_graphql::Error::new("Author not found"))?;
+            // Posts of purged accounts may have no author, or one that no longer exists
+            let author = match post.author {
+                Some(author_id) => user_collection.find_one(doc! { "_id": author_id }, None).await?,
+                None => None,
+            };
 
 
          // Use try_to_rfc3339_string() instead of the deprecated method
@@ -63,12 +63,12 @@ impl CmsQuery {
             id: post.id.map(|oid| oid.to_hex()).unwrap_or_default(),  // Convert ObjectId to String
             title: post.title,
             thumbnail: post.thumbnail,
-            author: Author {
+            author: author.map(|author| Author {
                 id: author.id.map(|oid| oid.to_hex()).unwrap_or_default(),
                 email: author.email,
                 full_name: author.full_name.unwrap_or_default(),
                 phone_number: author.phone_number,
-            },
+            }),
             desc: post.desc,
              created_at: created_at.unwrap_or_else(|| "".to_string()), 
             updated_at,  
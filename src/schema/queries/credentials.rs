@@ -0,0 +1,74 @@
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use mongodb::Database;
+
+use crate::credentials::{self, KIND_API_KEY, KIND_IDENTITY, KIND_PASSKEY, KIND_PASSWORD, KIND_TOTP};
+use crate::error::AppResult;
+use crate::models::models::Credential;
+use crate::schema::context::current_user_id;
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum CredentialKind {
+    Password,
+    /// Authenticator app
+    Totp,
+    Passkey,
+    ApiKey,
+    /// Account at an external identity provider
+    Identity,
+}
+
+impl CredentialKind {
+    fn from_str(kind: &str) -> Option<Self> {
+        match kind {
+            KIND_PASSWORD => Some(CredentialKind::Password),
+            KIND_TOTP => Some(CredentialKind::Totp),
+            KIND_PASSKEY => Some(CredentialKind::Passkey),
+            KIND_API_KEY => Some(CredentialKind::ApiKey),
+            KIND_IDENTITY => Some(CredentialKind::Identity),
+            _ => None,
+        }
+    }
+}
+
+/// A credential without its secret.
+#[derive(SimpleObject)]
+pub struct GQLCredential {
+    pub id: String,
+    pub kind: CredentialKind,
+    pub label: Option<String>,
+    /// Identity provider of a linked identity
+    pub provider: Option<String>,
+    pub created_at: String,
+    pub changed_at: String,
+    pub last_used_at: Option<String>,
+}
+
+impl GQLCredential {
+    fn new(credential: Credential) -> Option<Self> {
+        Some(GQLCredential {
+            id: credential.id?.to_hex(),
+            kind: CredentialKind::from_str(&credential.kind)?,
+            label: credential.label,
+            provider: credential.provider,
+            created_at: credential.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            changed_at: credential.changed_at.try_to_rfc3339_string().unwrap_or_default(),
+            last_used_at: credential.last_used_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct CredentialQuery;
+
+#[Object]
+impl CredentialQuery {
+    /// The ways the current user can sign in; unconfirmed setups are left out.
+    async fn credentials(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLCredential>> {
+        let db = ctx.data::<Database>()?;
+        let credentials = credentials::list(db, current_user_id(ctx)?).await?;
+        Ok(credentials.into_iter()
+            .filter(|credential| !credential.pending)
+            .filter_map(GQLCredential::new)
+            .collect())
+    }
+}
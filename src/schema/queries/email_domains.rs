@@ -0,0 +1,67 @@
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::doc, options::FindOptions, Database};
+
+use crate::email_domains::{ACTION_ALLOW, ACTION_DENY};
+use crate::error::AppResult;
+use crate::models::models::EmailDomainRule;
+use crate::schema::context::tenant;
+use crate::schema::guards::RoleGuard;
+
+/// Whether a domain rule lets addresses through or blocks them.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum DomainRuleAction {
+    /// Accept the domain even if it is on the disposable list or has no MX record
+    Allow,
+    Deny,
+}
+
+impl DomainRuleAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DomainRuleAction::Allow => ACTION_ALLOW,
+            DomainRuleAction::Deny => ACTION_DENY,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GQLEmailDomainRule {
+    pub id: String,
+    pub domain: String,
+    pub action: DomainRuleAction,
+    pub note: Option<String>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+impl From<EmailDomainRule> for GQLEmailDomainRule {
+    fn from(rule: EmailDomainRule) -> Self {
+        GQLEmailDomainRule {
+            id: rule.id.map(|id| id.to_hex()).unwrap_or_default(),
+            domain: rule.domain,
+            action: if rule.action == ACTION_ALLOW { DomainRuleAction::Allow } else { DomainRuleAction::Deny },
+            note: rule.note,
+            created_by: rule.created_by.to_hex(),
+            created_at: rule.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EmailDomainQuery;
+
+#[Object]
+impl EmailDomainQuery {
+    /// Allowed and denied email domains of the current organization. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn email_domain_rules(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLEmailDomainRule>> {
+        let db = ctx.data::<Database>()?;
+        let options = FindOptions::builder().sort(doc! { "domain": 1 }).build();
+        let rules: Vec<EmailDomainRule> = db.collection::<EmailDomainRule>("email_domain_rules")
+            .find(tenant(ctx)?.scope(doc! {}), options).await?
+            .try_collect().await?;
+
+        Ok(rules.into_iter().map(GQLEmailDomainRule::from).collect())
+    }
+}
@@ -0,0 +1,62 @@
+use async_graphql::{Context, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::doc, options::FindOptions, Database};
+
+use crate::error::AppResult;
+use crate::exports::{self, STATUS_READY};
+use crate::jwt::JwtConfig;
+use crate::models::models::DataExport;
+use crate::schema::context::current_user_id;
+use crate::settings::{ServerSettings, Settings};
+
+#[derive(SimpleObject)]
+pub struct GQLDataExport {
+    pub id: String,
+    pub format: String,
+    /// `pending`, `ready` or `failed`
+    pub status: String,
+    pub created_at: String,
+    pub completed_at: Option<String>,
+    /// Signed link valid for a few minutes; only set once the export is ready
+    pub download_url: Option<String>,
+}
+
+impl GQLDataExport {
+    pub fn new(export: DataExport, jwt: &JwtConfig, server: &ServerSettings) -> AppResult<Self> {
+        let download_url = match export.id {
+            Some(id) if export.status == STATUS_READY => Some(exports::download_url(jwt, server, id)?),
+            _ => None,
+        };
+        Ok(GQLDataExport {
+            id: export.id.map(|id| id.to_hex()).unwrap_or_default(),
+            format: export.format,
+            status: export.status,
+            created_at: export.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            completed_at: export.completed_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+            download_url,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct ExportQuery;
+
+#[Object]
+impl ExportQuery {
+    /// The current user's data exports, newest first. Poll until `status` is `ready`.
+    async fn data_exports(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLDataExport>> {
+        let db = ctx.data::<Database>()?;
+        let jwt = ctx.data::<JwtConfig>()?;
+        let settings = ctx.data::<Settings>()?;
+
+        let user_id = current_user_id(ctx)?;
+        exports::fail_stale(db, Some(user_id)).await?;
+
+        let options = FindOptions::builder().sort(doc! { "created_at": -1 }).build();
+        let exports: Vec<DataExport> = db.collection::<DataExport>("data_exports")
+            .find(doc! { "user_id": user_id }, options).await?
+            .try_collect().await?;
+
+        exports.into_iter().map(|export| GQLDataExport::new(export, jwt, &settings.server)).collect()
+    }
+}
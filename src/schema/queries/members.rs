@@ -0,0 +1,102 @@
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::{doc, DateTime}, Database};
+
+use crate::error::AppResult;
+use crate::models::models::{Invitation, User};
+use crate::schema::context::tenant;
+use crate::schema::guards::RoleGuard;
+use crate::schema::queries::GQLUser;
+
+/// Role of a user within their organization.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum MemberRole {
+    Member,
+    Admin,
+}
+
+impl MemberRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MemberRole::Member => "member",
+            MemberRole::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Self {
+        if role == "admin" { MemberRole::Admin } else { MemberRole::Member }
+    }
+
+    pub fn of(user: &User) -> Self {
+        if user.roles.iter().any(|role| role == "admin") { MemberRole::Admin } else { MemberRole::Member }
+    }
+
+    /// Replace the membership role in `roles`, keeping any unrelated roles.
+    pub fn apply(self, roles: &mut Vec<String>) {
+        roles.retain(|role| role != "admin" && role != "member");
+        roles.push(self.as_str().to_string());
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Member {
+    pub user: GQLUser,
+    pub role: MemberRole,
+}
+
+#[derive(SimpleObject)]
+pub struct GQLInvitation {
+    pub id: String,
+    pub email: String,
+    pub role: MemberRole,
+    pub invited_by: String,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+impl From<Invitation> for GQLInvitation {
+    fn from(invitation: Invitation) -> Self {
+        GQLInvitation {
+            id: invitation.id.map(|id| id.to_hex()).unwrap_or_default(),
+            email: invitation.email,
+            role: MemberRole::parse(&invitation.role),
+            invited_by: invitation.invited_by.to_hex(),
+            created_at: invitation.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            expires_at: invitation.expires_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemberQuery;
+
+#[Object]
+impl MemberQuery {
+    /// Users of the current organization with their roles. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn members(&self, ctx: &Context<'_>) -> AppResult<Vec<Member>> {
+        let db = ctx.data::<Database>()?;
+        let users: Vec<User> = db.collection::<User>("users")
+            .find(tenant(ctx)?.scope(doc! {}), None).await?
+            .try_collect().await?;
+
+        Ok(users.into_iter()
+            .map(|user| Member { role: MemberRole::of(&user), user: GQLUser::from(user) })
+            .collect())
+    }
+
+    /// Invitations that can still be accepted. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn invitations(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLInvitation>> {
+        let db = ctx.data::<Database>()?;
+        let invitations: Vec<Invitation> = db.collection::<Invitation>("invitations")
+            .find(tenant(ctx)?.scope(doc! {
+                "accepted_at": null,
+                "revoked_at": null,
+                "expires_at": { "$gt": DateTime::now() },
+            }), None).await?
+            .try_collect().await?;
+
+        Ok(invitations.into_iter().map(GQLInvitation::from).collect())
+    }
+}
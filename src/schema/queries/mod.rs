@@ -1,5 +1,21 @@
 mod users;
 mod cms;
+mod audit;
+mod organizations;
+mod members;
+mod exports;
+mod webhooks;
+mod registrations;
+mod email_domains;
+mod credentials;
 
 pub use users::*;
-pub use cms::*;
\ No newline at end of file
+pub use cms::*;
+pub use audit::*;
+pub use organizations::*;
+pub use members::*;
+pub use exports::*;
+pub use webhooks::*;
+pub use registrations::*;
+pub use email_domains::*;
+pub use credentials::*;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use async_graphql::{Context, Enum, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::doc, Database};
+
+use crate::error::AppResult;
+use crate::models::models::Organization;
+use crate::registration;
+use crate::schema::context::tenant;
+use crate::schema::guards::PlatformAdmin;
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum LoginMethod {
+    Password,
+    Sms,
+}
+
+impl LoginMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LoginMethod::Password => "password",
+            LoginMethod::Sms => "sms",
+        }
+    }
+}
+
+/// Who may create an account on their own.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum RegistrationMode {
+    Open,
+    Closed,
+    /// Registrations need an invite code
+    InviteCode,
+    /// Only addresses from the allowed email domains may register
+    DomainAllowlist,
+    /// New accounts wait for an admin in `pendingRegistrations`
+    Approval,
+}
+
+impl RegistrationMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RegistrationMode::Open => registration::MODE_OPEN,
+            RegistrationMode::Closed => registration::MODE_CLOSED,
+            RegistrationMode::InviteCode => registration::MODE_INVITE_CODE,
+            RegistrationMode::DomainAllowlist => registration::MODE_DOMAIN,
+            RegistrationMode::Approval => registration::MODE_APPROVAL,
+        }
+    }
+
+    pub fn parse(mode: &str) -> Self {
+        match mode {
+            registration::MODE_OPEN => RegistrationMode::Open,
+            registration::MODE_INVITE_CODE => RegistrationMode::InviteCode,
+            registration::MODE_DOMAIN => RegistrationMode::DomainAllowlist,
+            registration::MODE_APPROVAL => RegistrationMode::Approval,
+            _ => RegistrationMode::Closed,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GQLOrganization {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub allowed_login_methods: Vec<LoginMethod>,
+    pub registration_mode: RegistrationMode,
+    pub allowed_email_domains: Vec<String>,
+    /// Browser origins allowed to call this organization besides the deployment's own
+    pub allowed_origins: Vec<String>,
+}
+
+impl From<Organization> for GQLOrganization {
+    fn from(org: Organization) -> Self {
+        GQLOrganization {
+            id: org.id.map(|id| id.to_hex()).unwrap_or_default(),
+            slug: org.slug,
+            name: org.name,
+            allowed_login_methods: org.settings.allowed_login_methods.iter()
+                .filter_map(|method| match method.as_str() {
+                    "password" => Some(LoginMethod::Password),
+                    "sms" => Some(LoginMethod::Sms),
+                    _ => None,
+                })
+                .collect(),
+            registration_mode: RegistrationMode::parse(&org.settings.registration.mode),
+            allowed_email_domains: org.settings.registration.allowed_domains,
+            allowed_origins: org.settings.allowed_origins,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct OrganizationQuery;
+
+#[Object]
+impl OrganizationQuery {
+    /// The organization this request is served for; `null` on the default tenant.
+    async fn organization(&self, ctx: &Context<'_>) -> AppResult<Option<GQLOrganization>> {
+        Ok(tenant(ctx)?.org.clone().map(GQLOrganization::from))
+    }
+
+    /// How new accounts can be created on this tenant, so sign-up forms can ask for an invite code.
+    async fn registration_mode(&self, ctx: &Context<'_>) -> AppResult<RegistrationMode> {
+        Ok(RegistrationMode::parse(&tenant(ctx)?.registration_policy().mode))
+    }
+
+    /// All organizations. Platform admins only.
+    #[graphql(guard = "PlatformAdmin")]
+    async fn organizations(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLOrganization>> {
+        let db = ctx.data::<Database>()?;
+        let organizations: Vec<Organization> = db.collection::<Organization>("organizations")
+            .find(doc! {}, None).await?
+            .try_collect().await?;
+        Ok(organizations.into_iter().map(GQLOrganization::from).collect())
+    }
+}
@@ -0,0 +1,66 @@
+use async_graphql::{Context, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::{doc, DateTime}, options::FindOptions, Database};
+
+use crate::error::AppResult;
+use crate::models::models::{InviteCode, User};
+use crate::schema::context::tenant;
+use crate::schema::guards::RoleGuard;
+use crate::schema::queries::GQLUser;
+
+/// An invite code; the code itself is only shown when it is created.
+#[derive(SimpleObject)]
+pub struct GQLInviteCode {
+    pub id: String,
+    pub created_by: String,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub expires_at: Option<String>,
+    pub created_at: String,
+}
+
+impl From<InviteCode> for GQLInviteCode {
+    fn from(code: InviteCode) -> Self {
+        GQLInviteCode {
+            id: code.id.map(|id| id.to_hex()).unwrap_or_default(),
+            created_by: code.created_by.to_hex(),
+            max_uses: code.max_uses,
+            uses: code.uses,
+            expires_at: code.expires_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+            created_at: code.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RegistrationQuery;
+
+#[Object]
+impl RegistrationQuery {
+    /// Self-registered accounts waiting for approval, oldest first. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn pending_registrations(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLUser>> {
+        let db = ctx.data::<Database>()?;
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        let users: Vec<User> = db.collection::<User>("users")
+            .find(tenant(ctx)?.scope(doc! { "pending_approval": true }), options).await?
+            .try_collect().await?;
+
+        Ok(users.into_iter().map(GQLUser::from).collect())
+    }
+
+    /// Invite codes that can still be used. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn invite_codes(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLInviteCode>> {
+        let db = ctx.data::<Database>()?;
+        let codes: Vec<InviteCode> = db.collection::<InviteCode>("invite_codes")
+            .find(tenant(ctx)?.scope(doc! {
+                "revoked_at": null,
+                "$expr": { "$lt": ["$uses", "$max_uses"] },
+                "$or": [{ "expires_at": null }, { "expires_at": { "$gt": DateTime::now() } }],
+            }), None).await?
+            .try_collect().await?;
+
+        Ok(codes.into_iter().map(GQLInviteCode::from).collect())
+    }
+}
@@ -1,11 +1,14 @@
 use futures::stream::TryStreamExt;
-use actix_web::{get, web, HttpResponse, Responder};
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse, Responder};
 use async_graphql::{
-    http::GraphQLPlaygroundConfig, Context, Object, Result, SimpleObject,
+    http::GraphQLPlaygroundConfig, Context, Object, SimpleObject,
 };
 use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
 use mongodb::{bson::doc, Database};
-use crate::{models::models::User, MySchema};
+use crate::challenge::{Challenge, ChallengeConfig};
+use crate::error::{annotate_graphql_error, AppError, AppResult};
+use crate::schema::context::{current_user, impersonator_id, tenant};
+use crate::{audit::RequestMeta, email::normalize_email, jwt::JwtConfig, models::models::User, sessions, settings::Settings, tenant, MySchema};
 
 #[get("/graphiql")]
 pub async fn public_graphql_playground() -> impl Responder {
@@ -35,39 +38,77 @@ impl From<User> for GQLUser {
     }
 }
 
+/// The authenticated user, plus the admin behind the session when impersonating.
+#[derive(SimpleObject)]
+pub struct Viewer {
+    pub user: GQLUser,
+    pub impersonating: bool,
+    pub impersonator: Option<GQLUser>,
+}
+
 #[derive(Default)]
 pub struct UserQuery;
 
 #[Object]
 impl UserQuery {
-    async fn users(&self, ctx: &Context<'_>) -> Result<Vec<GQLUser>> {
+    async fn users(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLUser>> {
         let db = ctx.data::<Database>()?;
         let collection = db.collection::<User>("users");
 
         let mut cursor = collection
-            .find(doc! {}, None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+            .find(tenant(ctx)?.scope(doc! { "deactivated_at": null, "pending_approval": { "$ne": true } }), None)
+            .await?;
 
         let mut users = Vec::new();
         while let Some(user) = cursor
             .try_next()
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?
+            .await?
         {
             users.push(GQLUser::from(user));
         }
         Ok(users)
     }
 
-    async fn user(&self, ctx: &Context<'_>, email: String) -> Result<Option<GQLUser>> {
+    /// A bot challenge to solve before `register`, `changeExpiredPassword` or a retried `login`.
+    async fn bot_challenge(&self, ctx: &Context<'_>) -> AppResult<Challenge> {
+        ctx.data::<ChallengeConfig>()?.verifier.issue().await
+            .map_err(|e| AppError::internal("challenge", e))
+    }
+
+    /// The authenticated user.
+    async fn me(&self, ctx: &Context<'_>) -> AppResult<GQLUser> {
+        Ok(GQLUser::from(current_user(ctx).await?))
+    }
+
+    /// Who the current token belongs to; the UI shows a banner when `impersonating` is set.
+    async fn viewer(&self, ctx: &Context<'_>) -> AppResult<Viewer> {
+        let db = ctx.data::<Database>()?;
+        let user = current_user(ctx).await?;
+
+        let impersonator = match impersonator_id(ctx) {
+            Some(admin_id) => db.collection::<User>("users")
+                .find_one(doc! { "_id": admin_id }, None)
+                .await?
+                .map(GQLUser::from),
+            None => None,
+        };
+
+        Ok(Viewer {
+            user: GQLUser::from(user),
+            impersonating: impersonator_id(ctx).is_some(),
+            impersonator,
+        })
+    }
+
+    /// Look up an active user by email; like `users`, deactivated and unapproved accounts are hidden.
+    async fn user(&self, ctx: &Context<'_>, email: String) -> AppResult<Option<GQLUser>> {
         let db = ctx.data::<Database>()?;
         let collection = db.collection::<User>("users");
 
+        let email = normalize_email(&email).map_err(AppError::Validation)?;
         let user = collection
-            .find_one(doc! { "email": email }, None)
-            .await
-            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+            .find_one(tenant(ctx)?.scope(doc! { "email": email, "deactivated_at": null, "pending_approval": { "$ne": true } }), None)
+            .await?;
 
         Ok(user.map(GQLUser::from))
     }
@@ -85,10 +126,46 @@ impl UserQuery {
 pub async fn graphql_handler(
     schema: web::Data<MySchema>,
     db: web::Data<Database>,
+    jwt: web::Data<JwtConfig>,
+    settings: web::Data<Settings>,
+    http_req: HttpRequest,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
     let mut request = req.into_inner();
     request = request.data(db.clone()); // Clone and inject database reference
-    let response = schema.execute(request).await;
+    request = request.data(RequestMeta::from_request(&http_req, &settings.server));
+
+    match tenant::resolve(&db, &jwt, &settings, &http_req).await {
+        Ok(tenant) => request = request.data(tenant),
+        Err(e) => {
+            let mut error = async_graphql::Error::from(e).into_server_error(Default::default());
+            annotate_graphql_error(&mut error);
+            return async_graphql::Response::from_errors(vec![error]).into();
+        }
+    }
+
+    // Expose verified token claims to resolvers; invalid or revoked tokens are treated as anonymous
+    if let Some(claims) = bearer_token(&http_req).and_then(|token| jwt.verify(token).ok()) {
+        match sessions::is_current(&db, &claims).await {
+            Ok(true) => request = request.data(claims),
+            Ok(false) => {}
+            Err(e) => {
+                let mut error = async_graphql::Error::from(e).into_server_error(Default::default());
+                annotate_graphql_error(&mut error);
+                return async_graphql::Response::from_errors(vec![error]).into();
+            }
+        }
+    }
+    let mut response = schema.execute(request).await;
+    response.errors.iter_mut().for_each(annotate_graphql_error);
     GraphQLResponse::from(response)
 }
+
+/// Extract the token from an `Authorization: Bearer <token>` header
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
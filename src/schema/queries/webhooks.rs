@@ -0,0 +1,141 @@
+use async_graphql::{Context, Object, SimpleObject};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::{doc, oid::ObjectId}, options::FindOptions, Database};
+
+use crate::error::{AppError, AppResult};
+use crate::models::models::{DeliveryAttempt, WebhookDelivery, WebhookSubscription};
+use crate::schema::context::tenant;
+use crate::schema::guards::RoleGuard;
+use crate::webhooks::{EVENTS, STATUS_PENDING};
+
+const DEFAULT_DELIVERY_LIMIT: i64 = 50;
+const MAX_DELIVERY_LIMIT: i64 = 200;
+
+/// A webhook subscription; the signing secret is only shown when it is created.
+#[derive(SimpleObject)]
+pub struct GQLWebhook {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: String,
+}
+
+impl From<WebhookSubscription> for GQLWebhook {
+    fn from(subscription: WebhookSubscription) -> Self {
+        GQLWebhook {
+            id: subscription.id.map(|id| id.to_hex()).unwrap_or_default(),
+            url: subscription.url,
+            events: subscription.events,
+            active: subscription.active,
+            created_at: subscription.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GQLDeliveryAttempt {
+    pub at: String,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+impl From<DeliveryAttempt> for GQLDeliveryAttempt {
+    fn from(attempt: DeliveryAttempt) -> Self {
+        GQLDeliveryAttempt {
+            at: attempt.at.try_to_rfc3339_string().unwrap_or_default(),
+            status_code: attempt.status_code,
+            error: attempt.error,
+            duration_ms: attempt.duration_ms,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GQLWebhookDelivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub event: String,
+    /// JSON body sent to the endpoint
+    pub payload: String,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    pub attempts: u32,
+    pub next_attempt_at: Option<String>,
+    pub log: Vec<GQLDeliveryAttempt>,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+}
+
+impl From<WebhookDelivery> for GQLWebhookDelivery {
+    fn from(delivery: WebhookDelivery) -> Self {
+        let pending = delivery.status == STATUS_PENDING;
+        GQLWebhookDelivery {
+            id: delivery.id.map(|id| id.to_hex()).unwrap_or_default(),
+            webhook_id: delivery.subscription_id.to_hex(),
+            event: delivery.event,
+            payload: delivery.payload,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            next_attempt_at: pending.then(|| delivery.next_attempt_at.try_to_rfc3339_string().unwrap_or_default()),
+            log: delivery.log.into_iter().map(GQLDeliveryAttempt::from).collect(),
+            created_at: delivery.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            delivered_at: delivery.delivered_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct WebhookQuery;
+
+#[Object]
+impl WebhookQuery {
+    /// Event names that webhooks can subscribe to.
+    async fn webhook_events(&self) -> Vec<&'static str> {
+        EVENTS.to_vec()
+    }
+
+    /// Webhook subscriptions of the current organization. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn webhooks(&self, ctx: &Context<'_>) -> AppResult<Vec<GQLWebhook>> {
+        let db = ctx.data::<Database>()?;
+        let subscriptions: Vec<WebhookSubscription> = db.collection::<WebhookSubscription>("webhook_subscriptions")
+            .find(tenant(ctx)?.scope(doc! {}), None).await?
+            .try_collect().await?;
+
+        Ok(subscriptions.into_iter().map(GQLWebhook::from).collect())
+    }
+
+    /// Delivery log, newest first, optionally for one webhook or status. Admin only.
+    #[graphql(guard = "RoleGuard::new(\"admin\")")]
+    async fn webhook_deliveries(
+        &self,
+        ctx: &Context<'_>,
+        webhook_id: Option<String>,
+        status: Option<String>,
+        limit: Option<i64>,
+    ) -> AppResult<Vec<GQLWebhookDelivery>> {
+        let db = ctx.data::<Database>()?;
+
+        let mut filter = doc! {};
+        if let Some(webhook_id) = webhook_id {
+            let webhook_id = ObjectId::parse_str(&webhook_id)
+                .map_err(|_| AppError::Validation("Invalid webhook ID".to_string()))?;
+            filter.insert("subscription_id", webhook_id);
+        }
+        if let Some(status) = status {
+            filter.insert("status", status);
+        }
+
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .limit(limit.unwrap_or(DEFAULT_DELIVERY_LIMIT).clamp(1, MAX_DELIVERY_LIMIT))
+            .build();
+        let deliveries: Vec<WebhookDelivery> = db.collection::<WebhookDelivery>("webhook_deliveries")
+            .find(tenant(ctx)?.scope(filter), options).await?
+            .try_collect().await?;
+
+        Ok(deliveries.into_iter().map(GQLWebhookDelivery::from).collect())
+    }
+}
@@ -0,0 +1,131 @@
+use chrono::{Duration, Utc};
+use mongodb::{bson::{doc, oid::ObjectId, DateTime, Document}, Database};
+
+use crate::audit::RequestMeta;
+use crate::db::is_duplicate_key;
+use crate::error::{AppError, AppResult};
+use crate::models::models::{Claims, Session, User};
+use crate::tokens::{generate_token, hash_token};
+
+/// Start a session for `user_id` and return it with its refresh token.
+pub async fn create(
+    db: &Database,
+    user_id: ObjectId,
+    amr: &[&str],
+    meta: &RequestMeta,
+    ttl: Duration,
+) -> AppResult<(Session, String)> {
+    let refresh_token = generate_token();
+    let now = Utc::now();
+    let mut session = Session {
+        id: None,
+        user_id,
+        refresh_token_hash: hash_token(&refresh_token),
+        amr: amr.iter().map(|method| method.to_string()).collect(),
+        ip: meta.ip.clone(),
+        user_agent: meta.user_agent.clone(),
+        created_at: DateTime::from_millis(now.timestamp_millis()),
+        last_used_at: DateTime::from_millis(now.timestamp_millis()),
+        expires_at: DateTime::from_millis((now + ttl).timestamp_millis()),
+        revoked_at: None,
+    };
+
+    let result = db.collection::<Session>("sessions").insert_one(&session, None).await?;
+    session.id = result.inserted_id.as_object_id();
+    Ok((session, refresh_token))
+}
+
+/// Exchange a refresh token for a new one (rotation); the old token stops working.
+pub async fn rotate(db: &Database, refresh_token: &str) -> AppResult<(Session, String)> {
+    let sessions = db.collection::<Session>("sessions");
+    let new_token = generate_token();
+
+    let session = sessions.find_one_and_update(
+        doc! {
+            "refresh_token_hash": hash_token(refresh_token),
+            "revoked_at": null,
+            "expires_at": { "$gt": DateTime::now() },
+        },
+        doc! { "$set": {
+            "refresh_token_hash": hash_token(&new_token),
+            "last_used_at": DateTime::now(),
+        } },
+        None,
+    ).await?
+    .ok_or_else(|| AppError::Unauthenticated("Invalid or expired refresh token".to_string()))?;
+
+    Ok((session, new_token))
+}
+
+/// Revoke every active session of a user, e.g. after their roles change, and
+/// reject the access tokens already issued to them.
+pub async fn revoke_all(db: &Database, user_id: ObjectId) -> AppResult<u64> {
+    // `iat` has whole seconds, so round up: a token issued in this second is rejected too
+    let valid_after = DateTime::from_millis((Utc::now().timestamp() + 1) * 1000);
+    db.collection::<User>("users").update_one(
+        doc! { "_id": user_id },
+        doc! { "$max": { "tokens_valid_after": valid_after } },
+        None,
+    ).await?;
+
+    let result = db.collection::<Session>("sessions").update_many(
+        doc! { "user_id": user_id, "revoked_at": null },
+        doc! { "$set": { "revoked_at": DateTime::now() } },
+        None,
+    ).await?;
+    Ok(result.modified_count)
+}
+
+/// Reject a single access token by its `jti` until it expires, e.g. when an
+/// impersonation session is ended.
+pub async fn revoke_token(db: &Database, claims: &Claims) -> AppResult<()> {
+    let revoked = db.collection::<Document>("revoked_tokens").insert_one(doc! {
+        "_id": &claims.jti,
+        "expires_at": DateTime::from_millis(claims.exp as i64 * 1000),
+    }, None).await;
+    match revoked {
+        Err(e) if !is_duplicate_key(&e) => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether an access token is still honoured: its subject and, for an impersonation
+/// token, the acting admin still exist and had no sessions revoked since it was issued,
+/// and an impersonation token was not ended with `revoke_token`.
+pub async fn is_current(db: &Database, claims: &Claims) -> AppResult<bool> {
+    let users = db.collection::<User>("users");
+    // Only impersonation tokens are revoked one by one, so other tokens skip the lookup
+    if claims.act.is_some()
+        && db.collection::<Document>("revoked_tokens").count_documents(doc! { "_id": &claims.jti }, None).await? > 0
+    {
+        return Ok(false);
+    }
+    let issued_at = DateTime::from_millis(claims.iat as i64 * 1000);
+    let subjects = std::iter::once(&claims.sub).chain(claims.act.as_ref().map(|actor| &actor.sub));
+    for subject in subjects {
+        let Ok(user_id) = ObjectId::parse_str(subject) else {
+            return Ok(false);
+        };
+        let current = users.count_documents(
+            doc! {
+                "_id": user_id,
+                "$or": [{ "tokens_valid_after": null }, { "tokens_valid_after": { "$lte": issued_at } }],
+            },
+            None,
+        ).await?;
+        if current == 0 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Revoke the session owning `refresh_token` (logout).
+pub async fn revoke(db: &Database, refresh_token: &str) -> AppResult<bool> {
+    let result = db.collection::<Session>("sessions").update_one(
+        doc! { "refresh_token_hash": hash_token(refresh_token), "revoked_at": null },
+        doc! { "$set": { "revoked_at": DateTime::now() } },
+        None,
+    ).await?;
+    Ok(result.modified_count > 0)
+}
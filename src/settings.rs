@@ -0,0 +1,727 @@
+use std::{collections::HashMap, env, fmt, fs, path::Path};
+
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::audit::IpRange;
+use crate::challenge::CHALLENGE_HEADER;
+use crate::cors::{OriginPattern, METHODS};
+use crate::email_domains::normalize_domain;
+use crate::models::models::RegistrationPolicy;
+use crate::registration::{MODES, MODE_OPEN};
+use crate::tenant::TENANT_HEADER;
+
+/// Read when `CONFIG_FILE` is not set; a missing default file is not an error.
+const DEFAULT_FILES: &[&str] = &["config.toml", "config.yaml", "config.yml"];
+
+/// Everything the service is configured with, loaded once at startup from an
+/// optional TOML or YAML file and then environment variables.
+///
+/// Every variable may instead name a file holding its value with a `_FILE`
+/// suffix, e.g. `JWT_SECRET_FILE=/run/secrets/jwt`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub jwt: JwtSettings,
+    pub passwords: PasswordSettings,
+    pub accounts: AccountSettings,
+    pub registration: RegistrationSettings,
+    pub challenge: ChallengeSettings,
+    pub email: EmailSettings,
+    pub sms: SmsSettings,
+    pub cors: CorsSettings,
+    pub webhooks: WebhookSettings,
+    pub import: ImportSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// `host:port` to listen on
+    pub bind: String,
+    /// Frontend origin used in emailed links
+    pub app_base_url: String,
+    /// Tenants are also recognized by subdomain of this domain
+    pub tenant_base_domain: Option<String>,
+    /// Reverse proxies, as addresses or CIDR ranges, whose `X-Forwarded-For` names the client
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            bind: "127.0.0.1:8080".to_string(),
+            app_base_url: "http://localhost:8080".to_string(),
+            tenant_base_domain: None,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSettings {
+    pub uri: String,
+    /// One database can host several organizations; separate deployments may still pick their own
+    pub name: String,
+    /// How often to try reaching Mongo at startup before giving up
+    pub connect_attempts: u32,
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings { uri: String::new(), name: "rust_auth".to_string(), connect_attempts: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtSettings {
+    pub secret: String,
+    pub issuer: String,
+    pub audience: String,
+    pub access_ttl_secs: i64,
+    pub refresh_ttl_secs: i64,
+    pub elevated_ttl_secs: i64,
+    pub impersonation_ttl_secs: i64,
+    /// How long after authenticating a user may still perform sensitive operations
+    pub reauth_max_age_secs: i64,
+    /// Clock skew tolerated when checking `exp` and `nbf`
+    pub leeway_secs: i64,
+}
+
+impl Default for JwtSettings {
+    fn default() -> Self {
+        JwtSettings {
+            secret: String::new(),
+            issuer: "rust_auth".to_string(),
+            audience: "rust_auth".to_string(),
+            access_ttl_secs: 15 * 60,
+            refresh_ttl_secs: 30 * 24 * 60 * 60,
+            elevated_ttl_secs: 5 * 60,
+            impersonation_ttl_secs: 15 * 60,
+            reauth_max_age_secs: 5 * 60,
+            leeway_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordSettings {
+    pub bcrypt_cost: u32,
+    /// How many recent passwords, including the current one, cannot be chosen again
+    pub history_size: usize,
+    /// Maximum password age in days per role
+    pub max_age_days: HashMap<String, i64>,
+}
+
+impl Default for PasswordSettings {
+    fn default() -> Self {
+        PasswordSettings { bcrypt_cost: bcrypt::DEFAULT_COST, history_size: 5, max_age_days: HashMap::new() }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountSettings {
+    /// How long a deactivated account can still be restored
+    pub grace_period_days: i64,
+    pub purge_interval_secs: u64,
+    /// `delete`, `anonymize` or `reassign` to `post_reassign_to`
+    pub post_deletion_policy: String,
+    pub post_reassign_to: Option<String>,
+}
+
+impl Default for AccountSettings {
+    fn default() -> Self {
+        AccountSettings {
+            grace_period_days: 30,
+            purge_interval_secs: 60 * 60,
+            post_deletion_policy: "anonymize".to_string(),
+            post_reassign_to: None,
+        }
+    }
+}
+
+/// Registration policy of the default tenant; organizations set their own.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistrationSettings {
+    pub mode: String,
+    /// Email domains accepted in `domain` mode
+    pub allowed_domains: Vec<String>,
+}
+
+impl Default for RegistrationSettings {
+    fn default() -> Self {
+        RegistrationSettings { mode: MODE_OPEN.to_string(), allowed_domains: Vec::new() }
+    }
+}
+
+impl RegistrationSettings {
+    pub fn policy(&self) -> RegistrationPolicy {
+        RegistrationPolicy { mode: self.mode.clone(), allowed_domains: self.allowed_domains.clone() }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChallengeSettings {
+    /// `none`, `http` or `pow`
+    pub provider: String,
+    pub secret: Option<String>,
+    pub verify_url: String,
+    pub site_key: Option<String>,
+    pub pow_difficulty: u32,
+    /// Logins need a challenge once an address or IP has this many recent failures
+    pub login_after_failures: u64,
+}
+
+impl Default for ChallengeSettings {
+    fn default() -> Self {
+        ChallengeSettings {
+            provider: "none".to_string(),
+            secret: None,
+            verify_url: "https://api.hcaptcha.com/siteverify".to_string(),
+            site_key: None,
+            pow_difficulty: 20,
+            login_after_failures: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailSettings {
+    pub mx_check: bool,
+    /// Extra disposable domains, one per line
+    pub disposable_domains_file: Option<String>,
+}
+
+impl Default for EmailSettings {
+    fn default() -> Self {
+        EmailSettings { mx_check: true, disposable_domains_file: None }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmsSettings {
+    /// `log` or `file`
+    pub sender: String,
+    pub outbox_file: String,
+    pub max_per_hour: u64,
+    pub resend_interval_secs: i64,
+}
+
+impl Default for SmsSettings {
+    fn default() -> Self {
+        SmsSettings {
+            sender: "log".to_string(),
+            outbox_file: "sms_outbox.txt".to_string(),
+            max_per_hour: 5,
+            resend_interval_secs: 60,
+        }
+    }
+}
+
+/// Cross-origin access for browsers; by default no other origin may call the service.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSettings {
+    /// Exact origins such as `https://app.example.com`, or `https://*.example.com` for subdomains
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// How long browsers may cache a preflight answer
+    pub max_age_secs: usize,
+    /// Let browsers send cookies and HTTP authentication along
+    pub allow_credentials: bool,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        CorsSettings {
+            allowed_origins: Vec::new(),
+            allowed_methods: strings(&["GET", "POST"]),
+            allowed_headers: strings(&["accept", "authorization", "content-type", TENANT_HEADER, CHALLENGE_HEADER]),
+            max_age_secs: 3600,
+            allow_credentials: false,
+        }
+    }
+}
+
+/// Outgoing webhook deliveries.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    /// Accept plain `http://` endpoints, e.g. for local development
+    pub allow_http: bool,
+}
+
+/// Parameters for importing users from other systems; only needed while importing.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportSettings {
+    /// Base64, from the Firebase console's password hash parameters
+    pub firebase_signer_key: Option<String>,
+    pub firebase_salt_separator: Option<String>,
+    pub firebase_rounds: Option<u32>,
+    pub firebase_mem_cost: Option<u32>,
+}
+
+/// How an environment variable is turned into a setting.
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Integer,
+    Bool,
+    /// Comma-separated
+    List,
+    /// Comma-separated `key=number` pairs
+    Numbers,
+}
+
+/// Environment variables and the setting each one overrides.
+const ENV_OVERRIDES: &[(&str, &str, Kind)] = &[
+    ("BIND_ADDRESS", "server.bind", Kind::Text),
+    ("APP_BASE_URL", "server.app_base_url", Kind::Text),
+    ("TENANT_BASE_DOMAIN", "server.tenant_base_domain", Kind::Text),
+    ("TRUSTED_PROXIES", "server.trusted_proxies", Kind::List),
+    ("MONGO_URI", "database.uri", Kind::Text),
+    ("MONGO_DB", "database.name", Kind::Text),
+    ("MONGO_CONNECT_ATTEMPTS", "database.connect_attempts", Kind::Integer),
+    ("JWT_SECRET", "jwt.secret", Kind::Text),
+    ("JWT_ISSUER", "jwt.issuer", Kind::Text),
+    ("JWT_AUDIENCE", "jwt.audience", Kind::Text),
+    ("JWT_ACCESS_TTL_SECS", "jwt.access_ttl_secs", Kind::Integer),
+    ("JWT_REFRESH_TTL_SECS", "jwt.refresh_ttl_secs", Kind::Integer),
+    ("JWT_ELEVATED_TTL_SECS", "jwt.elevated_ttl_secs", Kind::Integer),
+    ("JWT_IMPERSONATION_TTL_SECS", "jwt.impersonation_ttl_secs", Kind::Integer),
+    ("REAUTH_MAX_AGE_SECS", "jwt.reauth_max_age_secs", Kind::Integer),
+    ("JWT_LEEWAY_SECS", "jwt.leeway_secs", Kind::Integer),
+    ("BCRYPT_COST", "passwords.bcrypt_cost", Kind::Integer),
+    ("PASSWORD_HISTORY_SIZE", "passwords.history_size", Kind::Integer),
+    ("PASSWORD_MAX_AGE_DAYS", "passwords.max_age_days", Kind::Numbers),
+    ("ACCOUNT_GRACE_PERIOD_DAYS", "accounts.grace_period_days", Kind::Integer),
+    ("ACCOUNT_PURGE_INTERVAL_SECS", "accounts.purge_interval_secs", Kind::Integer),
+    ("POST_DELETION_POLICY", "accounts.post_deletion_policy", Kind::Text),
+    ("POST_REASSIGN_TO", "accounts.post_reassign_to", Kind::Text),
+    ("REGISTRATION_MODE", "registration.mode", Kind::Text),
+    ("REGISTRATION_ALLOWED_DOMAINS", "registration.allowed_domains", Kind::List),
+    ("CHALLENGE_PROVIDER", "challenge.provider", Kind::Text),
+    ("CHALLENGE_SECRET", "challenge.secret", Kind::Text),
+    ("CHALLENGE_VERIFY_URL", "challenge.verify_url", Kind::Text),
+    ("CHALLENGE_SITE_KEY", "challenge.site_key", Kind::Text),
+    ("CHALLENGE_POW_DIFFICULTY", "challenge.pow_difficulty", Kind::Integer),
+    ("CHALLENGE_LOGIN_AFTER_FAILURES", "challenge.login_after_failures", Kind::Integer),
+    ("EMAIL_MX_CHECK", "email.mx_check", Kind::Bool),
+    ("DISPOSABLE_DOMAINS_FILE", "email.disposable_domains_file", Kind::Text),
+    ("SMS_SENDER", "sms.sender", Kind::Text),
+    ("SMS_OUTBOX_FILE", "sms.outbox_file", Kind::Text),
+    ("SMS_MAX_PER_HOUR", "sms.max_per_hour", Kind::Integer),
+    ("SMS_RESEND_INTERVAL_SECS", "sms.resend_interval_secs", Kind::Integer),
+    ("CORS_ALLOWED_ORIGINS", "cors.allowed_origins", Kind::List),
+    ("CORS_ALLOWED_METHODS", "cors.allowed_methods", Kind::List),
+    ("CORS_ALLOWED_HEADERS", "cors.allowed_headers", Kind::List),
+    ("CORS_MAX_AGE_SECS", "cors.max_age_secs", Kind::Integer),
+    ("CORS_ALLOW_CREDENTIALS", "cors.allow_credentials", Kind::Bool),
+    ("WEBHOOK_ALLOW_HTTP", "webhooks.allow_http", Kind::Bool),
+    ("FIREBASE_HASH_SIGNER_KEY", "import.firebase_signer_key", Kind::Text),
+    ("FIREBASE_HASH_SALT_SEPARATOR", "import.firebase_salt_separator", Kind::Text),
+    ("FIREBASE_HASH_ROUNDS", "import.firebase_rounds", Kind::Integer),
+    ("FIREBASE_HASH_MEM_COST", "import.firebase_mem_cost", Kind::Integer),
+];
+
+/// Every problem found while loading, reported together.
+#[derive(Debug)]
+pub struct SettingsError(pub Vec<String>);
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl Settings {
+    /// Load the file named by `CONFIG_FILE` (or a `config.toml`/`config.yaml` in the
+    /// working directory), apply environment overrides and validate the result.
+    pub fn load() -> Result<Settings, SettingsError> {
+        let mut problems = Vec::new();
+
+        let mut tree = match env::var("CONFIG_FILE") {
+            Ok(path) => read_file(Path::new(&path)).unwrap_or_else(|e| {
+                problems.push(e);
+                Value::Object(Map::new())
+            }),
+            Err(_) => DEFAULT_FILES.iter()
+                .map(Path::new)
+                .find(|path| path.exists())
+                .map(|path| read_file(path).unwrap_or_else(|e| {
+                    problems.push(e);
+                    Value::Object(Map::new())
+                }))
+                .unwrap_or_else(|| Value::Object(Map::new())),
+        };
+
+        for (name, path, kind) in ENV_OVERRIDES {
+            match env_value(name) {
+                Ok(Some(raw)) => match parse(&raw, *kind) {
+                    Ok(value) => set_path(&mut tree, path, value),
+                    Err(e) => problems.push(format!("{}: {}", name, e)),
+                },
+                Ok(None) => {}
+                Err(e) => problems.push(e),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(SettingsError(problems));
+        }
+
+        let mut settings: Settings = serde_json::from_value(tree).map_err(|e| SettingsError(vec![e.to_string()]))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Check values that deserialized fine but cannot work, normalizing a few on the way.
+    fn validate(&mut self) -> Result<(), SettingsError> {
+        let mut problems = Vec::new();
+        let mut require = |ok: bool, problem: &str| {
+            if !ok {
+                problems.push(problem.to_string());
+            }
+        };
+
+        let port = self.server.bind.rsplit_once(':').and_then(|(host, port)| (!host.is_empty()).then_some(port));
+        require(port.is_some_and(|port| port.parse::<u16>().is_ok()), "server.bind must look like host:port");
+        require(
+            url::Url::parse(&self.server.app_base_url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")),
+            "server.app_base_url must be an http(s) URL",
+        );
+        self.server.tenant_base_domain = self.server.tenant_base_domain.take().filter(|domain| !domain.is_empty());
+
+        require(
+            self.database.uri.starts_with("mongodb://") || self.database.uri.starts_with("mongodb+srv://"),
+            "database.uri (MONGO_URI) must be set to a mongodb:// or mongodb+srv:// URI",
+        );
+        require(!self.database.name.is_empty(), "database.name must not be empty");
+        require(self.database.connect_attempts > 0, "database.connect_attempts must be positive");
+
+        require(!self.jwt.secret.is_empty(), "jwt.secret (JWT_SECRET) must be set");
+        let jwt = &self.jwt;
+        for (name, value) in [
+            ("access_ttl_secs", jwt.access_ttl_secs),
+            ("refresh_ttl_secs", jwt.refresh_ttl_secs),
+            ("elevated_ttl_secs", jwt.elevated_ttl_secs),
+            ("impersonation_ttl_secs", jwt.impersonation_ttl_secs),
+            ("reauth_max_age_secs", jwt.reauth_max_age_secs),
+        ] {
+            require(value > 0, &format!("jwt.{} must be positive", name));
+        }
+        require(jwt.leeway_secs >= 0, "jwt.leeway_secs must not be negative");
+
+        require((4..=31).contains(&self.passwords.bcrypt_cost), "passwords.bcrypt_cost must be between 4 and 31");
+        require(self.passwords.max_age_days.values().all(|days| *days > 0), "passwords.max_age_days must be positive");
+
+        let accounts = &self.accounts;
+        require(accounts.grace_period_days >= 0, "accounts.grace_period_days must not be negative");
+        require(accounts.purge_interval_secs > 0, "accounts.purge_interval_secs must be positive");
+        match accounts.post_deletion_policy.as_str() {
+            "delete" | "anonymize" => {}
+            "reassign" => require(
+                accounts.post_reassign_to.as_deref().is_some_and(|id| ObjectId::parse_str(id).is_ok()),
+                "accounts.post_reassign_to (POST_REASSIGN_TO) must be a user id for the reassign policy",
+            ),
+            other => problems.push(format!("accounts.post_deletion_policy {} is not delete, anonymize or reassign", other)),
+        }
+
+        if !MODES.contains(&self.registration.mode.as_str()) {
+            problems.push(format!("registration.mode {} is not one of {}", self.registration.mode, MODES.join(", ")));
+        }
+        let mut domains = Vec::new();
+        for domain in self.registration.allowed_domains.iter().filter(|domain| !domain.trim().is_empty()) {
+            match normalize_domain(domain) {
+                Ok(domain) => domains.push(domain),
+                Err(e) => problems.push(format!("registration.allowed_domains: {}", e)),
+            }
+        }
+        self.registration.allowed_domains = domains;
+
+        let challenge = &self.challenge;
+        match challenge.provider.as_str() {
+            "none" => {}
+            "http" | "pow" => {
+                if challenge.secret.as_deref().unwrap_or_default().is_empty() {
+                    problems.push(format!("challenge.secret (CHALLENGE_SECRET) must be set for the {} provider", challenge.provider));
+                }
+                if challenge.provider == "http" && url::Url::parse(&challenge.verify_url).is_err() {
+                    problems.push("challenge.verify_url must be a URL".to_string());
+                }
+            }
+            other => problems.push(format!("challenge.provider {} is not none, http or pow", other)),
+        }
+        if !(1..=32).contains(&challenge.pow_difficulty) {
+            problems.push("challenge.pow_difficulty (CHALLENGE_POW_DIFFICULTY) must be between 1 and 32".to_string());
+        }
+
+        for proxy in &self.server.trusted_proxies {
+            if let Err(e) = IpRange::parse(proxy) {
+                problems.push(format!("server.trusted_proxies: {}", e));
+            }
+        }
+
+        if let Some(path) = self.email.disposable_domains_file.as_deref().filter(|path| !Path::new(path).is_file()) {
+            problems.push(format!("email.disposable_domains_file {} does not exist", path));
+        }
+        if !matches!(self.sms.sender.as_str(), "log" | "file") {
+            problems.push(format!("sms.sender {} is not log or file", self.sms.sender));
+        }
+
+        for origin in &self.cors.allowed_origins {
+            if let Err(e) = OriginPattern::parse(origin) {
+                problems.push(format!("cors.allowed_origins: {}", e));
+            }
+        }
+        for method in &mut self.cors.allowed_methods {
+            *method = method.to_uppercase();
+            if !METHODS.contains(&method.as_str()) {
+                problems.push(format!("cors.allowed_methods: {} is not one of {}", method, METHODS.join(", ")));
+            }
+        }
+        for name in &self.cors.allowed_headers {
+            if actix_web::http::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                problems.push(format!("cors.allowed_headers: {} is not a header name", name));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError(problems))
+        }
+    }
+}
+
+/// `NAME`, or the contents of the file named by `NAME_FILE`; empty values count as unset.
+fn env_value(name: &str) -> Result<Option<String>, String> {
+    let direct = env::var(name).ok().filter(|value| !value.is_empty());
+    let file = env::var(format!("{}_FILE", name)).ok().filter(|value| !value.is_empty());
+    match (direct, file) {
+        (Some(_), Some(_)) => Err(format!("set only one of {} and {}_FILE", name, name)),
+        (Some(value), None) => Ok(Some(value)),
+        (None, Some(path)) => fs::read_to_string(&path)
+            .map(|value| Some(value.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|e| format!("{}_FILE: cannot read {}: {}", name, path, e)),
+        (None, None) => Ok(None),
+    }
+}
+
+fn parse(raw: &str, kind: Kind) -> Result<Value, String> {
+    let list = || raw.split(',').map(str::trim).filter(|item| !item.is_empty());
+    match kind {
+        Kind::Text => Ok(Value::String(raw.to_string())),
+        Kind::Integer => raw.trim().parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| "must be a whole number".to_string()),
+        Kind::Bool => match raw.trim() {
+            "true" | "1" | "yes" => Ok(Value::Bool(true)),
+            "false" | "0" | "no" => Ok(Value::Bool(false)),
+            _ => Err("must be true or false".to_string()),
+        },
+        Kind::List => Ok(Value::Array(list().map(|item| Value::String(item.to_string())).collect())),
+        Kind::Numbers => list()
+            .map(|pair| {
+                let (key, number) = pair.split_once('=').ok_or_else(|| format!("entry {} must look like key=number", pair))?;
+                let number: i64 = number.trim().parse().map_err(|_| format!("entry {} must give a number", pair))?;
+                Ok((key.trim().to_string(), Value::from(number)))
+            })
+            .collect::<Result<Map<String, Value>, String>>()
+            .map(Value::Object),
+    }
+}
+
+/// Set `a.b.c` in a JSON tree, creating objects as needed.
+fn set_path(tree: &mut Value, path: &str, value: Value) {
+    let mut node = tree;
+    let mut keys = path.split('.').peekable();
+    while let Some(key) = keys.next() {
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        let Value::Object(map) = node else { unreachable!() };
+        if keys.peek().is_none() {
+            map.insert(key.to_string(), value);
+            return;
+        }
+        node = map.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+fn read_file(path: &Path) -> Result<Value, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match extension {
+        "toml" => toml::from_str::<toml::Value>(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+            .and_then(|value| serde_json::to_value(value).map_err(|e| format!("{}: {}", path.display(), e))),
+        "yaml" | "yml" => {
+            let documents = yaml_rust2::YamlLoader::load_from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            match documents.into_iter().next() {
+                Some(document) => yaml_to_json(document).map_err(|e| format!("{}: {}", path.display(), e)),
+                None => Ok(Value::Object(Map::new())),
+            }
+        }
+        _ => Err(format!("{} must be a .toml, .yaml or .yml file", path.display())),
+    }
+}
+
+fn yaml_to_json(yaml: yaml_rust2::Yaml) -> Result<Value, String> {
+    use yaml_rust2::Yaml;
+    Ok(match yaml {
+        Yaml::Null => Value::Null,
+        Yaml::Boolean(value) => Value::Bool(value),
+        Yaml::Integer(value) => Value::from(value),
+        Yaml::Real(value) => value.parse::<f64>().map(Value::from).map_err(|_| format!("invalid number {}", value))?,
+        Yaml::String(value) => Value::String(value),
+        Yaml::Array(items) => Value::Array(items.into_iter().map(yaml_to_json).collect::<Result<_, _>>()?),
+        Yaml::Hash(entries) => {
+            let mut map = Map::new();
+            for (key, value) in entries {
+                let key = match key {
+                    Yaml::String(key) => key,
+                    Yaml::Integer(key) => key.to_string(),
+                    other => return Err(format!("unsupported key {:?}", other)),
+                };
+                map.insert(key, yaml_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        other => return Err(format!("unsupported value {:?}", other)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn valid() -> Settings {
+        let mut settings = Settings::default();
+        settings.database.uri = "mongodb://localhost:27017".to_string();
+        settings.jwt.secret = "secret".to_string();
+        settings
+    }
+
+    fn problems(settings: &mut Settings) -> Vec<String> {
+        settings.validate().err().map(|SettingsError(problems)| problems).unwrap_or_default()
+    }
+
+    #[test]
+    fn parse_kinds() {
+        assert_eq!(parse(" text ", Kind::Text).unwrap(), json!(" text "));
+        assert_eq!(parse(" 42 ", Kind::Integer).unwrap(), json!(42));
+        assert!(parse("4.2", Kind::Integer).is_err());
+        assert_eq!(parse("yes", Kind::Bool).unwrap(), json!(true));
+        assert_eq!(parse("0", Kind::Bool).unwrap(), json!(false));
+        assert!(parse("maybe", Kind::Bool).is_err());
+        assert_eq!(parse("a, b,,c ", Kind::List).unwrap(), json!(["a", "b", "c"]));
+        assert_eq!(parse("", Kind::List).unwrap(), json!([]));
+        assert_eq!(parse("admin=90, user = 365", Kind::Numbers).unwrap(), json!({ "admin": 90, "user": 365 }));
+        assert!(parse("admin", Kind::Numbers).is_err());
+        assert!(parse("admin=soon", Kind::Numbers).is_err());
+    }
+
+    #[test]
+    fn set_path_creates_and_replaces_objects() {
+        let mut tree = json!({ "server": { "bind": "0.0.0.0:80" }, "jwt": "oops" });
+        set_path(&mut tree, "server.app_base_url", json!("https://example.com"));
+        set_path(&mut tree, "jwt.secret", json!("s"));
+        set_path(&mut tree, "cors.allowed_origins", json!([]));
+        assert_eq!(tree, json!({
+            "server": { "bind": "0.0.0.0:80", "app_base_url": "https://example.com" },
+            "jwt": { "secret": "s" },
+            "cors": { "allowed_origins": [] },
+        }));
+    }
+
+    #[test]
+    fn env_value_rejects_both_forms() {
+        env::set_var("SETTINGS_TEST_BOTH", "direct");
+        env::set_var("SETTINGS_TEST_BOTH_FILE", "/nonexistent");
+        assert!(env_value("SETTINGS_TEST_BOTH").unwrap_err().contains("set only one"));
+    }
+
+    #[test]
+    fn env_value_reads_file() {
+        let path = env::temp_dir().join(format!("settings-test-{}", std::process::id()));
+        fs::write(&path, "from file\n").unwrap();
+        env::set_var("SETTINGS_TEST_FILE_FILE", &path);
+        assert_eq!(env_value("SETTINGS_TEST_FILE").unwrap().as_deref(), Some("from file"));
+        fs::remove_file(&path).unwrap();
+
+        env::set_var("SETTINGS_TEST_MISSING_FILE", "/nonexistent/secret");
+        assert!(env_value("SETTINGS_TEST_MISSING").is_err());
+        env::set_var("SETTINGS_TEST_EMPTY", "");
+        assert_eq!(env_value("SETTINGS_TEST_EMPTY").unwrap(), None);
+    }
+
+    #[test]
+    fn yaml_to_json_converts_documents() {
+        let yaml = yaml_rust2::YamlLoader::load_from_str("server:\n  bind: 0.0.0.0:80\n  trusted_proxies: [10.0.0.0/8]\npasswords:\n  max_age_days:\n    admin: 90\n1: x\nratio: 0.5\nnothing: ~\n")
+            .unwrap().remove(0);
+        assert_eq!(yaml_to_json(yaml).unwrap(), json!({
+            "server": { "bind": "0.0.0.0:80", "trusted_proxies": ["10.0.0.0/8"] },
+            "passwords": { "max_age_days": { "admin": 90 } },
+            "1": "x",
+            "ratio": 0.5,
+            "nothing": null,
+        }));
+
+        let yaml = yaml_rust2::YamlLoader::load_from_str("[a]: b\n").unwrap().remove(0);
+        assert!(yaml_to_json(yaml).is_err());
+    }
+
+    #[test]
+    fn validate_accepts_minimal_settings() {
+        assert_eq!(problems(&mut valid()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut settings = valid();
+        settings.server.bind = "8080".to_string();
+        settings.database.uri = "postgres://localhost".to_string();
+        settings.jwt.secret = String::new();
+        settings.passwords.bcrypt_cost = 3;
+        settings.challenge.pow_difficulty = 33;
+        settings.server.trusted_proxies = vec!["10.0.0.0/33".to_string()];
+        let problems = problems(&mut settings);
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("server.bind")));
+        assert!(problems.iter().any(|problem| problem.starts_with("challenge.pow_difficulty")));
+    }
+
+    #[test]
+    fn validate_normalizes_values() {
+        let mut settings = valid();
+        settings.server.tenant_base_domain = Some(String::new());
+        settings.cors.allowed_methods = vec!["get".to_string()];
+        settings.registration.allowed_domains = vec!["@Example.COM".to_string(), " ".to_string()];
+        assert_eq!(problems(&mut settings), Vec::<String>::new());
+        assert_eq!(settings.server.tenant_base_domain, None);
+        assert_eq!(settings.cors.allowed_methods, vec!["GET"]);
+        assert_eq!(settings.registration.allowed_domains, vec!["example.com"]);
+    }
+}
@@ -0,0 +1,124 @@
+use async_trait::async_trait;
+use chrono::Duration;
+use std::sync::Arc;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::settings::SmsSettings;
+
+/// Outgoing SMS transport.
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String>;
+}
+
+/// Development sender that writes messages to the log.
+pub struct LogSmsSender;
+
+#[async_trait]
+impl SmsSender for LogSmsSender {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        log::info!("sms to {}: {}", to, body);
+        Ok(())
+    }
+}
+
+/// Sender that appends one line per message to a file, handy for local testing.
+pub struct FileSmsSender {
+    path: String,
+}
+
+#[async_trait]
+impl SmsSender for FileSmsSender {
+    async fn send(&self, to: &str, body: &str) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| e.to_string())?;
+        file.write_all(format!("{}\t{}\n", to, body).as_bytes())
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The configured sender and how often one number may be texted.
+#[derive(Clone)]
+pub struct SmsConfig {
+    pub sender: Arc<dyn SmsSender>,
+    pub max_per_hour: u64,
+    /// Minimum time between two texts to the same number
+    pub resend_interval: Duration,
+}
+
+impl SmsConfig {
+    /// Build from the `sms` section: the `log` sender, or `file` appending to `outbox_file`.
+    pub fn new(settings: &SmsSettings) -> Self {
+        let sender: Arc<dyn SmsSender> = match settings.sender.as_str() {
+            "file" => Arc::new(FileSmsSender { path: settings.outbox_file.clone() }),
+            _ => Arc::new(LogSmsSender),
+        };
+        SmsConfig {
+            sender,
+            max_per_hour: settings.max_per_hour,
+            resend_interval: Duration::seconds(settings.resend_interval_secs),
+        }
+    }
+}
+
+/// Normalize a phone number to E.164 (`+` followed by 8 to 15 digits).
+///
+/// Spaces, dashes, dots and parentheses are dropped and a `00` international prefix becomes `+`.
+pub fn normalize_phone(raw: &str) -> Result<String, String> {
+    let cleaned: String = raw
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect();
+
+    let digits = if let Some(rest) = cleaned.strip_prefix('+') {
+        rest
+    } else if let Some(rest) = cleaned.strip_prefix("00") {
+        rest
+    } else {
+        return Err("Phone number must include the country code, e.g. +14155552671".to_string());
+    };
+
+    if digits.len() < 8 || digits.len() > 15 || !digits.chars().all(|c| c.is_ascii_digit()) || digits.starts_with('0') {
+        return Err("Invalid phone number".to_string());
+    }
+    Ok(format!("+{}", digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_formatting() {
+        assert_eq!(normalize_phone(" +1 (415) 555-2671 ").unwrap(), "+14155552671");
+        assert_eq!(normalize_phone("+44.20.7946.0958").unwrap(), "+442079460958");
+    }
+
+    #[test]
+    fn accepts_international_prefix() {
+        assert_eq!(normalize_phone("0049 30 901820").unwrap(), "+4930901820");
+    }
+
+    #[test]
+    fn requires_country_code() {
+        assert!(normalize_phone("415 555 2671").unwrap_err().contains("country code"));
+        assert!(normalize_phone("0415 555 2671").is_err());
+    }
+
+    #[test]
+    fn enforces_e164_length_and_digits() {
+        assert!(normalize_phone("+1234567").is_err());
+        assert_eq!(normalize_phone("+12345678").unwrap(), "+12345678");
+        assert_eq!(normalize_phone("+123456789012345").unwrap(), "+123456789012345");
+        assert!(normalize_phone("+1234567890123456").is_err());
+        assert!(normalize_phone("+0123456789").is_err());
+        assert!(normalize_phone("+1415555267x").is_err());
+        assert!(normalize_phone("+1 415 555 2671 ext 2").is_err());
+    }
+}
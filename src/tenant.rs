@@ -0,0 +1,170 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures::future::LocalBoxFuture;
+use mongodb::{bson::{doc, oid::ObjectId, Document}, Database};
+
+use crate::error::{AppError, AppResult};
+use crate::jwt::JwtConfig;
+use crate::models::models::{Organization, OrganizationSettings, RegistrationPolicy};
+use crate::schema::bearer_token;
+use crate::settings::Settings;
+
+/// Header naming the organization by slug, for clients that cannot use subdomains.
+pub const TENANT_HEADER: &str = "X-Tenant";
+
+/// The organization a request runs against.
+///
+/// `org` is `None` for the default tenant, which holds accounts created before
+/// organizations existed and is served when no tenant is named.
+#[derive(Debug, Clone, Default)]
+pub struct Tenant {
+    pub org: Option<Organization>,
+    /// Registration policy of the default tenant, from the `registration` settings
+    pub default_policy: RegistrationPolicy,
+}
+
+impl Tenant {
+    pub fn new(org: Option<Organization>, settings: &Settings) -> Self {
+        Tenant { org, default_policy: settings.registration.policy() }
+    }
+
+    pub fn org_id(&self) -> Option<ObjectId> {
+        self.org.as_ref().and_then(|org| org.id)
+    }
+
+    /// Restrict a `users` or `posts` filter to this tenant.
+    pub fn scope(&self, mut filter: Document) -> Document {
+        filter.insert("org_id", self.org_id());
+        filter
+    }
+
+    /// Whether the tenant lets users sign in with `method` (`password` or `sms`).
+    pub fn allows_login(&self, method: &str) -> bool {
+        self.org.as_ref()
+            .map(|org| &org.settings)
+            .unwrap_or(&OrganizationSettings::default())
+            .allowed_login_methods
+            .iter()
+            .any(|allowed| allowed == method)
+    }
+
+    /// Who may register; the default tenant uses the deployment-wide policy.
+    pub fn registration_policy(&self) -> &RegistrationPolicy {
+        match &self.org {
+            Some(org) => &org.settings.registration,
+            None => &self.default_policy,
+        }
+    }
+
+    /// Fail unless `method` is enabled for this tenant.
+    pub fn require_login_method(&self, method: &str) -> AppResult<()> {
+        if self.allows_login(method) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!("{} login is disabled for this organization", method)))
+        }
+    }
+}
+
+/// Work out the tenant from, in order, the token's `org` claim, the `X-Tenant`
+/// header and the subdomain of `TENANT_BASE_DOMAIN`.
+///
+/// A token may only be used with the tenant it was issued for.
+pub async fn resolve(db: &Database, jwt: &JwtConfig, settings: &Settings, req: &HttpRequest) -> AppResult<Tenant> {
+    let organizations = db.collection::<Organization>("organizations");
+
+    let requested = match requested_slug(req, settings) {
+        Some(slug) => Some(
+            organizations.find_one(doc! { "slug": &slug }, None).await?
+                .ok_or_else(|| AppError::NotFound(format!("Unknown organization {}", slug)))?,
+        ),
+        None => None,
+    };
+
+    // Invalid tokens are rejected by whatever needs authentication, not here
+    let Some(claims) = bearer_token(req).and_then(|token| jwt.verify(token).ok()) else {
+        return Ok(Tenant::new(requested, settings));
+    };
+    let token_org = claims.org.as_deref().and_then(|id| ObjectId::parse_str(id).ok());
+
+    match requested {
+        Some(org) if org.id == token_org => Ok(Tenant::new(Some(org), settings)),
+        Some(_) => Err(AppError::Forbidden("Token belongs to another organization".to_string())),
+        None => match token_org {
+            Some(org_id) => {
+                let org = organizations.find_one(doc! { "_id": org_id }, None).await?
+                    .ok_or_else(|| AppError::Unauthenticated("Organization no longer exists".to_string()))?;
+                Ok(Tenant::new(Some(org), settings))
+            }
+            None => Ok(Tenant::new(None, settings)),
+        },
+    }
+}
+
+fn requested_slug(req: &HttpRequest, settings: &Settings) -> Option<String> {
+    let header = req.headers().get(TENANT_HEADER).and_then(|value| value.to_str().ok());
+    slug_from(header, &req.connection_info().host().to_lowercase(), settings.server.tenant_base_domain.as_deref())
+}
+
+/// The slug named by an `X-Tenant` header value, or else by the subdomain of `base_domain` in `host`.
+pub fn slug_from(header: Option<&str>, host: &str, base_domain: Option<&str>) -> Option<String> {
+    if let Some(slug) = header {
+        return Some(slug.trim().to_lowercase());
+    }
+
+    let base_domain = base_domain?;
+    let host = host.split(':').next().unwrap_or_default();
+    let slug = host.strip_suffix(base_domain)?.strip_suffix('.')?;
+    // Only a single label in front of the base domain names a tenant
+    (!slug.is_empty() && !slug.contains('.')).then(|| slug.to_string())
+}
+
+impl FromRequest for Tenant {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let db = req.app_data::<web::Data<Database>>()
+                .ok_or_else(|| AppError::internal("tenant", "database not configured"))?;
+            let jwt = req.app_data::<web::Data<JwtConfig>>()
+                .ok_or_else(|| AppError::internal("tenant", "jwt not configured"))?;
+            let settings = req.app_data::<web::Data<Settings>>()
+                .ok_or_else(|| AppError::internal("tenant", "settings not configured"))?;
+            resolve(db, jwt, settings, &req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: Option<&str> = Some("auth.example.com");
+
+    #[test]
+    fn header_takes_precedence() {
+        assert_eq!(slug_from(Some(" Acme "), "globex.auth.example.com", BASE).as_deref(), Some("acme"));
+        assert_eq!(slug_from(Some("acme"), "localhost:8080", None).as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn subdomain_names_tenant() {
+        assert_eq!(slug_from(None, "acme.auth.example.com", BASE).as_deref(), Some("acme"));
+        assert_eq!(slug_from(None, "acme.auth.example.com:8443", BASE).as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn nested_or_foreign_hosts_name_no_tenant() {
+        assert_eq!(slug_from(None, "www.acme.auth.example.com", BASE), None);
+        assert_eq!(slug_from(None, "auth.example.com", BASE), None);
+        assert_eq!(slug_from(None, "auth.example.com:8443", BASE), None);
+        assert_eq!(slug_from(None, "acmeauth.example.com", BASE), None);
+        assert_eq!(slug_from(None, "acme.example.org", BASE), None);
+    }
+
+    #[test]
+    fn subdomains_ignored_without_base_domain() {
+        assert_eq!(slug_from(None, "acme.auth.example.com", None), None);
+    }
+}
@@ -0,0 +1,297 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use async_graphql::{CustomValidator, InputValueError};
+use futures::future::LocalBoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+use std::ops::Deref;
+
+use crate::email::normalize_email;
+use crate::error::AppError;
+use crate::models::models::AuthUser;
+use crate::sms::normalize_phone;
+
+pub const NAME_MAX: usize = 100;
+pub const TITLE_MAX: usize = 200;
+pub const DESC_MAX: usize = 10_000;
+pub const URL_MAX: usize = 2048;
+pub const PASSWORD_MIN: usize = 8;
+/// bcrypt ignores everything after 72 bytes
+pub const PASSWORD_MAX_BYTES: usize = 72;
+
+/// A rule violation on a single input field.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Input rules shared by the REST extractor and the GraphQL validators.
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    /// Length in characters, inclusive
+    Length { min: usize, max: usize },
+    Email,
+    /// E.164 phone number
+    Phone,
+    /// Absolute http(s) URL
+    Url,
+    /// Lowercase DNS label, used for organization slugs
+    Slug,
+    /// `PASSWORD_MIN` characters or more, and no more bytes than bcrypt reads
+    Password,
+}
+
+impl Rule {
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        match *self {
+            Rule::Length { min, max } => {
+                let len = value.chars().count();
+                if len < min || len > max {
+                    return Err(format!("must be between {} and {} characters", min, max));
+                }
+                Ok(())
+            }
+            Rule::Email => {
+                if value.len() > 254 {
+                    return Err("is too long".to_string());
+                }
+                normalize_email(value).map(|_| ())
+            }
+            Rule::Phone => normalize_phone(value).map(|_| ()),
+            Rule::Url => {
+                if value.len() > URL_MAX {
+                    return Err("is too long".to_string());
+                }
+                match url::Url::parse(value) {
+                    Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+                    _ => Err("must be an absolute http or https URL".to_string()),
+                }
+            }
+            Rule::Slug => {
+                let valid = (1..=63).contains(&value.len())
+                    && value.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                    && !value.starts_with('-')
+                    && !value.ends_with('-');
+                if valid {
+                    Ok(())
+                } else {
+                    Err("must be 1 to 63 lowercase letters, digits or hyphens".to_string())
+                }
+            }
+            Rule::Password => {
+                if value.chars().count() < PASSWORD_MIN {
+                    return Err(format!("must be at least {} characters", PASSWORD_MIN));
+                }
+                // Multi-byte characters count several times towards the limit
+                if value.len() > PASSWORD_MAX_BYTES {
+                    return Err(format!("must be at most {} bytes", PASSWORD_MAX_BYTES));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Check `value` against `rule`, recording a violation for `field`.
+pub fn check(errors: &mut Vec<FieldError>, field: &str, value: &str, rule: Rule) {
+    if let Err(message) = rule.check(value) {
+        errors.push(FieldError { field: field.to_string(), message });
+    }
+}
+
+/// Strip surrounding whitespace. Usable as a GraphQL `process_with` hook.
+pub fn trim(value: &mut String) {
+    let trimmed = value.trim();
+    if trimmed.len() != value.len() {
+        *value = trimmed.to_string();
+    }
+}
+
+/// Strip surrounding whitespace, treating a blank value as absent.
+pub fn trim_optional(value: &mut Option<String>) {
+    if let Some(inner) = value.as_mut() {
+        trim(inner);
+        if inner.is_empty() {
+            *value = None;
+        }
+    }
+}
+
+/// GraphQL validator applying a [`Rule`] to a named field.
+///
+/// Failures carry the same `code` and `fields` extensions as `AppError::InvalidFields`.
+pub struct Field {
+    name: &'static str,
+    rule: Rule,
+}
+
+impl Field {
+    pub fn length(name: &'static str, min: usize, max: usize) -> Self {
+        Field { name, rule: Rule::Length { min, max } }
+    }
+
+    pub fn email(name: &'static str) -> Self {
+        Field { name, rule: Rule::Email }
+    }
+
+    pub fn phone(name: &'static str) -> Self {
+        Field { name, rule: Rule::Phone }
+    }
+
+    pub fn url(name: &'static str) -> Self {
+        Field { name, rule: Rule::Url }
+    }
+
+    pub fn slug(name: &'static str) -> Self {
+        Field { name, rule: Rule::Slug }
+    }
+
+    pub fn password(name: &'static str) -> Self {
+        Field { name, rule: Rule::Password }
+    }
+}
+
+impl CustomValidator<String> for Field {
+    fn check(&self, value: &String) -> Result<(), InputValueError<String>> {
+        self.rule.check(value).map_err(|message| {
+            let fields = vec![FieldError { field: self.name.to_string(), message: message.clone() }];
+            InputValueError::custom(format!("{} {}", self.name, message))
+                .with_extension("code", "VALIDATION")
+                .with_extension("fields", async_graphql::to_value(fields).unwrap_or_default())
+        })
+    }
+}
+
+/// Request bodies that can be normalized and checked before use.
+pub trait Validate {
+    /// Trim fields in place and return every rule violation.
+    fn validate(&mut self) -> Vec<FieldError>;
+}
+
+impl Validate for AuthUser {
+    fn validate(&mut self) -> Vec<FieldError> {
+        trim(&mut self.email);
+        trim_optional(&mut self.full_name);
+        trim_optional(&mut self.phone_number);
+        trim_optional(&mut self.invite_code);
+
+        let mut errors = Vec::new();
+        check(&mut errors, "email", &self.email, Rule::Email);
+        check(&mut errors, "password", &self.password, Rule::Password);
+        if let Some(full_name) = &self.full_name {
+            check(&mut errors, "full_name", full_name, Rule::Length { min: 1, max: NAME_MAX });
+        }
+        if let Some(phone_number) = &self.phone_number {
+            check(&mut errors, "phone_number", phone_number, Rule::Phone);
+        }
+        errors
+    }
+}
+
+/// JSON body extractor that runs [`Validate`] and rejects the request with
+/// field-level errors in the shared problem format.
+pub struct Valid<T>(pub T);
+
+impl<T> Deref for Valid<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for Valid<T> {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let mut value = json.await
+                .map_err(|e| AppError::Validation(e.to_string()))?
+                .into_inner();
+            let errors = value.validate();
+            if errors.is_empty() {
+                Ok(Valid(value))
+            } else {
+                Err(AppError::InvalidFields(errors))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counts_characters() {
+        let rule = Rule::Length { min: 2, max: 4 };
+        assert!(rule.check("a").is_err());
+        assert!(rule.check("ab").is_ok());
+        assert!(rule.check("äöüß").is_ok());
+        assert!(rule.check("abcde").is_err());
+    }
+
+    #[test]
+    fn email_bounds() {
+        assert!(Rule::Email.check("alice@example.com").is_ok());
+        assert!(Rule::Email.check("alice").is_err());
+        let local = "a".repeat(64);
+        let long = format!("{}@{}.com", local, "b".repeat(254 - 64 - 5));
+        assert_eq!(long.len(), 254);
+        assert!(Rule::Email.check(&long).is_ok());
+        assert_eq!(Rule::Email.check(&format!("a{}", long)).unwrap_err(), "is too long");
+    }
+
+    #[test]
+    fn phone_requires_e164() {
+        assert!(Rule::Phone.check("+14155552671").is_ok());
+        assert!(Rule::Phone.check("4155552671").is_err());
+    }
+
+    #[test]
+    fn url_bounds() {
+        assert!(Rule::Url.check("https://example.com/hook").is_ok());
+        assert!(Rule::Url.check("http://localhost:8080").is_ok());
+        for url in ["ftp://example.com", "example.com", "/relative", "mailto:alice@example.com", "https://"] {
+            assert!(Rule::Url.check(url).is_err(), "{:?}", url);
+        }
+        let path = "a".repeat(URL_MAX - "https://example.com/".len());
+        assert!(Rule::Url.check(&format!("https://example.com/{}", path)).is_ok());
+        assert_eq!(Rule::Url.check(&format!("https://example.com/{}a", path)).unwrap_err(), "is too long");
+    }
+
+    #[test]
+    fn slug_bounds() {
+        assert!(Rule::Slug.check("acme-2").is_ok());
+        assert!(Rule::Slug.check(&"a".repeat(63)).is_ok());
+        for slug in ["", "Acme", "-acme", "acme-", "ac_me", "ac.me"] {
+            assert!(Rule::Slug.check(slug).is_err(), "{:?}", slug);
+        }
+        assert!(Rule::Slug.check(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn password_counts_characters_for_min_and_bytes_for_max() {
+        assert!(Rule::Password.check("1234567").is_err());
+        assert!(Rule::Password.check("12345678").is_ok());
+        // 8 characters but 16 bytes
+        assert!(Rule::Password.check("ääääääää").is_ok());
+        assert!(Rule::Password.check(&"a".repeat(PASSWORD_MAX_BYTES)).is_ok());
+        assert!(Rule::Password.check(&"a".repeat(PASSWORD_MAX_BYTES + 1)).is_err());
+        // 37 characters, 74 bytes
+        assert_eq!(
+            Rule::Password.check(&"ä".repeat(37)).unwrap_err(),
+            format!("must be at most {} bytes", PASSWORD_MAX_BYTES),
+        );
+    }
+
+    #[test]
+    fn check_records_field_errors() {
+        let mut errors = Vec::new();
+        check(&mut errors, "email", "alice@example.com", Rule::Email);
+        check(&mut errors, "slug", "Acme", Rule::Slug);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "slug");
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::Instant,
+};
+
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use hmac::{Hmac, Mac};
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    Database,
+};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use url::{Host, Url};
+
+use crate::error::{AppError, AppResult};
+use crate::models::models::{DeliveryAttempt, Post, User, WebhookDelivery, WebhookSubscription};
+use crate::settings::WebhookSettings;
+
+/// Events a subscription can ask for; `*` matches all of them.
+pub const EVENTS: &[&str] = &["user.registered", "user.updated", "post.created", "post.updated", "post.removed"];
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_DELIVERED: &str = "delivered";
+pub const STATUS_FAILED: &str = "failed";
+
+/// Attempts before a delivery is given up on.
+pub const MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first retry; doubled after every further failure.
+const RETRY_BASE_SECS: i64 = 30;
+const RETRY_MAX_SECS: i64 = 6 * 60 * 60;
+/// How long a claimed delivery is hidden from other workers while it is being sent.
+const CLAIM_SECS: i64 = 60;
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Whether `name` is a known event or the wildcard.
+pub fn is_event(name: &str) -> bool {
+    name == "*" || EVENTS.contains(&name)
+}
+
+/// Check that deliveries may be sent to `url`: HTTPS (plain HTTP only when
+/// `allow_http`) and not an internal address. Host names are checked again
+/// when they are resolved for each delivery.
+pub fn check_endpoint(url: &str, allow_http: bool) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| format!("is not a valid URL: {}", e))?;
+    match url.scheme() {
+        "https" => {}
+        "http" if allow_http => {}
+        _ => return Err("must use https".to_string()),
+    }
+    let public = match url.host() {
+        None => return Err("must name a host".to_string()),
+        Some(Host::Domain(_)) => true,
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+    };
+    if !public {
+        return Err("must not point at a private, loopback or link-local address".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `ip` is reachable on the public internet, as opposed to the
+/// service's own host or network.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Resolves endpoint host names when connecting and refuses names with any
+/// non-public address, so a name cannot be pointed at internal services.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+                return Err(format!("{} resolves to the non-public address {}", host, addr.ip()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Build the client deliveries are sent with: no redirects, no proxy, and
+/// only public addresses.
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy()
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Failed to build webhook HTTP client")
+}
+
+/// The error with its causes, which say why a connection was refused.
+fn describe(error: &reqwest::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Queue `event` for every active subscription of the organization that wants it.
+///
+/// Failures are logged rather than returned so webhooks never break the operation that fired them.
+pub async fn emit(db: &Database, org_id: Option<ObjectId>, event: &str, data: Value) {
+    if let Err(e) = enqueue(db, org_id, event, data).await {
+        log::error!("failed to queue webhook event {}: {}", event, e);
+    }
+}
+
+async fn enqueue(db: &Database, org_id: Option<ObjectId>, event: &str, data: Value) -> AppResult<()> {
+    let subscriptions: Vec<WebhookSubscription> = db.collection::<WebhookSubscription>("webhook_subscriptions")
+        .find(doc! { "org_id": org_id, "active": true, "events": { "$in": [event, "*"] } }, None).await?
+        .try_collect().await?;
+    if subscriptions.is_empty() {
+        return Ok(());
+    }
+
+    let payload = json!({
+        "id": ObjectId::new().to_hex(),
+        "event": event,
+        "created_at": Utc::now().to_rfc3339(),
+        "data": data,
+    }).to_string();
+    let deliveries: Vec<WebhookDelivery> = subscriptions.iter()
+        .filter_map(|subscription| subscription.id)
+        .map(|subscription_id| new_delivery(subscription_id, org_id, event, payload.clone()))
+        .collect();
+    db.collection::<WebhookDelivery>("webhook_deliveries").insert_many(deliveries, None).await?;
+    Ok(())
+}
+
+fn new_delivery(subscription_id: ObjectId, org_id: Option<ObjectId>, event: &str, payload: String) -> WebhookDelivery {
+    WebhookDelivery {
+        id: None,
+        subscription_id,
+        org_id,
+        event: event.to_string(),
+        payload,
+        status: STATUS_PENDING.to_string(),
+        attempts: 0,
+        next_attempt_at: DateTime::now(),
+        log: Vec::new(),
+        created_at: DateTime::now(),
+        delivered_at: None,
+    }
+}
+
+/// Queue a fresh copy of an earlier delivery, keeping the original and its log intact.
+pub async fn redeliver(db: &Database, delivery: &WebhookDelivery) -> AppResult<WebhookDelivery> {
+    let mut copy = new_delivery(delivery.subscription_id, delivery.org_id, &delivery.event, delivery.payload.clone());
+    copy.id = db.collection::<WebhookDelivery>("webhook_deliveries")
+        .insert_one(&copy, None).await?
+        .inserted_id.as_object_id();
+    Ok(copy)
+}
+
+/// Webhook body for user events; never includes credentials.
+pub fn user_data(user: &User) -> Value {
+    json!({
+        "id": user.id.map(|id| id.to_hex()),
+        "email": user.email,
+        "full_name": user.full_name,
+        "phone_number": user.phone_number,
+        "roles": user.roles,
+    })
+}
+
+/// Webhook body for post events.
+pub fn post_data(post: &Post) -> Value {
+    json!({
+        "id": post.id.map(|id| id.to_hex()),
+        "title": post.title,
+        "thumbnail": post.thumbnail,
+        "author_id": post.author.map(|id| id.to_hex()),
+        "desc": post.desc,
+    })
+}
+
+/// `X-Webhook-Signature` value: HMAC-SHA256 of `"{timestamp}.{body}"` keyed with the subscription secret.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Delay before attempt number `attempts + 1`.
+fn backoff(attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(20);
+    Duration::seconds((RETRY_BASE_SECS << exponent).min(RETRY_MAX_SECS))
+}
+
+/// Claim the next due delivery so concurrent workers do not send it twice.
+async fn claim(db: &Database) -> AppResult<Option<WebhookDelivery>> {
+    let lease = DateTime::from_millis((Utc::now() + Duration::seconds(CLAIM_SECS)).timestamp_millis());
+    Ok(db.collection::<WebhookDelivery>("webhook_deliveries")
+        .find_one_and_update(
+            doc! { "status": STATUS_PENDING, "next_attempt_at": { "$lte": DateTime::now() } },
+            doc! { "$set": { "next_attempt_at": lease } },
+            FindOneAndUpdateOptions::builder()
+                .sort(doc! { "next_attempt_at": 1 })
+                .return_document(ReturnDocument::After)
+                .build(),
+        ).await?)
+}
+
+/// POST one delivery and record the attempt, scheduling a retry on failure.
+async fn attempt(db: &Database, client: &reqwest::Client, settings: &WebhookSettings, delivery: WebhookDelivery) -> AppResult<()> {
+    let delivery_id = delivery.id
+        .ok_or_else(|| AppError::internal("webhook delivery", "delivery has no id"))?;
+    let deliveries = db.collection::<WebhookDelivery>("webhook_deliveries");
+    let subscription = db.collection::<WebhookSubscription>("webhook_subscriptions")
+        .find_one(doc! { "_id": delivery.subscription_id, "active": true }, None).await?;
+
+    let started = Instant::now();
+    let (status_code, error) = match &subscription {
+        None => (None, Some("Subscription was removed or disabled".to_string())),
+        Some(subscription) => send(client, settings, subscription, delivery_id, &delivery).await,
+    };
+
+    let attempts = delivery.attempts + 1;
+    let entry = DeliveryAttempt {
+        at: DateTime::now(),
+        status_code,
+        error: error.clone(),
+        duration_ms: started.elapsed().as_millis() as i64,
+    };
+    let mut update = doc! { "attempts": attempts };
+    if error.is_none() {
+        update.insert("status", STATUS_DELIVERED);
+        update.insert("delivered_at", DateTime::now());
+    } else if subscription.is_none() || attempts >= MAX_ATTEMPTS {
+        update.insert("status", STATUS_FAILED);
+    } else {
+        let next = Utc::now() + backoff(attempts);
+        update.insert("next_attempt_at", DateTime::from_millis(next.timestamp_millis()));
+    }
+
+    let entry = bson::to_bson(&entry).map_err(|e| AppError::internal("webhook delivery", e))?;
+    deliveries.update_one(
+        doc! { "_id": delivery_id },
+        doc! { "$set": update, "$push": { "log": entry } },
+        None,
+    ).await?;
+    Ok(())
+}
+
+/// POST `delivery` to the subscription's endpoint, answering the status code and any error.
+async fn send(
+    client: &reqwest::Client,
+    settings: &WebhookSettings,
+    subscription: &WebhookSubscription,
+    delivery_id: ObjectId,
+    delivery: &WebhookDelivery,
+) -> (Option<u16>, Option<String>) {
+    // The endpoint may predate the current rules or settings
+    if let Err(e) = check_endpoint(&subscription.url, settings.allow_http) {
+        return (None, Some(format!("Endpoint {}", e)));
+    }
+
+    let timestamp = Utc::now().timestamp();
+    let result = client.post(&subscription.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", delivery_id.to_hex())
+        .header("X-Webhook-Event", &delivery.event)
+        .header("X-Webhook-Signature", signature(&subscription.secret, timestamp, &delivery.payload))
+        .body(delivery.payload.clone())
+        .send().await;
+    match result {
+        Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+        Ok(response) => (Some(response.status().as_u16()), Some(format!("Endpoint answered {}", response.status()))),
+        Err(e) => (None, Some(describe(&e))),
+    }
+}
+
+/// Send due deliveries in the background, polling every few seconds.
+pub fn spawn_delivery_worker(db: Database, settings: WebhookSettings) {
+    let client = client();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            loop {
+                match claim(&db).await {
+                    Ok(Some(delivery)) => {
+                        if let Err(e) = attempt(&db, &client, &settings, delivery).await {
+                            log::error!("webhook delivery failed: {}", e);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::error!("failed to claim webhook delivery: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_https_unless_allowed() {
+        assert!(check_endpoint("https://hooks.example.com/in", false).is_ok());
+        assert!(check_endpoint("http://hooks.example.com/in", false).is_err());
+        assert!(check_endpoint("http://hooks.example.com/in", true).is_ok());
+        assert!(check_endpoint("ftp://hooks.example.com/in", true).is_err());
+    }
+
+    #[test]
+    fn refuses_internal_address_literals() {
+        for url in [
+            "https://127.0.0.1/",
+            "https://10.1.2.3/",
+            "https://172.16.0.1/",
+            "https://192.168.1.1/",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.64.0.1/",
+            "https://0.0.0.0/",
+            "https://[::1]/",
+            "https://[fe80::1]/",
+            "https://[fd00::1]/",
+            "https://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(check_endpoint(url, false).is_err(), "{}", url);
+        }
+        assert!(check_endpoint("https://93.184.216.34/", false).is_ok());
+        assert!(check_endpoint("https://[2606:2800:220:1::]/", false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_names_of_internal_addresses() {
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}